@@ -6,42 +6,69 @@
 // agents. The operations defined here are mostly abstract operations that should
 // be implemented by the user of the framework in accordance with their specific
 // use case.
-use rustc_serialize::{Encoder, Encodable, Decoder, Decodable};
+use serde::{de, Serialize, Serializer, Deserialize, Deserializer};
+use std::io;
 use byteid::ByteId;
+use identity::{self, Keypair};
+use codec::{self, Codec, Bincode};
 use std::vec::Vec;
-use std::io::IoResult;
-use std::io::net::ip::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddr};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::option;
 
-#[derive(Clone, Eq, PartialEq, Show)]
+#[derive(Clone, Eq, PartialEq, Debug)]
 pub struct SwarmAddr(SocketAddr);
 
-impl<'a> ToSocketAddr for &'a SwarmAddr {
-    fn to_socket_addr(&self) -> IoResult<SocketAddr> {
-        let &&SwarmAddr(addr) = self;
-        Ok(addr)
+impl SwarmAddr {
+    pub fn new(addr: SocketAddr) -> SwarmAddr {
+        SwarmAddr(addr)
+    }
+
+    pub fn socket_addr(&self) -> SocketAddr {
+        let &SwarmAddr(addr) = self;
+        addr
     }
+}
+
+impl ToSocketAddrs for &SwarmAddr {
+    type Iter = option::IntoIter<SocketAddr>;
 
-    fn to_socket_addr_all(&self) -> IoResult<Vec<SocketAddr>> {
+    fn to_socket_addrs(&self) -> io::Result<option::IntoIter<SocketAddr>> {
         let &&SwarmAddr(addr) = self;
-        let mut vec = Vec::new();
-        vec.push(addr);
-        Ok(vec)
+        Ok(Some(addr).into_iter())
     }
 }
 
-#[derive(Clone, Eq, PartialEq, Show, RustcDecodable, RustcEncodable)]
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
 pub struct SwarmAgent<L> {
     swarm_id: ByteId,
     loc: L,
-    addr: SwarmAddr
+    addr: SwarmAddr,
+    #[serde(with = "codec::bytes")]
+    public_key: Vec<u8>,
+    // X25519 key other agents agree session keys with.
+    #[serde(with = "codec::bytes")]
+    exchange_key: Vec<u8>,
+    // Proof-of-work nonce the id was derived with.
+    nonce: u64,
+    // Signature of the agent over its id, location, address and exchange
+    // key, so that records passed on by other agents cannot be altered or
+    // made up.
+    #[serde(with = "codec::bytes")]
+    signature: Vec<u8>
 }
 
 impl<L> SwarmAgent<L> {
+    // An agent with a random id and without a public key. Such an agent can
+    // not be authenticated by the rest of the swarm.
     pub fn new(location: L, address: SocketAddr) -> SwarmAgent<L> {
         SwarmAgent {
             loc: location,
             swarm_id: ByteId::random_id(),
-            addr: SwarmAddr(address)
+            addr: SwarmAddr(address),
+            public_key: Vec::new(),
+            exchange_key: Vec::new(),
+            nonce: 0,
+            signature: Vec::new()
         }
     }
 
@@ -49,8 +76,11 @@ impl<L> SwarmAgent<L> {
        &self.loc
     }
 
+    // The record has to be signed again for the new location to be accepted,
+    // see sign.
     pub fn update_location(&mut self, location: L) {
         self.loc = location;
+        self.signature.clear();
     }
 
     pub fn id(&self) -> &ByteId {
@@ -60,69 +90,100 @@ impl<L> SwarmAgent<L> {
     pub fn address(&self) -> &SwarmAddr {
         &self.addr
     }
+
+    pub fn public_key(&self) -> &[u8] {
+        self.public_key.as_slice()
+    }
+
+    pub fn exchange_key(&self) -> &[u8] {
+        self.exchange_key.as_slice()
+    }
 }
 
-impl Encodable for SwarmAddr {
-    fn encode<S:Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
-        let &SwarmAddr(ref addr) = self;
-        match addr.ip {
-            Ipv4Addr(a,b,c,d) => {
-               try!(s.emit_u8(a));
-               try!(s.emit_u8(b));
-               try!(s.emit_u8(c));
-               try!(s.emit_u8(d));
-            }
-            Ipv6Addr(_,_,_,_,_,_,_,_) => {
-                panic!("not yet implemented");
-            }
-        }
-        s.emit_u16(addr.port)
+impl<L: Serialize> SwarmAgent<L> {
+    pub fn with_keypair(location: L, address: SocketAddr,
+                        keypair: &Keypair) -> SwarmAgent<L> {
+        let mut agent = SwarmAgent {
+            loc: location,
+            swarm_id: keypair.id(),
+            addr: SwarmAddr(address),
+            public_key: keypair.public_key().to_vec(),
+            exchange_key: keypair.exchange_key().to_vec(),
+            nonce: keypair.nonce(),
+            signature: Vec::new()
+        };
+        agent.sign(keypair);
+        agent
+    }
+
+    // Sign the record as the agent it describes.
+    pub fn sign(&mut self, keypair: &Keypair) {
+        self.signature = keypair.sign(self.signed_data().as_slice());
+    }
+
+    // Whether the agent's id belongs to its public key, and the record was
+    // signed by the agent itself.
+    pub fn is_authentic(&self) -> bool {
+        !self.public_key.is_empty() &&
+            ByteId::from_public_key(self.public_key(), self.nonce) == self.swarm_id &&
+            identity::verify(self.public_key(),
+                             self.signed_data().as_slice(),
+                             self.signature.as_slice())
+    }
+
+    fn signed_data(&self) -> Vec<u8> {
+        Bincode.encode(&(&self.swarm_id, &self.loc, &self.addr, &self.exchange_key)).
+            unwrap_or_default()
     }
 }
 
-impl Decodable for SwarmAddr {
-    fn decode<D:Decoder>(dec: &mut D) -> Result<SwarmAddr,D::Error> {
-        let (a,b,c,d) = (try!(dec.read_u8()),
-                         try!(dec.read_u8()),
-                         try!(dec.read_u8()),
-                         try!(dec.read_u8()));
-        let port = try!(dec.read_u16());
+impl Serialize for SwarmAddr {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let SwarmAddr(addr) = self;
+        s.serialize_str(format!("{}", addr).as_str())
+    }
+}
 
-        Ok(SwarmAddr(SocketAddr { ip: Ipv4Addr(a,b,c,d), port: port }))
+impl<'de> Deserialize<'de> for SwarmAddr {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<SwarmAddr, D::Error> {
+        let addr: String = Deserialize::deserialize(d)?;
+        match addr.parse::<SocketAddr>() {
+            Ok(addr) => Ok(SwarmAddr(addr)),
+            Err(_) => Err(de::Error::custom(format!("invalid address: {}", addr)))
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
-    extern crate bincode;
-
     use agent::{SwarmAgent, SwarmAddr};
+    use codec::{Codec, Bincode, Json, MessagePack};
+    use identity::Keypair;
     use Location;
-    use std::io::net::ip::{SocketAddr, Ipv4Addr};
-    use std::num::SignedInt;
+    use std::net::{SocketAddr, Ipv4Addr};
 
     impl Location for isize {
         fn distance(&self, other: &isize) -> usize {
-            (*self - *other).abs() as usize
+            (*self - *other).unsigned_abs()
         }
     }
 
     #[test]
     fn new_test() {
-        let ipaddr = Ipv4Addr(127, 0, 0, 0);
+        let ipaddr = Ipv4Addr::new(127, 0, 0, 0);
         let p = 1234;
-        let addr = SocketAddr{ ip: ipaddr, port: p };
-        let loc = 9is;
+        let addr = SocketAddr::from((ipaddr, p));
+        let loc = 9isize;
 
-        let agent = SwarmAgent::new(loc, addr);
+        let _agent = SwarmAgent::new(loc, addr);
     }
 
     #[test]
     fn location_test() {
-        let ipaddr = Ipv4Addr(127, 0, 0, 0);
+        let ipaddr = Ipv4Addr::new(127, 0, 0, 0);
         let p = 1234;
-        let addr = SocketAddr{ ip: ipaddr, port: p };
-        let loc = 9is;
+        let addr = SocketAddr::from((ipaddr, p));
+        let loc = 9isize;
 
         let agent = SwarmAgent::new(loc, addr);
         assert_eq!(agent.location(), &loc);
@@ -130,11 +191,11 @@ mod test {
 
     #[test]
     fn update_location_test() {
-        let ipaddr = Ipv4Addr(127, 0, 0, 0);
+        let ipaddr = Ipv4Addr::new(127, 0, 0, 0);
         let p = 1234;
-        let addr = SocketAddr{ ip: ipaddr, port: p };
-        let loc = 9is;
-        let upd_loc = -9is;
+        let addr = SocketAddr::from((ipaddr, p));
+        let loc = 9isize;
+        let upd_loc = -9isize;
 
         let mut agent = SwarmAgent::new(loc, addr);
         agent.update_location(upd_loc);
@@ -143,10 +204,10 @@ mod test {
 
     #[test]
     fn id_test() {
-        let ipaddr = Ipv4Addr(127, 0, 0, 0);
+        let ipaddr = Ipv4Addr::new(127, 0, 0, 0);
         let p = 1234;
-        let addr = SocketAddr{ ip: ipaddr, port: p };
-        let loc = 9is;
+        let addr = SocketAddr::from((ipaddr, p));
+        let loc = 9isize;
 
         let agent = SwarmAgent::new(loc, addr);
         assert_eq!(agent.id(), &agent.swarm_id);
@@ -154,30 +215,102 @@ mod test {
 
     #[test]
     fn address_test() {
-        let ipaddr = Ipv4Addr(127, 0, 0, 0);
+        let ipaddr = Ipv4Addr::new(127, 0, 0, 0);
         let p = 1234;
-        let addr = SocketAddr{ ip: ipaddr, port: p };
-        let loc = 9is;
+        let addr = SocketAddr::from((ipaddr, p));
+        let loc = 9isize;
 
         let agent = SwarmAgent::new(loc, addr);
         assert_eq!(*agent.address(), agent.addr);
     }
 
     #[test]
-    fn bincode_test() {
-        let ipaddr = Ipv4Addr(127, 0, 0, 0);
+    fn authentic_test() {
+        let ipaddr = Ipv4Addr::new(127, 0, 0, 0);
         let p = 1234;
-        let addr = SocketAddr{ ip: ipaddr, port: p };
-        let loc = 9is;
+        let addr = SocketAddr::from((ipaddr, p));
+        let keypair = Keypair::generate();
 
-        let agent = SwarmAgent::new(loc, addr);
-        let limit = bincode::SizeLimit::Infinite;
-        let encoded = bincode::encode(&agent, limit).ok().unwrap();
+        let agent = SwarmAgent::with_keypair(9isize, addr, &keypair);
+        assert!(agent.is_authentic());
+        assert_eq!(*agent.id(), keypair.id());
+        assert_eq!(agent.public_key(), keypair.public_key());
+
+        let mut forged = SwarmAgent::with_keypair(9isize, addr, &Keypair::generate());
+        forged.swarm_id = keypair.id();
+        assert!(!forged.is_authentic());
+
+        assert!(!SwarmAgent::new(9isize, addr).is_authentic());
+
+        let worked = Keypair::generate_with_work(4);
+        let agent = SwarmAgent::with_keypair(9isize, addr, &worked);
+        assert!(agent.is_authentic());
+        assert!(agent.id().leading_zeros() >= 4);
+
+        let mut wrong_nonce = agent.clone();
+        wrong_nonce.nonce += 1;
+        assert!(!wrong_nonce.is_authentic());
+    }
+
+    #[test]
+    fn signed_record_test() {
+        let addr = SocketAddr::from(([127, 0, 0, 1], 1234));
+        let keypair = Keypair::generate();
+        let agent = SwarmAgent::with_keypair(9isize, addr, &keypair);
+
+        // Records altered by anyone but the agent are rejected.
+        let mut moved = agent.clone();
+        moved.loc = 10;
+        assert!(!moved.is_authentic());
+        let mut redirected = agent.clone();
+        redirected.addr = SwarmAddr(SocketAddr::from(([10, 0, 0, 1], 1234)));
+        assert!(!redirected.is_authentic());
+        let mut rekeyed = agent.clone();
+        rekeyed.exchange_key = Keypair::generate().exchange_key().to_vec();
+        assert!(!rekeyed.is_authentic());
+
+        let mut updated = agent.clone();
+        updated.update_location(10);
+        assert!(!updated.is_authentic());
+        updated.sign(&keypair);
+        assert!(updated.is_authentic());
+        updated.sign(&Keypair::generate());
+        assert!(!updated.is_authentic());
+    }
+
+    fn codec_tester<C: Codec>(codec: C) {
+        let ipaddr = Ipv4Addr::new(127, 0, 0, 0);
+        let p = 1234;
+        let addr = SocketAddr::from((ipaddr, p));
+        let loc = 9isize;
+
+        let agent = SwarmAgent::with_keypair(loc, addr, &Keypair::generate());
+        let encoded = codec.encode(&agent).ok().unwrap();
         let dec_agnt: SwarmAgent<isize> =
-            bincode::decode(encoded.as_slice()).ok().unwrap();
+            codec.decode(encoded.as_slice()).ok().unwrap();
 
         assert_eq!(agent.address(), dec_agnt.address());
         assert_eq!(agent.location(), dec_agnt.location());
         assert_eq!(agent.id(), dec_agnt.id());
+        assert_eq!(agent.public_key(), dec_agnt.public_key());
+        assert!(dec_agnt.is_authentic());
+    }
+
+    #[test]
+    fn codec_test() {
+        codec_tester(Bincode);
+        codec_tester(Json);
+        codec_tester(MessagePack);
+    }
+
+    #[test]
+    fn address_json_test() {
+        let addr = SwarmAddr(SocketAddr::from(([10, 0, 0, 1], 4000)));
+        let encoded = Json.encode(&addr).ok().unwrap();
+        assert_eq!(encoded.as_slice(), b"\"10.0.0.1:4000\"");
+
+        let decoded: SwarmAddr = Json.decode(encoded.as_slice()).ok().unwrap();
+        assert_eq!(decoded, addr);
+        assert!(Json.decode::<SwarmAddr>(b"\"not an address\"").is_err());
     }
 }
@@ -4,19 +4,27 @@
 // discovered by Agents locally and knowledge of Artifacts is dispersed throughout
 // the Swarm using either the INFO or BROADCAST RPC, depending on the associated
 // importance of a particular Artifact.
-use Location;
+//
+// An Artifact carries a user defined payload describing the resource, e.g. its
+// kind and the quantity left. Every change to an Artifact increases its
+// version, so that agents can tell which of two copies is the most recent one.
 use byteid::ByteId;
 
-#[derive(Clone, Eq, PartialEq, Show, RustcDecodable, RustcEncodable)]
-pub struct SwarmArtifact<L> {
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct SwarmArtifact<L, P> {
     id: ByteId,
-    location: L
+    location: L,
+    version: u64,
+    payload: P
 }
 
-impl<L> SwarmArtifact<L> {
-    pub fn new(loc: L) -> SwarmArtifact<L> {
+impl<L, P> SwarmArtifact<L, P> {
+    pub fn new(loc: L, payload: P) -> SwarmArtifact<L, P> {
         SwarmArtifact {
-            id: ByteId::random_id(), location: loc
+            id: ByteId::random_id(),
+            location: loc,
+            version: 0,
+            payload
         }
     }
 
@@ -28,29 +36,77 @@ impl<L> SwarmArtifact<L> {
         &self.id
     }
 
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn payload(&self) -> &P {
+        &self.payload
+    }
+
     pub fn update_location(&mut self, location: L) {
         self.location = location;
+        self.version += 1;
+    }
+
+    pub fn update_payload(&mut self, payload: P) {
+        self.payload = payload;
+        self.version += 1;
+    }
+
+    // Whether this artifact is a more recent copy of other.
+    pub fn is_newer_than(&self, other: &SwarmArtifact<L, P>) -> bool {
+        self.id == other.id && self.version > other.version
     }
 }
 
 #[cfg(test)]
 mod test {
-    extern crate bincode;
     use artifact::SwarmArtifact;
-    use std::vec::Vec;
-    use std::path::BytesContainer;
+    use codec::{Codec, Bincode, Json, MessagePack};
 
-    #[test]
-    fn bincode_test() {
-        let loc = 9is;
+    #[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+    struct Food {
+        kind: u8,
+        quantity: u32
+    }
 
-        let art = SwarmArtifact::new(loc);
-        let limit = bincode::SizeLimit::Infinite;
-        let encoded = bincode::encode(&art, limit).ok().unwrap();
-        let dec_art: SwarmArtifact<isize> =
-            bincode::decode(encoded.as_slice()).ok().unwrap();
+    fn codec_tester<C: Codec>(codec: C) {
+        let loc = 9isize;
+
+        let art = SwarmArtifact::new(loc, Food { kind: 1, quantity: 100 });
+        let encoded = codec.encode(&art).ok().unwrap();
+        let dec_art: SwarmArtifact<isize, Food> =
+            codec.decode(encoded.as_slice()).ok().unwrap();
 
         assert_eq!(art.location(), dec_art.location());
         assert_eq!(art.id(), dec_art.id());
+        assert_eq!(art.version(), dec_art.version());
+        assert_eq!(art.payload(), dec_art.payload());
+    }
+
+    #[test]
+    fn codec_test() {
+        codec_tester(Bincode);
+        codec_tester(Json);
+        codec_tester(MessagePack);
+    }
+
+    #[test]
+    fn update_version_test() {
+        let mut art = SwarmArtifact::new(9isize, Food { kind: 1, quantity: 100 });
+        let orig = art.clone();
+        assert_eq!(art.version(), 0);
+
+        art.update_payload(Food { kind: 1, quantity: 30 });
+        assert_eq!(art.version(), 1);
+        assert_eq!(art.payload().quantity, 30);
+
+        art.update_location(10isize);
+        assert_eq!(art.version(), 2);
+
+        assert!(art.is_newer_than(&orig));
+        assert!(!orig.is_newer_than(&art));
+        assert!(!art.is_newer_than(&SwarmArtifact::new(9isize, orig.payload().clone())));
     }
 }
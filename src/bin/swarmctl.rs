@@ -0,0 +1,64 @@
+// swarmctl
+//
+// Inspect and control a running agent through its admin socket, see
+// SwarmNetwork::enable_admin.
+//
+// usage: swarmctl <admin address> <command>
+extern crate swarm;
+
+use swarm::admin::{self, AdminRequest};
+use std::env;
+use std::net::SocketAddr;
+use std::process;
+
+const USAGE: &str = "usage: swarmctl <admin address> <command>
+
+commands:
+    agent               show the agent record
+    neighbors           show the neighbor table
+    artifacts           show the artifact store
+    counters            show the agent's counters
+    join <address>      join the swarm through the agent at address
+    leave               leave the swarm
+    broadcast <text>    broadcast a custom event carrying text";
+
+fn fail(msg: &str) {
+    eprintln!("{}", msg);
+    process::exit(1);
+}
+
+fn parse_request(args: &[String]) -> Option<AdminRequest> {
+    let command = args[0].as_str();
+    match (command, args.get(1)) {
+        ("agent", None) => Some(AdminRequest::Agent),
+        ("neighbors", None) => Some(AdminRequest::Neighbors),
+        ("artifacts", None) => Some(AdminRequest::Artifacts),
+        ("counters", None) => Some(AdminRequest::Counters),
+        ("join", Some(addr)) => Some(AdminRequest::Join(addr.clone())),
+        ("leave", None) => Some(AdminRequest::Leave),
+        ("broadcast", Some(text)) => Some(AdminRequest::Broadcast(text.as_bytes().to_vec())),
+        _ => None
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 || args.len() > 4 {
+        return fail(USAGE)
+    }
+
+    let agent = match args[1].parse::<SocketAddr>() {
+        Ok(addr) => addr,
+        Err(_) => return fail(format!("swarmctl: invalid address: {}", args[1]).as_str())
+    };
+
+    let request = match parse_request(&args[2..]) {
+        Some(req) => req,
+        None => return fail(USAGE)
+    };
+
+    match admin::request(agent, &request) {
+        Ok(response) => println!("{}", response),
+        Err(e) => fail(format!("swarmctl: {}", e).as_str())
+    }
+}
@@ -1,48 +1,93 @@
-use rustc_serialize::{Encoder, Encodable, Decoder, Decodable};
-use std::fmt;
-use std::rand::{thread_rng, Rng};
+use codec;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use sha1::{Digest, Sha1};
+use rand::{thread_rng, Rng};
 use std::ops::{BitAnd, BitOr, BitXor, Not};
 
 const BYTE_ID_LEN: usize = 20;
 
-#[derive(Clone, Eq, PartialEq, Show)]
+// Ids are ordered byte by byte, which leader election relies on.
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
 pub struct ByteId([u8; BYTE_ID_LEN]);
 
 impl ByteId {
     pub fn random_id() -> ByteId {
         let mut data = [0u8; BYTE_ID_LEN];
-        thread_rng().fill_bytes(&mut data);
+        thread_rng().fill(&mut data);
         ByteId(data)
     }
 
+    // The id belonging to an agent's public key and proof-of-work nonce.
+    pub fn from_public_key(key: &[u8], nonce: u64) -> ByteId {
+        let mut data = [0u8; BYTE_ID_LEN];
+        let mut sha = Sha1::new();
+        sha.update(key);
+        sha.update(nonce.to_le_bytes());
+        data.copy_from_slice(sha.finalize().as_slice());
+        ByteId(data)
+    }
+
+    // Number of leading zero bits, i.e. the amount of work that went into
+    // finding the id.
+    pub fn leading_zeros(&self) -> u32 {
+        let ByteId(data) = self;
+        let mut zeros = 0u32;
+        for &byte in data.iter() {
+            if byte == 0 {
+                zeros += 8;
+                continue
+            }
+
+            let mut mask = 0x80u8;
+            while byte & mask == 0 {
+                zeros += 1;
+                mask >>= 1;
+            }
+            break
+        }
+        zeros
+    }
+
     pub fn set_byte(&mut self, index: usize, val: u8) {
         let &mut ByteId(ref mut data) = self;
         data[index % BYTE_ID_LEN] = val;
     }
 
     pub fn byte(&self, index: usize) -> u8 {
-        let &ByteId(ref data) = self;
+        let ByteId(data) = self;
         data[index % BYTE_ID_LEN]
     }
+
+    pub fn to_hex(&self) -> String {
+        let ByteId(data) = self;
+        codec::to_hex(data)
+    }
 }
 
-impl Encodable for ByteId {
-    fn encode<S:Encoder>(&self, s: &mut S) -> Result<(),S::Error> {
-        let &ByteId(ref data) = self;
-        for &n in data.iter() {
-            try!(s.emit_u8(n))
+// Ids are written as hex strings in human-readable formats, and as they are
+// otherwise.
+impl Serialize for ByteId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let ByteId(data) = self;
+        if serializer.is_human_readable() {
+            serializer.serialize_str(self.to_hex().as_str())
+        } else {
+            data.serialize(serializer)
         }
-        Ok(())
     }
 }
 
-impl Decodable for ByteId {
-    fn decode<D:Decoder>(d: &mut D) -> Result<ByteId,D::Error> {
+impl<'de> Deserialize<'de> for ByteId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ByteId, D::Error> {
+        if !deserializer.is_human_readable() {
+            return <[u8; BYTE_ID_LEN]>::deserialize(deserializer).map(ByteId)
+        }
+        let hex = String::deserialize(deserializer)?;
         let mut data = [0u8; BYTE_ID_LEN];
-        for i in range(0us, BYTE_ID_LEN) {
-            data[i] = try!(d.read_u8());
+        match codec::from_hex(hex.as_str()) {
+            Some(ref bytes) if bytes.len() == BYTE_ID_LEN => data.copy_from_slice(bytes),
+            _ => return Err(de::Error::custom("invalid id"))
         }
-
         Ok(ByteId(data))
     }
 }
@@ -56,7 +101,7 @@ impl BitAnd<ByteId> for ByteId {
         let ByteId(ref me) = self;
         let ByteId(ref you) = other;
 
-        for i in range(0us, BYTE_ID_LEN) {
+        for i in 0usize..BYTE_ID_LEN {
             ret[i] = me[i] & you[i];
         }
 
@@ -73,7 +118,7 @@ impl BitOr<ByteId> for ByteId {
         let ByteId(ref me) = self;
         let ByteId(ref you) = other;
 
-        for i in range(0us, BYTE_ID_LEN) {
+        for i in 0usize..BYTE_ID_LEN {
             ret[i] = me[i] | you[i];
         }
 
@@ -90,7 +135,7 @@ impl BitXor<ByteId> for ByteId {
         let ByteId(ref me) = self;
         let ByteId(ref you) = other;
 
-        for i in range(0us, BYTE_ID_LEN) {
+        for i in 0usize..BYTE_ID_LEN {
             ret[i] = me[i] ^ you[i];
         }
 
@@ -106,7 +151,7 @@ impl Not for ByteId {
         let mut ret = [0u8; BYTE_ID_LEN];
         let ByteId(ref me) = self;
 
-        for i in range(0us, BYTE_ID_LEN) {
+        for i in 0usize..BYTE_ID_LEN {
             ret[i] = !me[i];
         }
 
@@ -117,6 +162,7 @@ impl Not for ByteId {
 #[cfg(test)]
 mod test {
     use super::{ByteId, BYTE_ID_LEN};
+    use codec::{Codec, Bincode, Json};
 
     #[test]
     fn byte_test() {
@@ -131,6 +177,45 @@ mod test {
         assert_eq!(id_b.byte(BYTE_ID_LEN/2), 9u8);
     }
 
+    #[test]
+    fn ordering_test() {
+        let mut low = ByteId([0xffu8; BYTE_ID_LEN]);
+        low.set_byte(0, 0x01);
+        let mut high = ByteId([0u8; BYTE_ID_LEN]);
+        high.set_byte(0, 0x02);
+
+        assert!(low < high);
+        assert_eq!(vec![high.clone(), low.clone()].into_iter().min(), Some(low));
+    }
+
+    #[test]
+    fn hex_test() {
+        let mut id = ByteId([0u8; BYTE_ID_LEN]);
+        id.set_byte(0, 0xab);
+        id.set_byte(BYTE_ID_LEN - 1, 0x05);
+
+        let hex = id.to_hex();
+        assert_eq!(hex.len(), 2 * BYTE_ID_LEN);
+        assert!(hex.starts_with("ab00"));
+        assert!(hex.ends_with("0005"));
+    }
+
+    #[test]
+    fn encode_test() {
+        let id = ByteId::random_id();
+        let encoded = Bincode.encode(&id).ok().unwrap();
+        assert_eq!(encoded.len(), BYTE_ID_LEN);
+
+        let decoded: ByteId = Bincode.decode(encoded.as_slice()).ok().unwrap();
+        assert_eq!(decoded, id);
+
+        let encoded = Json.encode(&id).ok().unwrap();
+        assert_eq!(encoded, format!("\"{}\"", id.to_hex()).into_bytes());
+        let decoded: ByteId = Json.decode(encoded.as_slice()).ok().unwrap();
+        assert_eq!(decoded, id);
+        assert!(Json.decode::<ByteId>(b"\"ab00\"").is_err());
+    }
+
     #[test]
     fn set_byte_test() {
         let a = [0u8; BYTE_ID_LEN];
@@ -146,7 +231,7 @@ mod test {
     #[test]
     fn bitand_test() {
         let data = [0u8; BYTE_ID_LEN];
-        let mut id_a = ByteId(data.clone());
+        let mut id_a = ByteId(data);
         let mut id_b = ByteId(data);
 
         id_a.set_byte(3, 0b1010_1010);
@@ -159,7 +244,7 @@ mod test {
     #[test]
     fn bitor_test() {
         let data = [0u8; BYTE_ID_LEN];
-        let mut id_a = ByteId(data.clone());
+        let mut id_a = ByteId(data);
         let mut id_b = ByteId(data);
 
         id_a.set_byte(3, 0b1010_1010);
@@ -172,7 +257,7 @@ mod test {
     #[test]
     fn bitxor_test() {
         let data = [0u8; BYTE_ID_LEN];
-        let mut id_a = ByteId(data.clone());
+        let mut id_a = ByteId(data);
         let mut id_b = ByteId(data);
 
         id_a.set_byte(3, 0b1010_1010);
@@ -200,7 +285,36 @@ mod test {
         let id_c = id_a.clone();
 
         assert!(id_c == id_a);
-        assert!(!(id_b == id_a));
+        assert!((id_b != id_a));
+    }
+
+    #[test]
+    fn from_public_key_test() {
+        let key = [7u8; 32];
+        let id_a = ByteId::from_public_key(&key, 0);
+        let id_b = ByteId::from_public_key(&key, 0);
+        let id_c = ByteId::from_public_key(&[8u8; 32], 0);
+        let id_d = ByteId::from_public_key(&key, 1);
+
+        assert_eq!(id_a, id_b);
+        assert!(id_a != id_c);
+        assert!(id_a != id_d);
+    }
+
+    #[test]
+    fn leading_zeros_test() {
+        let mut id = ByteId([0u8; BYTE_ID_LEN]);
+        assert_eq!(id.leading_zeros(), 8 * BYTE_ID_LEN as u32);
+
+        id.set_byte(0, 0b1000_0000);
+        assert_eq!(id.leading_zeros(), 0);
+
+        id.set_byte(0, 0b0001_0000);
+        assert_eq!(id.leading_zeros(), 3);
+
+        id.set_byte(0, 0);
+        id.set_byte(1, 0b0000_0001);
+        assert_eq!(id.leading_zeros(), 15);
     }
 
     #[test]
@@ -0,0 +1,18 @@
+// Clock
+//
+// Timeouts, rate limits and expiries are measured in milliseconds of a
+// monotonic clock, so that they are not thrown off when the system time is
+// set. Timestamps that are compared between agents, such as the writes to a
+// shared register, are taken from the wall clock instead.
+use time;
+
+// Milliseconds of the monotonic clock, from an arbitrary starting point.
+pub fn now_ms() -> u64 {
+    time::precise_time_ns() / 1_000_000
+}
+
+// Milliseconds since the Unix epoch.
+pub fn wall_clock_ms() -> u64 {
+    let now = time::get_time();
+    now.sec as u64 * 1000 + now.nsec as u64 / 1_000_000
+}
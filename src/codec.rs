@@ -0,0 +1,321 @@
+// Wire Codecs
+//
+// The RPCs exchanged by agents are serialized with serde, and the encoding used
+// on the wire is pluggable through the Codec trait. Bincode is the default and
+// the most compact; JSON and MessagePack allow tools written in other languages
+// to talk to the Swarm.
+//
+// Signatures are always computed over the Bincode encoding, so that they do
+// not depend on the encoding a packet was sent with.
+//
+// The JSON encoding doubles as a debugging aid: to_json_pretty renders any
+// value, e.g. a decoded packet, in a human-readable form.
+//
+// Keys and signatures are written as base64 strings in JSON, and ids as hex
+// strings, rather than as arrays of numbers, which would take up to four times
+// the space and keep a HRTBTACK from fitting a packet.
+use serde::{Deserializer, Serialize, Serializer};
+use serde::de::{self, DeserializeOwned, SeqAccess, Visitor};
+use bincode::{self, Options};
+use serde_json;
+use rmp_serde;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use std::fmt;
+use std::vec::Vec;
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum CodecError {
+    // The value could not be encoded.
+    Encoding(String),
+    // The data is not a valid encoding of the expected type.
+    Decoding(String)
+}
+
+pub type CodecResult<T> = Result<T, CodecError>;
+
+pub trait Codec {
+    fn encode<T: Serialize>(&self, value: &T) -> CodecResult<Vec<u8>>;
+    fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> CodecResult<T>;
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Bincode;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Json;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct MessagePack;
+
+// The encoding used by a SwarmSocket. The format of a packet is recorded in
+// its header by id.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub enum WireFormat {
+    Bincode,
+    Json,
+    MessagePack
+}
+
+impl WireFormat {
+    pub fn id(&self) -> u8 {
+        match *self {
+            WireFormat::Bincode => 0,
+            WireFormat::Json => 1,
+            WireFormat::MessagePack => 2
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<WireFormat> {
+        match id {
+            0 => Some(WireFormat::Bincode),
+            1 => Some(WireFormat::Json),
+            2 => Some(WireFormat::MessagePack),
+            _ => None
+        }
+    }
+}
+
+pub fn to_hex(data: &[u8]) -> String {
+    let mut hex = String::with_capacity(2 * data.len());
+    for byte in data.iter() {
+        hex.push_str(format!("{:02x}", byte).as_str());
+    }
+    hex
+}
+
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None
+    }
+    (0..hex.len()).step_by(2).
+        map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).
+        collect()
+}
+
+// Serde adapter for byte vectors, used with #[serde(with = "codec::bytes")]:
+// a base64 string in human-readable formats, and raw bytes otherwise. Bincode
+// encodes raw bytes the same as a Vec<u8>, so signatures are not affected.
+pub mod bytes {
+    use super::{de, fmt, BASE64, Deserializer, Engine, SeqAccess, Serializer, Visitor};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(BASE64.encode(data).as_str())
+        } else {
+            serializer.serialize_bytes(data)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(BytesVisitor)
+        } else {
+            deserializer.deserialize_byte_buf(BytesVisitor)
+        }
+    }
+
+    struct BytesVisitor;
+
+    impl<'de> Visitor<'de> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("bytes or a base64 string")
+        }
+
+        fn visit_str<E: de::Error>(self, text: &str) -> Result<Vec<u8>, E> {
+            BASE64.decode(text).map_err(|_| E::custom("invalid base64 string"))
+        }
+
+        fn visit_bytes<E: de::Error>(self, data: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(data.to_vec())
+        }
+
+        fn visit_byte_buf<E: de::Error>(self, data: Vec<u8>) -> Result<Vec<u8>, E> {
+            Ok(data)
+        }
+
+        // Encodings without a bytes type may write them as a sequence.
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+            let mut data = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+            while let Some(byte) = seq.next_element()? {
+                data.push(byte);
+            }
+            Ok(data)
+        }
+    }
+}
+
+pub fn to_json_pretty<T: Serialize>(value: &T) -> CodecResult<String> {
+    serde_json::to_string_pretty(value).
+        map_err(|e| CodecError::Encoding(format!("{:?}", e)))
+}
+
+impl Codec for Bincode {
+    fn encode<T: Serialize>(&self, value: &T) -> CodecResult<Vec<u8>> {
+        bincode::serialize(value).
+            map_err(|e| CodecError::Encoding(format!("{:?}", e)))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> CodecResult<T> {
+        // A valid encoding never reads past the end of the data, which keeps
+        // bogus length prefixes from allocating large buffers.
+        bincode::DefaultOptions::new().
+            with_fixint_encoding().
+            allow_trailing_bytes().
+            with_limit(data.len() as u64).
+            deserialize(data).
+            map_err(|e| CodecError::Decoding(format!("{:?}", e)))
+    }
+}
+
+impl Codec for Json {
+    fn encode<T: Serialize>(&self, value: &T) -> CodecResult<Vec<u8>> {
+        serde_json::to_vec(value).
+            map_err(|e| CodecError::Encoding(format!("{:?}", e)))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> CodecResult<T> {
+        serde_json::from_slice(data).
+            map_err(|e| CodecError::Decoding(format!("{:?}", e)))
+    }
+}
+
+impl Codec for MessagePack {
+    fn encode<T: Serialize>(&self, value: &T) -> CodecResult<Vec<u8>> {
+        let mut buf = Vec::new();
+        match value.serialize(&mut rmp_serde::Serializer::new(&mut buf)) {
+            Ok(()) => Ok(buf),
+            Err(e) => Err(CodecError::Encoding(format!("{:?}", e)))
+        }
+    }
+
+    fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> CodecResult<T> {
+        rmp_serde::from_slice(data).
+            map_err(|e| CodecError::Decoding(format!("{:?}", e)))
+    }
+}
+
+impl Codec for WireFormat {
+    fn encode<T: Serialize>(&self, value: &T) -> CodecResult<Vec<u8>> {
+        match *self {
+            WireFormat::Bincode => Bincode.encode(value),
+            WireFormat::Json => Json.encode(value),
+            WireFormat::MessagePack => MessagePack.encode(value)
+        }
+    }
+
+    fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> CodecResult<T> {
+        match *self {
+            WireFormat::Bincode => Bincode.decode(data),
+            WireFormat::Json => Json.decode(data),
+            WireFormat::MessagePack => MessagePack.decode(data)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Codec, Bincode, Json, MessagePack, WireFormat, from_hex, to_hex,
+                to_json_pretty};
+
+    #[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+    struct Reading {
+        sensor: u8,
+        values: Vec<u32>,
+        label: String
+    }
+
+    fn reading() -> Reading {
+        Reading { sensor: 3, values: vec![1, 2, 300000], label: "north".to_string() }
+    }
+
+    fn round_trip<C: Codec>(codec: C) {
+        let encoded = codec.encode(&reading()).ok().unwrap();
+        let decoded: Reading = codec.decode(encoded.as_slice()).ok().unwrap();
+        assert_eq!(decoded, reading());
+    }
+
+    #[test]
+    fn round_trip_test() {
+        round_trip(Bincode);
+        round_trip(Json);
+        round_trip(MessagePack);
+        round_trip(WireFormat::Bincode);
+        round_trip(WireFormat::Json);
+        round_trip(WireFormat::MessagePack);
+    }
+
+    #[test]
+    fn json_test() {
+        let encoded = Json.encode(&reading()).ok().unwrap();
+        let text = String::from_utf8(encoded).ok().unwrap();
+        assert!(text.contains("\"label\":\"north\""));
+    }
+
+    #[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+    struct Signed {
+        #[serde(with = "super::bytes")]
+        signature: Vec<u8>
+    }
+
+    #[test]
+    fn bytes_test() {
+        let signed = Signed { signature: vec![0, 1, 0xab, 0xff] };
+        for &format in [WireFormat::Bincode, WireFormat::Json, WireFormat::MessagePack].iter() {
+            let encoded = format.encode(&signed).ok().unwrap();
+            let decoded: Signed = format.decode(encoded.as_slice()).ok().unwrap();
+            assert_eq!(decoded, signed);
+        }
+
+        let text = String::from_utf8(Json.encode(&signed).ok().unwrap()).ok().unwrap();
+        assert_eq!(text, "{\"signature\":\"AAGr/w==\"}");
+        assert!(Json.decode::<Signed>(b"{\"signature\":\"AAG\"}").is_err());
+        // Bincode writes bytes the same as a Vec<u8>.
+        assert_eq!(Bincode.encode(&signed).ok().unwrap(),
+                   Bincode.encode(&signed.signature).ok().unwrap());
+    }
+
+    #[test]
+    fn hex_test() {
+        assert_eq!(to_hex(&[0, 0x7f, 0xff]), "007fff");
+        assert_eq!(from_hex("007FFF"), Some(vec![0, 0x7f, 0xff]));
+        assert_eq!(from_hex(""), Some(vec![]));
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+        assert_eq!(from_hex("é0"), None);
+    }
+
+    #[test]
+    fn wire_format_id_test() {
+        let formats = [WireFormat::Bincode, WireFormat::Json, WireFormat::MessagePack];
+        for &format in formats.iter() {
+            assert_eq!(WireFormat::from_id(format.id()), Some(format));
+        }
+        assert_eq!(WireFormat::from_id(3), None);
+    }
+
+    #[test]
+    fn pretty_test() {
+        let text = to_json_pretty(&reading()).ok().unwrap();
+        assert!(text.contains("\n"));
+        assert!(text.contains("\"sensor\": 3"));
+
+        let decoded: Reading = Json.decode(text.as_bytes()).ok().unwrap();
+        assert_eq!(decoded, reading());
+    }
+
+    #[test]
+    fn invalid_data_test() {
+        let encoded = Bincode.encode(&reading()).ok().unwrap();
+        let truncated = &encoded[..encoded.len() - 1];
+        assert!(Bincode.decode::<Reading>(truncated).is_err());
+        assert!(Json.decode::<Reading>(b"{\"sensor\":").is_err());
+        assert!(MessagePack.decode::<Reading>(&[0xc1]).is_err());
+
+        // A length prefix far larger than the packet.
+        let bogus = [0xffu8, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x0f];
+        assert!(Bincode.decode::<Vec<u8>>(&bogus).is_err());
+    }
+}
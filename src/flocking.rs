@@ -0,0 +1,281 @@
+// Flocking
+//
+// Reynolds' boids: every agent steers by three rules, looking only at its
+// neighbors.
+//
+// - Separation: move away from neighbors that are too close.
+// - Alignment: match the velocity of the neighbors, for those whose velocity
+//   is known.
+// - Cohesion: move towards the center of the neighbors.
+//
+// The weighted sum of the three is the steering update, an acceleration that
+// is added to the velocity of the agent. Positions come from the Coordinates of
+// the agents' locations; velocities are not part of a SwarmAgent, so they are
+// only known where the application carries them itself, e.g. in Custom events.
+// Agents that move implement Mover, which the steering update is applied to.
+use agent::SwarmAgent;
+use std::vec::Vec;
+use Coordinates;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Vector {
+    pub x: f64,
+    pub y: f64
+}
+
+impl Vector {
+    pub fn new(x: f64, y: f64) -> Vector {
+        Vector { x, y }
+    }
+
+    pub fn zero() -> Vector {
+        Vector::new(0.0, 0.0)
+    }
+
+    pub fn add(&self, other: Vector) -> Vector {
+        Vector::new(self.x + other.x, self.y + other.y)
+    }
+
+    pub fn sub(&self, other: Vector) -> Vector {
+        Vector::new(self.x - other.x, self.y - other.y)
+    }
+
+    pub fn scale(&self, factor: f64) -> Vector {
+        Vector::new(self.x * factor, self.y * factor)
+    }
+
+    pub fn length(&self) -> f64 {
+        (self.x * self.x + self.y * self.y).sqrt()
+    }
+
+    // The vector shortened to at most max.
+    pub fn limit(&self, max: f64) -> Vector {
+        let length = self.length();
+        if length > max {
+            self.scale(max / length)
+        } else {
+            *self
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Neighbor {
+    pub position: Vector,
+    pub velocity: Option<Vector>
+}
+
+impl Neighbor {
+    // A neighbor at the position of an agent, moving at an unknown velocity.
+    pub fn from_agent<Loc: Coordinates>(agent: &SwarmAgent<Loc>) -> Neighbor {
+        let (x, y) = agent.location().coordinates();
+        Neighbor {
+            position: Vector::new(x, y),
+            velocity: None
+        }
+    }
+}
+
+// An agent that moves, and can be steered.
+pub trait Mover {
+    fn position(&self) -> Vector;
+    fn velocity(&self) -> Vector;
+    // Set the velocity of the agent; moving along it is up to the agent.
+    fn set_velocity(&mut self, velocity: Vector);
+}
+
+pub struct Flock {
+    // Neighbors closer than this are steered away from.
+    pub separation_distance: f64,
+    pub separation_weight: f64,
+    pub alignment_weight: f64,
+    pub cohesion_weight: f64,
+    // Largest steering update per step.
+    pub max_force: f64,
+    pub max_speed: f64
+}
+
+impl Default for Flock {
+    fn default() -> Flock {
+        Flock::new()
+    }
+}
+
+impl Flock {
+    pub fn new() -> Flock {
+        Flock {
+            separation_distance: 1.0,
+            separation_weight: 1.5,
+            alignment_weight: 1.0,
+            cohesion_weight: 1.0,
+            max_force: 0.1,
+            max_speed: 1.0
+        }
+    }
+
+    // The steering update of an agent at position, moving at velocity.
+    pub fn steer(&self, position: Vector, velocity: Vector,
+                 neighbors: &[Neighbor]) -> Vector {
+        if neighbors.is_empty() {
+            return Vector::zero()
+        }
+
+        let mut separation = Vector::zero();
+        let mut center = Vector::zero();
+        let mut heading = Vector::zero();
+        let mut headings = 0usize;
+        for n in neighbors.iter() {
+            let away = position.sub(n.position);
+            let distance = away.length();
+            // Repulsion grows as the neighbor gets closer.
+            if distance > 0.0 && distance < self.separation_distance {
+                separation = separation.add(away.scale(1.0 / (distance * distance)));
+            } else if distance == 0.0 && self.separation_distance > 0.0 {
+                // A neighbor at the very same position gives no direction to
+                // move away in. Move away from its velocity, or along the x
+                // axis if that is unknown or the same, as hard as from a
+                // neighbor at a hundredth of the separation distance.
+                let nudge = match n.velocity {
+                    Some(v) if v != velocity => velocity.sub(v),
+                    _ => Vector::new(1.0, 0.0)
+                };
+                let strength = 100.0 / self.separation_distance;
+                separation = separation.add(nudge.scale(strength / nudge.length()));
+            }
+            center = center.add(n.position);
+            if let Some(v) = n.velocity {
+                heading = heading.add(v);
+                headings += 1;
+            }
+        }
+
+        let cohesion = center.scale(1.0 / neighbors.len() as f64).sub(position);
+        let alignment = if headings > 0 {
+            heading.scale(1.0 / headings as f64).sub(velocity)
+        } else {
+            Vector::zero()
+        };
+
+        separation.scale(self.separation_weight).
+            add(alignment.scale(self.alignment_weight)).
+            add(cohesion.scale(self.cohesion_weight)).
+            limit(self.max_force)
+    }
+
+    // Steer a mover, returning its new velocity.
+    pub fn apply<M: Mover>(&self, mover: &mut M, neighbors: &[Neighbor]) -> Vector {
+        let velocity = mover.velocity();
+        let force = self.steer(mover.position(), velocity, neighbors);
+        let velocity = velocity.add(force).limit(self.max_speed);
+        mover.set_velocity(velocity);
+        velocity
+    }
+
+    // Steer a mover by the locations of its neighbors in the swarm, e.g. as
+    // returned from SwarmNetwork::neighbors.
+    pub fn apply_to_agents<M: Mover, Loc: Coordinates>(&self, mover: &mut M,
+                                                      agents: &[SwarmAgent<Loc>]) -> Vector {
+        let neighbors: Vec<Neighbor> = agents.iter().map(|a| Neighbor::from_agent(a)).collect();
+        self.apply(mover, neighbors.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+    use agent::SwarmAgent;
+    use Coordinates;
+    use super::{Flock, Mover, Neighbor, Vector};
+
+    struct Point(f64, f64);
+
+    impl Coordinates for Point {
+        fn coordinates(&self) -> (f64, f64) {
+            let Point(x, y) = *self;
+            (x, y)
+        }
+    }
+
+    struct Boid {
+        position: Vector,
+        velocity: Vector
+    }
+
+    impl Mover for Boid {
+        fn position(&self) -> Vector {
+            self.position
+        }
+
+        fn velocity(&self) -> Vector {
+            self.velocity
+        }
+
+        fn set_velocity(&mut self, velocity: Vector) {
+            self.velocity = velocity;
+        }
+    }
+
+    fn at(x: f64, y: f64) -> Neighbor {
+        Neighbor { position: Vector::new(x, y), velocity: None }
+    }
+
+    #[test]
+    fn separation_test() {
+        let mut flock = Flock::new();
+        flock.cohesion_weight = 0.0;
+        let steer = flock.steer(Vector::zero(), Vector::zero(), &[at(0.5, 0.0)]);
+        assert!(steer.x < 0.0);
+        assert_eq!(steer.y, 0.0);
+
+        // Neighbors at the same position are moved away from all the same.
+        let steer = flock.steer(Vector::zero(), Vector::zero(), &[at(0.0, 0.0)]);
+        assert!((steer.x - flock.max_force).abs() < 1e-9);
+        assert_eq!(steer.y, 0.0);
+        let down = Neighbor { position: Vector::zero(), velocity: Some(Vector::new(0.0, -1.0)) };
+        let steer = flock.steer(Vector::zero(), Vector::zero(), &[down]);
+        assert!(steer.y > 0.0);
+        assert!(steer.x.abs() < 1e-9);
+
+        // Neighbors further away are left alone.
+        let steer = flock.steer(Vector::zero(), Vector::zero(), &[at(2.0, 0.0)]);
+        assert_eq!(steer, Vector::zero());
+    }
+
+    #[test]
+    fn cohesion_test() {
+        let flock = Flock::new();
+        let steer = flock.steer(Vector::zero(), Vector::zero(),
+                                &[at(5.0, 1.0), at(5.0, -1.0)]);
+        assert!(steer.x > 0.0);
+        assert!(steer.y.abs() < 1e-9);
+        assert!(steer.length() <= flock.max_force + 1e-9);
+    }
+
+    #[test]
+    fn alignment_test() {
+        let flock = Flock::new();
+        let up = Some(Vector::new(0.0, 1.0));
+        let neighbors = [Neighbor { position: Vector::new(-5.0, 0.0), velocity: up },
+                         Neighbor { position: Vector::new(5.0, 0.0), velocity: up }];
+        let steer = flock.steer(Vector::zero(), Vector::zero(), &neighbors);
+        assert!(steer.x.abs() < 1e-9);
+        assert!(steer.y > 0.0);
+
+        assert_eq!(flock.steer(Vector::zero(), Vector::zero(), &[]), Vector::zero());
+    }
+
+    #[test]
+    fn apply_test() {
+        let addr = SocketAddr::from(([127, 0, 0, 1], 7000));
+        let agents = [SwarmAgent::new(Point(10.0, 0.0), addr)];
+        let mut flock = Flock::new();
+        flock.max_force = 5.0;
+        let mut boid = Boid { position: Vector::zero(), velocity: Vector::new(0.5, 0.0) };
+
+        let velocity = flock.apply_to_agents(&mut boid, &agents);
+        assert_eq!(velocity, boid.velocity);
+        // Pulled towards the neighbor, at no more than the top speed.
+        assert!((velocity.x - flock.max_speed).abs() < 1e-9);
+        assert_eq!(velocity.y, 0.0);
+    }
+}
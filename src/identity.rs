@@ -0,0 +1,167 @@
+// Agent Identity
+//
+// Every agent holds an Ed25519 keypair. The agent's ByteId is derived from the
+// public key and a nonce, so that an agent cannot claim an id that it does not
+// hold the secret key for. Optionally, the nonce has to be a proof-of-work: the
+// id must start with a given number of zero bits, which makes creating large
+// numbers of identities expensive. All RPCs sent by an agent are signed with
+// its secret key. Session keys between neighbors are agreed with a separate
+// X25519 keypair, whose public key the agent signs into its agent record.
+use byteid::ByteId;
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use x25519_dalek::{PublicKey, StaticSecret};
+use rand::thread_rng;
+use std::vec::Vec;
+
+const PUBLIC_KEY_LEN: usize = 32;
+const SIGNATURE_LEN: usize = 64;
+
+#[derive(Clone)]
+pub struct Keypair {
+    signing: SigningKey,
+    public: Vec<u8>,
+    exchange: StaticSecret,
+    exchange_public: Vec<u8>,
+    nonce: u64
+}
+
+impl Keypair {
+    pub fn generate() -> Keypair {
+        let signing = SigningKey::generate(&mut thread_rng());
+        let exchange = StaticSecret::random_from_rng(thread_rng());
+
+        Keypair {
+            public: signing.verifying_key().to_bytes().to_vec(),
+            signing,
+            exchange_public: PublicKey::from(&exchange).to_bytes().to_vec(),
+            exchange,
+            nonce: 0
+        }
+    }
+
+    // Generate a keypair whose id has at least difficulty leading zero bits.
+    // Every additional bit doubles the expected work.
+    pub fn generate_with_work(difficulty: u32) -> Keypair {
+        let mut keypair = Keypair::generate();
+        while keypair.id().leading_zeros() < difficulty {
+            keypair.nonce += 1;
+        }
+        keypair
+    }
+
+    pub fn public_key(&self) -> &[u8] {
+        self.public.as_slice()
+    }
+
+    // The X25519 public key other agents agree session keys with.
+    pub fn exchange_key(&self) -> &[u8] {
+        self.exchange_public.as_slice()
+    }
+
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    pub fn id(&self) -> ByteId {
+        ByteId::from_public_key(self.public_key(), self.nonce)
+    }
+
+    pub fn sign(&self, data: &[u8]) -> Vec<u8> {
+        self.signing.sign(data).to_bytes().to_vec()
+    }
+
+    // Diffie-Hellman key exchange with another agent's exchange key. Both
+    // agents arrive at the same shared secret. Returns None if the key is not
+    // a valid X25519 key, or one that would let its owner fix the secret.
+    pub fn exchange(&self, exchange_key: &[u8]) -> Option<Vec<u8>> {
+        if exchange_key.len() != PUBLIC_KEY_LEN {
+            return None
+        }
+        let mut key = [0u8; PUBLIC_KEY_LEN];
+        key.copy_from_slice(exchange_key);
+
+        let shared = self.exchange.diffie_hellman(&PublicKey::from(key));
+        if shared.was_contributory() {
+            Some(shared.as_bytes().to_vec())
+        } else {
+            None
+        }
+    }
+}
+
+pub fn verify(public_key: &[u8], data: &[u8], signature: &[u8]) -> bool {
+    if public_key.len() != PUBLIC_KEY_LEN || signature.len() != SIGNATURE_LEN {
+        return false
+    }
+    let mut key = [0u8; PUBLIC_KEY_LEN];
+    key.copy_from_slice(public_key);
+    let mut sig = [0u8; SIGNATURE_LEN];
+    sig.copy_from_slice(signature);
+
+    match VerifyingKey::from_bytes(&key) {
+        Ok(key) => key.verify_strict(data, &Signature::from_bytes(&sig)).is_ok(),
+        Err(_) => false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Keypair, verify};
+
+    #[test]
+    fn sign_verify_test() {
+        let keypair = Keypair::generate();
+        let data = [1u8, 2, 3, 4];
+
+        let sig = keypair.sign(&data);
+        assert!(verify(keypair.public_key(), &data, sig.as_slice()));
+        assert!(!verify(keypair.public_key(), &[1u8, 2, 3], sig.as_slice()));
+    }
+
+    #[test]
+    fn verify_other_key_test() {
+        let keypair = Keypair::generate();
+        let other = Keypair::generate();
+        let data = [1u8, 2, 3, 4];
+
+        let sig = keypair.sign(&data);
+        assert!(!verify(other.public_key(), &data, sig.as_slice()));
+        assert!(!verify(keypair.public_key(), &data, &[0u8; 3]));
+    }
+
+    #[test]
+    fn exchange_test() {
+        let keypair = Keypair::generate();
+        let other = Keypair::generate();
+
+        assert!(keypair.exchange(other.exchange_key()).is_some());
+        assert_eq!(keypair.exchange(other.exchange_key()),
+                   other.exchange(keypair.exchange_key()));
+        assert!(keypair.exchange(other.exchange_key()) !=
+                keypair.exchange(Keypair::generate().exchange_key()));
+
+        // The exchange key is not the signing key.
+        assert!(keypair.exchange_key() != keypair.public_key());
+        assert_eq!(keypair.exchange(&[0u8; 32]), None);
+        assert_eq!(keypair.exchange(&[1u8; 3]), None);
+    }
+
+    #[test]
+    fn generate_with_work_test() {
+        let keypair = Keypair::generate_with_work(8);
+        assert!(keypair.id().leading_zeros() >= 8);
+
+        let data = [1u8, 2, 3, 4];
+        let sig = keypair.sign(&data);
+        assert!(verify(keypair.public_key(), &data, sig.as_slice()));
+    }
+
+    #[test]
+    fn id_test() {
+        let keypair = Keypair::generate();
+        let other = Keypair::generate();
+
+        assert_eq!(keypair.id(), keypair.id());
+        assert!(keypair.id() != other.id());
+    }
+}
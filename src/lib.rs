@@ -1,29 +1,94 @@
 #![crate_name = "swarm"]
 #![crate_type = "rlib"]
 #![crate_type = "dylib"]
-extern crate "rustc-serialize" as rustc_serialize;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate rmp_serde;
 extern crate bincode;
+extern crate time;
+extern crate rand;
+extern crate ed25519_dalek;
+extern crate x25519_dalek;
+extern crate chacha20poly1305;
+extern crate sha1;
+extern crate sha2;
+extern crate base64;
+#[cfg(test)]
+extern crate tempfile;
 
-use swarm::{SwarmMsg, SwarmController};
 use agent::{SwarmAgent};
-use artifact::{SwarmArtifact};
-use swarm::network::SwarmNetwork;
+use serde::Serialize;
+use std::net::ToSocketAddrs;
+
+pub use swarm::{SwarmMsg, SwarmEvent, SwarmController};
+pub use swarm::network::SwarmNetwork;
+
+pub use swarm::network::packet_to_json;
+pub use swarm::metrics::{Metrics, Histogram};
+pub use swarm::admin;
+pub use swarm::admission;
+pub use swarm::capture;
+pub use swarm::reputation;
+pub use swarm::session::Encryption;
+pub use swarm::trace;
+pub use swarm::topology;
+pub use swarm::crawler;
+pub use swarm::aggregate::Estimate;
+pub use swarm::task;
+pub use swarm::crdt;
 
 
 pub mod agent;
 mod byteid;
+mod clock;
+pub mod codec;
+pub mod identity;
 pub mod artifact;
+pub mod registry;
+pub mod pheromone;
+pub mod flocking;
 mod swarm;
 
-pub trait ReactToSwarm<Loc: Location> {
-    fn react(&mut self, msg: &SwarmMsg<Loc>);
+pub trait ReactToSwarm<Loc: Location, P> {
+    fn react(&mut self, msg: &SwarmMsg<Loc, P>);
+
+    // Called when the leader of the agent's region changes, see
+    // SwarmNetwork::enable_election. The leader may be the agent itself.
+    fn leader_changed(&mut self, _leader: &SwarmAgent<Loc>) {}
 }
 
 pub trait Location {
     fn distance(&self, other: &Self) -> usize;
 }
 
-pub struct Swarm<T, Loc> {
-    controller: SwarmController<T, Loc>,
-    network: SwarmNetwork<Loc>
+// Position of a location in the plane, for locations that have one. Used to
+// lay out exported topologies.
+pub trait Coordinates {
+    fn coordinates(&self) -> (f64, f64);
+}
+
+pub struct Swarm<T, Loc, P> {
+    controller: SwarmController<T, Loc, P>,
+    network: SwarmNetwork<Loc, P>
+}
+
+impl<T: ReactToSwarm<Loc, P>, Loc: Location + Serialize + Clone, P: Clone> Swarm<T, Loc, P> {
+    // An agent at loc, listening on address, whose messages are handed to
+    // actor.
+    pub fn new<A: ToSocketAddrs>(actor: T, loc: Loc, address: A) -> Swarm<T, Loc, P> {
+        Swarm {
+            controller: SwarmController::new(actor),
+            network: SwarmNetwork::new(loc, address)
+        }
+    }
+
+    pub fn controller(&mut self) -> &mut SwarmController<T, Loc, P> {
+        &mut self.controller
+    }
+
+    pub fn network(&mut self) -> &mut SwarmNetwork<Loc, P> {
+        &mut self.network
+    }
 }
@@ -0,0 +1,258 @@
+// Pheromone Fields
+//
+// Agents coordinate indirectly by leaving markers in their environment, in the
+// same way ants lay pheromone trails to a food source. Markers are deposited at
+// a location, evaporate over time and diffuse into the surrounding locations.
+// An agent can query the gradient around its position in order to follow a
+// trail.
+//
+// Every agent keeps its own field. Deposits reach the fields of the agent's
+// neighbors, which are the agents around it, as Pheromone events sent with
+// SwarmNetwork::deposit_pheromone and taken up with update.
+use Location;
+use swarm::{SwarmMsg, SwarmEvent};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::vec::Vec;
+
+// Markers weaker than this are removed from the field.
+const MIN_INTENSITY: f64 = 0.001;
+
+// A Location whose surrounding locations can be enumerated, e.g. the cells
+// adjacent to a cell in a grid.
+pub trait Neighborhood: Location + Sized {
+    fn adjacent(&self) -> Vec<Self>;
+}
+
+pub struct PheromoneField<L> {
+    markers: HashMap<L, f64>,
+    // Fraction of every marker that is lost per step.
+    evaporation: f64,
+    // Fraction of every marker that spreads to the adjacent locations per step.
+    diffusion: f64
+}
+
+impl<L: Neighborhood + Hash + Eq + Clone> PheromoneField<L> {
+    // Returns None unless evaporation and diffusion are fractions between 0
+    // and 1.
+    pub fn new(evaporation: f64, diffusion: f64) -> Option<PheromoneField<L>> {
+        if !(0.0..=1.0).contains(&evaporation) || !(0.0..=1.0).contains(&diffusion) {
+            return None
+        }
+        Some(PheromoneField {
+            markers: HashMap::new(),
+            evaporation,
+            diffusion
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.markers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.markers.is_empty()
+    }
+
+    // Amounts that are NaN or infinite are ignored, they would spread to every
+    // marker they touch.
+    pub fn deposit(&mut self, loc: L, amount: f64) {
+        if !amount.is_finite() {
+            return
+        }
+        let intensity = self.intensity(&loc) + amount;
+        self.markers.insert(loc, intensity);
+    }
+
+    // Take up a deposit made by another agent, see
+    // SwarmNetwork::deposit_pheromone. Only positive amounts are taken.
+    pub fn update<P>(&mut self, msg: &SwarmMsg<L, P>) {
+        match *msg.event() {
+            SwarmEvent::Pheromone(ref loc, amount) if amount > 0.0 => {
+                self.deposit(loc.clone(), amount);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn intensity(&self, loc: &L) -> f64 {
+        match self.markers.get(loc) {
+            Some(i) => *i,
+            None => 0.0
+        }
+    }
+
+    pub fn evaporate(&mut self) {
+        let remaining = 1.0 - self.evaporation;
+        let markers: Vec<(L, f64)> = self.markers.
+            iter().
+            map(|(l, i)| (l.clone(), *i * remaining)).
+            collect();
+        self.replace(markers);
+    }
+
+    pub fn diffuse(&mut self) {
+        let mut diffused: HashMap<L, f64> = HashMap::new();
+
+        for (loc, intensity) in self.markers.iter() {
+            let adjacent = loc.adjacent();
+            let count = adjacent.len();
+            let spread = if count == 0 {
+                0.0
+            } else {
+                *intensity * self.diffusion
+            };
+
+            add_intensity(&mut diffused, loc.clone(), *intensity - spread);
+            for adj in adjacent.into_iter() {
+                add_intensity(&mut diffused, adj, spread / count as f64);
+            }
+        }
+
+        let markers: Vec<(L, f64)> = diffused.into_iter().collect();
+        self.replace(markers);
+    }
+
+    // Advance the field by one time step.
+    pub fn step(&mut self) {
+        self.diffuse();
+        self.evaporate();
+    }
+
+    // Intensity at each location adjacent to loc, strongest first.
+    pub fn gradient(&self, loc: &L) -> Vec<(L, f64)> {
+        let mut gradient: Vec<(L, f64)> = loc.adjacent().
+            into_iter().
+            map(|l| {
+                let i = self.intensity(&l);
+                (l, i)
+            }).
+            collect();
+        gradient.sort_by(|a, b| b.1.total_cmp(&a.1));
+        gradient
+    }
+
+    // The adjacent location with the strongest marker, if there is any marker
+    // around loc at all.
+    pub fn strongest_adjacent(&self, loc: &L) -> Option<L> {
+        self.gradient(loc).
+            into_iter().
+            filter(|&(_, i)| i > 0.0).
+            map(|(l, _)| l).
+            next()
+    }
+
+    fn replace(&mut self, markers: Vec<(L, f64)>) {
+        self.markers = markers.
+            into_iter().
+            filter(|&(_, i)| i >= MIN_INTENSITY).
+            collect();
+    }
+}
+
+fn add_intensity<L: Hash + Eq>(markers: &mut HashMap<L, f64>, loc: L, amount: f64) {
+    let intensity = match markers.get(&loc) {
+        Some(i) => *i + amount,
+        None => amount
+    };
+    markers.insert(loc, intensity);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Neighborhood, PheromoneField};
+    use agent::SwarmAgent;
+    use swarm::SwarmMsg;
+    use std::net::SocketAddr;
+    use std::vec::Vec;
+
+    impl Neighborhood for isize {
+        fn adjacent(&self) -> Vec<isize> {
+            vec![*self - 1, *self + 1]
+        }
+    }
+
+    #[test]
+    fn deposit_test() {
+        let mut field = PheromoneField::new(0.5, 0.5).unwrap();
+        field.deposit(3isize, 2.0);
+        field.deposit(3isize, 1.5);
+
+        assert_eq!(field.intensity(&3isize), 3.5);
+        assert_eq!(field.intensity(&4isize), 0.0);
+    }
+
+    #[test]
+    fn evaporate_test() {
+        let mut field = PheromoneField::new(0.5, 0.0).unwrap();
+        field.deposit(0isize, 10.0);
+
+        field.evaporate();
+        assert_eq!(field.intensity(&0isize), 5.0);
+
+        // Markers disappear once they are weak enough.
+        for _ in 0usize..20 {
+            field.evaporate();
+        }
+        assert_eq!(field.len(), 0);
+    }
+
+    #[test]
+    fn diffuse_test() {
+        let mut field = PheromoneField::new(0.0, 0.5).unwrap();
+        field.deposit(0isize, 10.0);
+
+        field.diffuse();
+        assert_eq!(field.intensity(&0isize), 5.0);
+        assert_eq!(field.intensity(&-1isize), 2.5);
+        assert_eq!(field.intensity(&1isize), 2.5);
+        assert_eq!(field.len(), 3);
+    }
+
+    #[test]
+    fn gradient_test() {
+        let mut field = PheromoneField::new(0.1, 0.1).unwrap();
+        assert!(field.strongest_adjacent(&0isize).is_none());
+
+        field.deposit(-1isize, 1.0);
+        field.deposit(1isize, 4.0);
+
+        let gradient = field.gradient(&0isize);
+        assert_eq!(gradient, vec![(1isize, 4.0), (-1isize, 1.0)]);
+        assert_eq!(field.strongest_adjacent(&0isize), Some(1isize));
+    }
+
+    #[test]
+    fn non_finite_test() {
+        let mut field = PheromoneField::new(0.1, 0.1).unwrap();
+        field.deposit(1isize, f64::NAN);
+        field.deposit(-1isize, f64::INFINITY);
+        assert_eq!(field.len(), 0);
+
+        field.deposit(1isize, 2.0);
+        field.step();
+        assert_eq!(field.strongest_adjacent(&0isize), Some(1isize));
+    }
+
+    #[test]
+    fn rates_test() {
+        assert!(PheromoneField::<isize>::new(1.5, 0.1).is_none());
+        assert!(PheromoneField::<isize>::new(0.1, -0.1).is_none());
+        assert!(PheromoneField::<isize>::new(f64::NAN, 0.1).is_none());
+        assert!(PheromoneField::<isize>::new(1.0, 0.0).is_some());
+    }
+
+    #[test]
+    fn update_test() {
+        let mut field = PheromoneField::new(0.1, 0.1).unwrap();
+        let agent = SwarmAgent::new(0isize, SocketAddr::from(([127, 0, 0, 1], 55555)));
+
+        field.update(&SwarmMsg::<isize, ()>::new_pheromone_msg(agent.clone(), 3, 2.0));
+        assert_eq!(field.intensity(&3isize), 2.0);
+
+        field.update(&SwarmMsg::<isize, ()>::new_pheromone_msg(agent.clone(), 3, -2.0));
+        field.update(&SwarmMsg::<isize, ()>::new_pheromone_msg(agent.clone(), 3, f64::NAN));
+        field.update(&SwarmMsg::<isize, ()>::new_converge_msg(agent, 3));
+        assert_eq!(field.intensity(&3isize), 2.0);
+    }
+}
@@ -0,0 +1,272 @@
+// Artifact Registry
+//
+// Each agent keeps a memory of the artifacts it has heard about. The registry
+// is keyed by the artifact's id and is kept up to date from the Artifact and
+// ArtifactGone events that pass through the agent. Artifact and ArtifactUpdate
+// events only replace a known artifact when they carry a newer version, and
+// refresh it when they carry the same one. Artifacts can optionally expire, so
+// that knowledge which is not refreshed disappears over time.
+use Location;
+use byteid::ByteId;
+use artifact::SwarmArtifact;
+use swarm::{SwarmMsg, SwarmEvent};
+use std::collections::HashMap;
+use std::vec::Vec;
+use clock::now_ms;
+
+struct Entry<L, P> {
+    artifact: SwarmArtifact<L, P>,
+    seen: u64
+}
+
+pub struct ArtifactRegistry<L, P> {
+    artifacts: HashMap<ByteId, Entry<L, P>>,
+    // Time in milliseconds an artifact is kept after it was last seen.
+    expiry: Option<u64>
+}
+
+impl<L: Location + Clone, P: Clone> Default for ArtifactRegistry<L, P> {
+    fn default() -> ArtifactRegistry<L, P> {
+        ArtifactRegistry::new()
+    }
+}
+
+impl<L: Location + Clone, P: Clone> ArtifactRegistry<L, P> {
+    pub fn new() -> ArtifactRegistry<L, P> {
+        ArtifactRegistry {
+            artifacts: HashMap::new(),
+            expiry: None
+        }
+    }
+
+    pub fn with_expiry(expiry_ms: u64) -> ArtifactRegistry<L, P> {
+        ArtifactRegistry {
+            artifacts: HashMap::new(),
+            expiry: Some(expiry_ms)
+        }
+    }
+
+    // Number of artifacts that have not expired.
+    pub fn len(&self) -> usize {
+        self.live().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn insert(&mut self, art: SwarmArtifact<L, P>) {
+        let entry = Entry { artifact: art, seen: now_ms() };
+        self.artifacts.insert(entry.artifact.id().clone(), entry);
+    }
+
+    // Insert the artifact unless a copy with the same or a newer version is
+    // already known. A copy with the same version counts as seen again.
+    // Returns whether the artifact was inserted.
+    pub fn insert_if_newer(&mut self, art: SwarmArtifact<L, P>) -> bool {
+        let newer = match self.artifacts.get_mut(art.id()) {
+            Some(e) => {
+                if e.artifact.version() == art.version() {
+                    e.seen = now_ms();
+                }
+                art.is_newer_than(&e.artifact)
+            }
+            None => true
+        };
+
+        if newer {
+            self.insert(art);
+        }
+        newer
+    }
+
+    pub fn remove(&mut self, id: &ByteId) -> Option<SwarmArtifact<L, P>> {
+        self.artifacts.remove(id).map(|e| e.artifact)
+    }
+
+    pub fn get(&self, id: &ByteId) -> Option<&SwarmArtifact<L, P>> {
+        match self.artifacts.get(id) {
+            Some(e) if self.is_live(e, now_ms()) => Some(&e.artifact),
+            _ => None
+        }
+    }
+
+    // Update the registry from an event received from the swarm.
+    pub fn update(&mut self, msg: &SwarmMsg<L, P>) {
+        match msg.event() {
+            &SwarmEvent::Artifact(ref art) |
+            &SwarmEvent::ArtifactUpdate(ref art) => {
+                self.insert_if_newer(art.clone());
+            }
+            SwarmEvent::ArtifactGone(art) => {
+                self.remove(art.id());
+            }
+            _ => {}
+        }
+        self.expire();
+    }
+
+    pub fn artifacts(&self) -> Vec<&SwarmArtifact<L, P>> {
+        self.live()
+    }
+
+    pub fn nearest(&self, loc: &L) -> Option<&SwarmArtifact<L, P>> {
+        self.live().
+            into_iter().
+            min_by_key(|a| a.location().distance(loc))
+    }
+
+    pub fn within(&self, loc: &L, radius: usize) -> Vec<&SwarmArtifact<L, P>> {
+        self.live().
+            into_iter().
+            filter(|a| a.location().distance(loc) <= radius).
+            collect()
+    }
+
+    // Remove all artifacts that have not been seen within the expiry time.
+    pub fn expire(&mut self) {
+        let now = now_ms();
+        let stale: Vec<ByteId> = self.artifacts.
+            iter().
+            filter(|&(_, e)| !self.is_live(e, now)).
+            map(|(id, _)| id.clone()).
+            collect();
+
+        for id in stale.iter() {
+            self.artifacts.remove(id);
+        }
+    }
+
+    fn live(&self) -> Vec<&SwarmArtifact<L, P>> {
+        let now = now_ms();
+        self.artifacts.
+            values().
+            filter(|e| self.is_live(*e, now)).
+            map(|e| &e.artifact).
+            collect()
+    }
+
+    fn is_live(&self, entry: &Entry<L, P>, now: u64) -> bool {
+        match self.expiry {
+            Some(exp) => now - entry.seen < exp,
+            None => true
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use agent::SwarmAgent;
+    use artifact::SwarmArtifact;
+    use swarm::SwarmMsg;
+    use super::ArtifactRegistry;
+    use std::net::SocketAddr;
+    use std::thread;
+    use std::time::Duration;
+
+    fn test_agent() -> SwarmAgent<isize> {
+        SwarmAgent::new(0isize, SocketAddr::from(([127,0,0,1], 55555)))
+    }
+
+    #[test]
+    fn insert_remove_test() {
+        let mut reg = ArtifactRegistry::new();
+        let art = SwarmArtifact::new(5isize, ());
+        let id = art.id().clone();
+
+        reg.insert(art);
+        assert_eq!(reg.len(), 1);
+        assert_eq!(*reg.get(&id).unwrap().location(), 5isize);
+        assert_eq!(reg.artifacts().len(), 1);
+
+        assert!(reg.remove(&id).is_some());
+        assert!(reg.get(&id).is_none());
+    }
+
+    #[test]
+    fn update_from_events_test() {
+        let mut reg = ArtifactRegistry::new();
+        let art = SwarmArtifact::new(5isize, ());
+
+        reg.update(&SwarmMsg::new_artifact_msg(test_agent(), art.clone()));
+        assert!(reg.get(art.id()).is_some());
+
+        reg.update(&SwarmMsg::new_artifact_gone_msg(test_agent(), art.clone()));
+        assert!(reg.get(art.id()).is_none());
+    }
+
+    #[test]
+    fn update_versions_test() {
+        let mut reg = ArtifactRegistry::new();
+        let mut art = SwarmArtifact::new(5isize, 100u32);
+        let orig = art.clone();
+        art.update_payload(30u32);
+
+        reg.update(&SwarmMsg::new_artifact_update_msg(test_agent(), art.clone()));
+        assert_eq!(*reg.get(art.id()).unwrap().payload(), 30u32);
+
+        // A stale copy does not replace the newer one, whichever event it
+        // comes with.
+        reg.update(&SwarmMsg::new_artifact_update_msg(test_agent(), orig.clone()));
+        assert_eq!(*reg.get(art.id()).unwrap().payload(), 30u32);
+        reg.update(&SwarmMsg::new_artifact_msg(test_agent(), orig.clone()));
+        assert_eq!(*reg.get(art.id()).unwrap().payload(), 30u32);
+        assert!(!reg.insert_if_newer(orig));
+    }
+
+    #[test]
+    fn refresh_test() {
+        let mut reg = ArtifactRegistry::with_expiry(20);
+        let art = SwarmArtifact::new(5isize, ());
+        reg.insert(art.clone());
+        thread::sleep(Duration::from_millis(30));
+        assert_eq!(reg.len(), 0);
+
+        // Seeing the same version again keeps the artifact alive.
+        reg.update(&SwarmMsg::new_artifact_msg(test_agent(), art.clone()));
+        assert_eq!(reg.len(), 1);
+        assert!(reg.get(art.id()).is_some());
+    }
+
+    #[test]
+    fn nearest_test() {
+        let mut reg = ArtifactRegistry::new();
+        assert!(reg.nearest(&0isize).is_none());
+
+        reg.insert(SwarmArtifact::new(10isize, ()));
+        reg.insert(SwarmArtifact::new(-3isize, ()));
+        reg.insert(SwarmArtifact::new(4isize, ()));
+
+        assert_eq!(*reg.nearest(&0isize).unwrap().location(), -3isize);
+        assert_eq!(*reg.nearest(&8isize).unwrap().location(), 10isize);
+    }
+
+    #[test]
+    fn within_test() {
+        let mut reg = ArtifactRegistry::new();
+        reg.insert(SwarmArtifact::new(10isize, ()));
+        reg.insert(SwarmArtifact::new(-3isize, ()));
+        reg.insert(SwarmArtifact::new(4isize, ()));
+
+        assert_eq!(reg.within(&0isize, 4).len(), 2);
+        assert_eq!(reg.within(&0isize, 2).len(), 0);
+        assert_eq!(reg.within(&0isize, 10).len(), 3);
+    }
+
+    #[test]
+    fn expiry_test() {
+        let mut stale = ArtifactRegistry::with_expiry(0);
+        let art = SwarmArtifact::new(1isize, ());
+        stale.insert(art.clone());
+        assert_eq!(stale.len(), 0);
+        assert!(stale.get(art.id()).is_none());
+        assert!(stale.nearest(&1isize).is_none());
+        stale.expire();
+        assert_eq!(stale.len(), 0);
+
+        let mut fresh = ArtifactRegistry::with_expiry(60_000);
+        fresh.insert(art.clone());
+        fresh.expire();
+        assert!(fresh.get(art.id()).is_some());
+    }
+}
@@ -0,0 +1,213 @@
+// Admin Interface
+//
+// An agent can listen for admin requests on a separate UDP socket, bound to
+// localhost, through which an operator can inspect the agent and trigger a
+// join, leave or broadcast by hand. The requests are not authenticated, so the
+// socket only binds to loopback addresses and only answers local requests.
+// Requests and responses are encoded as JSON, so that the swarmctl tool can
+// print the responses of any swarm without knowing its location and payload
+// types.
+use agent::SwarmAgent;
+use artifact::SwarmArtifact;
+use codec::{self, Codec, Json};
+use serde::Serialize;
+use serde_json::Value;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+use std::vec::Vec;
+
+// Largest UDP payload, admin responses may list the whole artifact store.
+const MAX_ADMIN_PACKET_SIZE: usize = 65507;
+const ADMIN_TIMEOUT: Option<Duration> = Some(Duration::from_millis(100));
+const CLIENT_TIMEOUT: Option<Duration> = Some(Duration::from_millis(2000));
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub enum AdminRequest {
+    Agent,
+    Neighbors,
+    Artifacts,
+    Counters,
+    // Join the swarm through the agent at the given address.
+    Join(String),
+    Leave,
+    // Broadcast a Custom event with the given data.
+    Broadcast(Vec<u8>)
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Neighbor<Loc> {
+    pub agent: SwarmAgent<Loc>,
+    pub score: i32,
+    // Time since a packet was last received from the neighbor, if ever.
+    pub last_seen_ms_ago: Option<u64>
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Counters {
+    pub neighbors: usize,
+    pub stored_artifacts: usize,
+    pub found_artifacts: usize,
+    pub rejected_packets: u64
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub enum AdminResponse<Loc, P> {
+    Agent(SwarmAgent<Loc>),
+    Neighbors(Vec<Neighbor<Loc>>),
+    Artifacts(Vec<SwarmArtifact<Loc, P>>),
+    Counters(Counters),
+    Done,
+    Error(String)
+}
+
+fn invalid_data(detail: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput,
+                   format!("Invalid admin packet: {}", detail))
+}
+
+fn not_loopback(addr: SocketAddr) -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied,
+                   format!("Admin requests are only accepted on localhost, not {}", addr))
+}
+
+fn recv_buf() -> Vec<u8> {
+    vec![0u8; MAX_ADMIN_PACKET_SIZE]
+}
+
+pub struct AdminSocket {
+    socket: UdpSocket,
+    buf: Vec<u8>
+}
+
+impl AdminSocket {
+    // Bind to a loopback address, other addresses are refused.
+    pub fn bind<A: ToSocketAddrs>(address: A) -> io::Result<AdminSocket> {
+        let addrs: Vec<SocketAddr> = address.to_socket_addrs()?.collect();
+        if let Some(&addr) = addrs.iter().find(|a| !a.ip().is_loopback()) {
+            return Err(not_loopback(addr))
+        }
+        let socket = UdpSocket::bind(addrs.as_slice())?;
+        Ok(AdminSocket { socket, buf: recv_buf() })
+    }
+
+    pub fn socket_name(&mut self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    // Wait briefly for the next request. Requests that can not be decoded are
+    // answered with an error right away, requests from other hosts are
+    // dropped.
+    pub fn recv_request(&mut self) -> io::Result<(AdminRequest, SocketAddr)> {
+        self.socket.set_read_timeout(ADMIN_TIMEOUT)?;
+        let (amt, src) = self.socket.recv_from(self.buf.as_mut_slice())?;
+        if !src.ip().is_loopback() {
+            return Err(not_loopback(src))
+        }
+
+        match Json.decode(&self.buf[..amt]) {
+            Ok(req) => Ok((req, src)),
+            Err(e) => {
+                let response: AdminResponse<(), ()> =
+                    AdminResponse::Error("invalid request".to_string());
+                self.send_response(&response, src)?;
+                Err(invalid_data(format!("{:?}", e)))
+            }
+        }
+    }
+
+    pub fn send_response<R: Serialize>(&mut self, response: &R,
+                                       dest: SocketAddr) -> io::Result<()> {
+        let data = match Json.encode(response) {
+            Ok(data) => data,
+            Err(e) => return Err(invalid_data(format!("{:?}", e)))
+        };
+        self.socket.set_write_timeout(ADMIN_TIMEOUT)?;
+        self.socket.send_to(data.as_slice(), dest).map(|_| ())
+    }
+}
+
+// Send a request to the admin socket of an agent, and return the response as
+// pretty-printed JSON.
+pub fn request<A: ToSocketAddrs>(agent: A, req: &AdminRequest) -> io::Result<String> {
+    let socket = UdpSocket::bind(SocketAddr::from(([127, 0, 0, 1], 0)))?;
+    let data = match Json.encode(req) {
+        Ok(data) => data,
+        Err(e) => return Err(invalid_data(format!("{:?}", e)))
+    };
+
+    socket.set_read_timeout(CLIENT_TIMEOUT)?;
+    socket.send_to(data.as_slice(), agent)?;
+    let mut buf = recv_buf();
+    let (amt, _) = socket.recv_from(buf.as_mut_slice())?;
+
+    let response: Value = match Json.decode(&buf[..amt]) {
+        Ok(response) => response,
+        Err(e) => return Err(invalid_data(format!("{:?}", e)))
+    };
+    codec::to_json_pretty(&response).map_err(|e| invalid_data(format!("{:?}", e)))
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+    use std::thread;
+    use codec::{Codec, Json};
+    use super::{AdminSocket, AdminRequest, AdminResponse, Counters, request};
+
+    fn local_socket() -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], 0))
+    }
+
+    #[test]
+    fn request_json_test() {
+        let req = AdminRequest::Join("127.0.0.1:4000".to_string());
+        let encoded = Json.encode(&req).ok().unwrap();
+        let decoded: AdminRequest = Json.decode(encoded.as_slice()).ok().unwrap();
+        assert_eq!(decoded, req);
+    }
+
+    #[test]
+    fn request_response_test() {
+        let mut admin = AdminSocket::bind(local_socket()).ok().unwrap();
+        let addr = admin.socket_name().ok().unwrap();
+
+        let client = thread::spawn(move || {
+            request(addr, &AdminRequest::Counters)
+        });
+
+        let (req, src) = admin.recv_request().ok().unwrap();
+        assert_eq!(req, AdminRequest::Counters);
+        let response: AdminResponse<isize, ()> = AdminResponse::Counters(Counters {
+            neighbors: 2,
+            stored_artifacts: 0,
+            found_artifacts: 0,
+            rejected_packets: 5
+        });
+        assert!(admin.send_response(&response, src).is_ok());
+
+        let text = client.join().ok().unwrap().ok().unwrap();
+        assert!(text.contains("\"rejected_packets\": 5"));
+    }
+
+    #[test]
+    fn loopback_only_test() {
+        assert!(AdminSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).is_err());
+        assert!(AdminSocket::bind(SocketAddr::from(([10, 0, 0, 1], 0))).is_err());
+        assert!(AdminSocket::bind("localhost:0").is_ok());
+    }
+
+    #[test]
+    fn invalid_request_test() {
+        let mut admin = AdminSocket::bind(local_socket()).ok().unwrap();
+        let addr = admin.socket_name().ok().unwrap();
+        let client = ::std::net::UdpSocket::bind(local_socket()).ok().unwrap();
+
+        assert!(client.send_to(b"{\"Shutdown\":null}", addr).is_ok());
+        assert!(admin.recv_request().is_err());
+
+        let mut buf = [0u8; 256];
+        let (amt, _) = client.recv_from(&mut buf).ok().unwrap();
+        assert!(buf[..amt].starts_with(b"{\"Error\""));
+    }
+}
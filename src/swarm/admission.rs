@@ -0,0 +1,169 @@
+// Admission Control
+//
+// Before an agent accepts a JOIN or a HRTBT, and with it a new neighbor, the
+// request is checked against a blocklist of agent ids and IP addresses and a
+// per source address rate limit. Requests that pass are handed to the
+// Admission hook, which lets the user of the framework apply their own policy.
+// The default hook admits every agent.
+use agent::SwarmAgent;
+use byteid::ByteId;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use clock::now_ms;
+
+pub trait Admission<Loc> {
+    fn admit_join(&mut self, _agn: &SwarmAgent<Loc>) -> bool {
+        true
+    }
+
+    fn admit_heartbeat(&mut self, _agn: &SwarmAgent<Loc>) -> bool {
+        true
+    }
+}
+
+pub struct AllowAll;
+
+impl<Loc> Admission<Loc> for AllowAll {}
+
+pub struct Blocklist {
+    ids: HashSet<ByteId>,
+    ips: HashSet<IpAddr>
+}
+
+impl Default for Blocklist {
+    fn default() -> Blocklist {
+        Blocklist::new()
+    }
+}
+
+impl Blocklist {
+    pub fn new() -> Blocklist {
+        Blocklist {
+            ids: HashSet::new(),
+            ips: HashSet::new()
+        }
+    }
+
+    pub fn block_id(&mut self, id: ByteId) {
+        self.ids.insert(id);
+    }
+
+    pub fn unblock_id(&mut self, id: &ByteId) {
+        self.ids.remove(id);
+    }
+
+    pub fn block_ip(&mut self, ip: IpAddr) {
+        self.ips.insert(ip);
+    }
+
+    pub fn unblock_ip(&mut self, ip: &IpAddr) {
+        self.ips.remove(ip);
+    }
+
+    pub fn is_blocked_id(&self, id: &ByteId) -> bool {
+        self.ids.contains(id)
+    }
+
+    pub fn is_blocked_ip(&self, ip: &IpAddr) -> bool {
+        self.ips.contains(ip)
+    }
+}
+
+// Limits the number of requests accepted from a single IP address within a
+// window of time.
+pub struct RateLimiter {
+    max_requests: u32,
+    window_ms: u64,
+    // Start of the current window and the requests seen in it, by address.
+    windows: HashMap<IpAddr, (u64, u32)>
+}
+
+impl RateLimiter {
+    pub fn new(max_requests: u32, window_ms: u64) -> RateLimiter {
+        RateLimiter {
+            max_requests,
+            window_ms,
+            windows: HashMap::new()
+        }
+    }
+
+    pub fn allow(&mut self, ip: IpAddr) -> bool {
+        self.allow_at(ip, now_ms())
+    }
+
+    // Whether a request from ip at now, in milliseconds, is allowed.
+    pub fn allow_at(&mut self, ip: IpAddr, now: u64) -> bool {
+        // Forget the addresses whose window has passed.
+        let window_ms = self.window_ms;
+        self.windows.retain(|_, &mut (start, _)| now.saturating_sub(start) < window_ms);
+
+        let (start, count) = match self.windows.get(&ip) {
+            Some(&(start, count)) => (start, count),
+            None => (now, 0)
+        };
+
+        if count >= self.max_requests {
+            return false
+        }
+
+        self.windows.insert(ip, (start, count + 1));
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, SocketAddr};
+    use agent::SwarmAgent;
+    use byteid::ByteId;
+    use super::{Admission, AllowAll, Blocklist, RateLimiter};
+
+    fn test_agent() -> SwarmAgent<isize> {
+        SwarmAgent::new(0isize, SocketAddr::from(([127,0,0,1], 55555)))
+    }
+
+    #[test]
+    fn allow_all_test() {
+        let mut admission = AllowAll;
+        assert!(admission.admit_join(&test_agent()));
+        assert!(admission.admit_heartbeat(&test_agent()));
+    }
+
+    #[test]
+    fn blocklist_test() {
+        let mut blocklist = Blocklist::new();
+        let id = ByteId::random_id();
+        let ip = IpAddr::from([10, 0, 0, 1]);
+
+        assert!(!blocklist.is_blocked_id(&id));
+        blocklist.block_id(id.clone());
+        assert!(blocklist.is_blocked_id(&id));
+        assert!(!blocklist.is_blocked_id(&ByteId::random_id()));
+        blocklist.unblock_id(&id);
+        assert!(!blocklist.is_blocked_id(&id));
+
+        blocklist.block_ip(ip);
+        assert!(blocklist.is_blocked_ip(&ip));
+        assert!(!blocklist.is_blocked_ip(&IpAddr::from([10, 0, 0, 2])));
+        blocklist.unblock_ip(&ip);
+        assert!(!blocklist.is_blocked_ip(&ip));
+    }
+
+    #[test]
+    fn rate_limit_test() {
+        let mut limiter = RateLimiter::new(2, 1000);
+        let ip = IpAddr::from([10, 0, 0, 1]);
+        let other = IpAddr::from([10, 0, 0, 2]);
+
+        assert!(limiter.allow_at(ip, 0));
+        assert!(limiter.allow_at(ip, 10));
+        assert!(!limiter.allow_at(ip, 20));
+        assert!(limiter.allow_at(other, 20));
+
+        // A new window starts after window_ms.
+        assert!(limiter.allow_at(ip, 1000));
+        // Windows that have passed are forgotten.
+        assert!(limiter.allow_at(ip, 1020));
+        assert_eq!(limiter.windows.len(), 1);
+    }
+}
@@ -0,0 +1,250 @@
+// Gossip Aggregates
+//
+// Swarm-wide sums, counts and averages are computed with push-sum gossip.
+// For every aggregate, each agent holds a mass made up of the sum of the
+// values contributed, the number of contributions and a weight. In every
+// round an agent keeps half of its mass and sends the other half to a random
+// neighbor with an AGGREGATE RPC, which adds it to its own. The total mass in
+// the swarm never changes, so the ratios of the parts at every agent converge
+// on the ratios of the totals:
+//
+//     average = value / count
+//     sum     = value / weight
+//     count   = count / weight
+//
+// The agent that starts an aggregate holds the entire weight of 1, so the sum
+// and count are only known once it has spread; the average is known as soon as
+// an agent has received any contribution. Agents take part in an aggregate
+// from the first time they hear of it, and a value contributed before or after
+// that is added to their mass. Mass sent in a datagram that is lost is lost
+// with it, which skews the sum and count but not the average.
+//
+// Any neighbor can name a new aggregate, so an agent takes part in at most
+// MAX_AGGREGATES of them.
+use std::collections::HashMap;
+use std::vec::Vec;
+
+pub const MAX_AGGREGATES: usize = 64;
+
+// The whole swarm holds a weight of 1 for every aggregate it started, and a
+// count of one for every contribution.
+const MAX_WEIGHT: f64 = 1.0;
+const MAX_COUNT: f64 = 1e9;
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Mass {
+    pub value: f64,
+    pub count: f64,
+    pub weight: f64
+}
+
+impl Mass {
+    // Parts that are negative, not finite or larger than the swarm could
+    // hold could never have come from a correct agent.
+    pub fn is_valid(&self) -> bool {
+        [self.value, self.count, self.weight].iter().all(|p| p.is_finite()) &&
+            self.count >= 0.0 && self.count <= MAX_COUNT &&
+            self.weight >= 0.0 && self.weight <= MAX_WEIGHT
+    }
+
+    fn add(&mut self, other: &Mass) {
+        self.value += other.value;
+        self.count += other.count;
+        self.weight += other.weight;
+    }
+
+    fn halve(&mut self) -> Mass {
+        self.value /= 2.0;
+        self.count /= 2.0;
+        self.weight /= 2.0;
+        *self
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Estimate {
+    pub sum: Option<f64>,
+    pub count: Option<f64>,
+    pub average: Option<f64>
+}
+
+fn ratio(num: f64, denom: f64) -> Option<f64> {
+    if denom > 0.0 { Some(num / denom) } else { None }
+}
+
+pub struct Aggregates {
+    masses: HashMap<String, Mass>,
+    // Values contributed to aggregates not heard of yet.
+    pending: HashMap<String, Mass>
+}
+
+impl Aggregates {
+    pub fn new() -> Aggregates {
+        Aggregates {
+            masses: HashMap::new(),
+            pending: HashMap::new()
+        }
+    }
+
+    // Start an aggregate with this agent holding all of the weight. Starting
+    // an aggregate that is already known has no effect.
+    pub fn start(&mut self, name: &str) {
+        if !self.masses.contains_key(name) {
+            self.join(name, Mass { value: 0.0, count: 0.0, weight: 1.0 });
+        }
+    }
+
+    pub fn contribute(&mut self, name: &str, value: f64) {
+        let contribution = Mass { value, count: 1.0, weight: 0.0 };
+        if let Some(mass) = self.masses.get_mut(name) {
+            mass.add(&contribution);
+            return
+        }
+        if let Some(pending) = self.pending.get_mut(name) {
+            pending.add(&contribution);
+            return
+        }
+        self.pending.insert(name.to_string(), contribution);
+    }
+
+    // Add mass received from a neighbor. Mass of an aggregate not known yet
+    // is dropped once MAX_AGGREGATES are known.
+    pub fn receive(&mut self, name: &str, mass: Mass) {
+        if let Some(own) = self.masses.get_mut(name) {
+            own.add(&mass);
+            return
+        }
+        if self.masses.len() < MAX_AGGREGATES {
+            self.join(name, mass);
+        }
+    }
+
+    fn join(&mut self, name: &str, mut mass: Mass) {
+        if let Some(pending) = self.pending.remove(name) {
+            mass.add(&pending);
+        }
+        self.masses.insert(name.to_string(), mass);
+    }
+
+    // Halve the mass of every aggregate, returning the halves to send to a
+    // neighbor.
+    pub fn split(&mut self) -> Vec<(String, Mass)> {
+        self.masses.iter_mut().
+            map(|(name, mass)| (name.clone(), mass.halve())).
+            collect()
+    }
+
+    pub fn estimate(&self, name: &str) -> Option<Estimate> {
+        self.masses.get(name).map(|mass| {
+            Estimate {
+                sum: ratio(mass.value, mass.weight),
+                count: ratio(mass.count, mass.weight),
+                average: ratio(mass.value, mass.count)
+            }
+        })
+    }
+
+    #[cfg(test)]
+    pub fn names(&self) -> Vec<String> {
+        self.masses.keys().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Aggregates, Mass, MAX_AGGREGATES};
+
+    #[test]
+    fn valid_mass_test() {
+        assert!(Mass { value: -3.0, count: 1.0, weight: 0.5 }.is_valid());
+        assert!(!Mass { value: 1.0, count: -1.0, weight: 0.0 }.is_valid());
+        assert!(!Mass { value: 1.0, count: 1.0, weight: f64::NAN }.is_valid());
+        assert!(!Mass { value: 1.0, count: 1.0, weight: 2.0 }.is_valid());
+        assert!(!Mass { value: 1.0, count: 1e12, weight: 0.5 }.is_valid());
+    }
+
+    fn close(a: Option<f64>, b: f64) -> bool {
+        a.is_some_and(|a| (a - b).abs() < 1e-6)
+    }
+
+    // Push the halves of every agent to the next one, around a ring.
+    fn ring_round(agents: &mut [Aggregates]) {
+        let halves: Vec<Vec<(String, Mass)>> = agents.iter_mut().map(|a| a.split()).collect();
+        let n = agents.len();
+        for (i, half) in halves.into_iter().enumerate() {
+            for (name, mass) in half.into_iter() {
+                agents[(i + 1) % n].receive(name.as_str(), mass);
+            }
+        }
+    }
+
+    #[test]
+    fn contribute_test() {
+        let mut aggregates = Aggregates::new();
+        aggregates.contribute("battery", 0.5);
+        aggregates.contribute("battery", 0.3);
+        assert!(aggregates.estimate("battery").is_none());
+
+        aggregates.start("battery");
+        aggregates.contribute("battery", 0.7);
+        let estimate = aggregates.estimate("battery").unwrap();
+        assert!(close(estimate.sum, 1.5));
+        assert!(close(estimate.count, 3.0));
+        assert!(close(estimate.average, 0.5));
+    }
+
+    #[test]
+    fn split_test() {
+        let mut aggregates = Aggregates::new();
+        aggregates.start("battery");
+        aggregates.contribute("battery", 4.0);
+
+        let halves = aggregates.split();
+        assert_eq!(halves.len(), 1);
+        assert_eq!(halves[0].1, Mass { value: 2.0, count: 0.5, weight: 0.5 });
+
+        // Halving leaves the estimates as they were.
+        let estimate = aggregates.estimate("battery").unwrap();
+        assert!(close(estimate.sum, 4.0));
+        assert!(close(estimate.count, 1.0));
+        assert!(aggregates.estimate("other").is_none());
+    }
+
+    #[test]
+    fn max_aggregates_test() {
+        let mut aggregates = Aggregates::new();
+        let mass = Mass { value: 1.0, count: 1.0, weight: 0.5 };
+        for i in 0..MAX_AGGREGATES {
+            aggregates.receive(format!("aggregate {}", i).as_str(), mass);
+        }
+        aggregates.receive("one too many", mass);
+        assert_eq!(aggregates.names().len(), MAX_AGGREGATES);
+        assert!(aggregates.estimate("one too many").is_none());
+
+        // Aggregates started or known already still take mass.
+        aggregates.receive("aggregate 0", mass);
+        assert!(close(aggregates.estimate("aggregate 0").unwrap().count, 2.0));
+    }
+
+    #[test]
+    fn converge_test() {
+        let values = [10.0, 20.0, 30.0, 40.0, 50.0];
+        let mut agents: Vec<Aggregates> = values.iter().map(|&v| {
+            let mut aggregates = Aggregates::new();
+            aggregates.contribute("battery", v);
+            aggregates
+        }).collect();
+        agents[0].start("battery");
+
+        for _ in 0usize..200 {
+            ring_round(&mut agents);
+        }
+
+        for agent in agents.iter() {
+            let estimate = agent.estimate("battery").unwrap();
+            assert!(close(estimate.sum, 150.0));
+            assert!(close(estimate.count, 5.0));
+            assert!(close(estimate.average, 30.0));
+        }
+    }
+}
@@ -0,0 +1,181 @@
+// Packet Capture
+//
+// A SwarmSocket can record every packet it sends and receives to a capture
+// file, along with the time and the address of the peer. Packets are recorded
+// in cleartext, after decryption and before encryption, so that a capture can
+// be inspected with packet_to_json and replayed into a SwarmNetwork.
+//
+// A capture file is a sequence of records, each a big-endian u32 length
+// followed by the Bincode encoding of a CapturedPacket.
+use agent::SwarmAddr;
+use codec::{Codec, Bincode};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::vec::Vec;
+use time;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Direction {
+    Sent,
+    Received
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct CapturedPacket {
+    // Milliseconds since the epoch.
+    timestamp: u64,
+    direction: Direction,
+    peer: SwarmAddr,
+    data: Vec<u8>
+}
+
+impl CapturedPacket {
+    pub fn new(direction: Direction, peer: SocketAddr, data: Vec<u8>) -> CapturedPacket {
+        let now = time::get_time();
+        let timestamp = now.sec as u64 * 1000 + now.nsec as u64 / 1_000_000;
+        CapturedPacket::at(timestamp, direction, peer, data)
+    }
+
+    // A packet captured at timestamp, in milliseconds since the epoch.
+    pub fn at(timestamp: u64, direction: Direction, peer: SocketAddr,
+              data: Vec<u8>) -> CapturedPacket {
+        CapturedPacket {
+            timestamp,
+            direction,
+            peer: SwarmAddr::new(peer),
+            data
+        }
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    pub fn peer(&self) -> SocketAddr {
+        self.peer.socket_addr()
+    }
+
+    pub fn data(&self) -> &[u8] {
+        self.data.as_slice()
+    }
+}
+
+fn corrupt_capture(detail: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput,
+                   format!("Corrupt capture record: {}", detail))
+}
+
+pub struct CaptureWriter {
+    file: File
+}
+
+impl CaptureWriter {
+    pub fn create(path: &Path) -> io::Result<CaptureWriter> {
+        let file = File::create(path)?;
+        Ok(CaptureWriter { file })
+    }
+
+    pub fn write(&mut self, packet: &CapturedPacket) -> io::Result<()> {
+        let record = match Bincode.encode(packet) {
+            Ok(record) => record,
+            Err(e) => return Err(corrupt_capture(format!("{:?}", e)))
+        };
+        self.file.write_all(&(record.len() as u32).to_be_bytes())?;
+        self.file.write_all(record.as_slice())?;
+        self.file.flush()
+    }
+}
+
+pub fn read_capture(path: &Path) -> io::Result<Vec<CapturedPacket>> {
+    let mut file = File::open(path)?;
+    let mut remaining = file.metadata()?.len();
+    let mut packets = Vec::new();
+
+    loop {
+        let mut len = [0u8; 4];
+        match file.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e)
+        };
+        // The length is checked against what is left of the file before
+        // anything is allocated for the record.
+        remaining = remaining.saturating_sub(len.len() as u64);
+        let len = u32::from_be_bytes(len) as u64;
+        if len > remaining {
+            return Err(corrupt_capture(format!("record of {} bytes, {} left", len, remaining)))
+        }
+        remaining -= len;
+        let mut record = vec![0u8; len as usize];
+        file.read_exact(&mut record)?;
+        match Bincode.decode(record.as_slice()) {
+            Ok(packet) => packets.push(packet),
+            Err(e) => return Err(corrupt_capture(format!("{:?}", e)))
+        }
+    }
+
+    Ok(packets)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Write};
+    use std::net::SocketAddr;
+    use tempfile::TempDir;
+    use super::{CaptureWriter, CapturedPacket, Direction, read_capture};
+
+    fn test_addr() -> SocketAddr {
+        SocketAddr::from(([127,0,0,1], 55555))
+    }
+
+    #[test]
+    fn capture_test() {
+        let dir = TempDir::new().ok().unwrap();
+        let path = dir.path().join("swarm.cap");
+        let sent = CapturedPacket::new(Direction::Sent, test_addr(), vec![1u8, 2, 3]);
+        let received = CapturedPacket::new(Direction::Received, test_addr(), vec![4u8]);
+
+        {
+            let mut writer = CaptureWriter::create(&path).ok().unwrap();
+            assert!(writer.write(&sent).is_ok());
+            assert!(writer.write(&received).is_ok());
+        }
+
+        let packets = read_capture(&path).ok().unwrap();
+        assert_eq!(packets, vec![sent, received]);
+        assert_eq!(packets[1].direction(), Direction::Received);
+        assert_eq!(packets[1].peer(), test_addr());
+        assert_eq!(packets[1].data(), [4u8].as_slice());
+        assert!(packets[1].timestamp() > 0);
+    }
+
+    #[test]
+    fn corrupt_capture_test() {
+        let dir = TempDir::new().ok().unwrap();
+        let path = dir.path().join("swarm.cap");
+        {
+            let mut writer = CaptureWriter::create(&path).ok().unwrap();
+            assert!(writer.file.write_all(&3u32.to_be_bytes()).is_ok());
+            assert!(writer.file.write_all(&[1u8, 2, 3]).is_ok());
+        }
+
+        assert!(read_capture(&path).is_err());
+        assert!(read_capture(&dir.path().join("missing.cap")).is_err());
+
+        // A length beyond the end of the file is not allocated for.
+        let path = dir.path().join("truncated.cap");
+        {
+            let mut writer = CaptureWriter::create(&path).ok().unwrap();
+            assert!(writer.file.write_all(&u32::MAX.to_be_bytes()).is_ok());
+            assert!(writer.file.write_all(&[1u8, 2, 3]).is_ok());
+        }
+        let err = read_capture(&path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
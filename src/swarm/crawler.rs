@@ -0,0 +1,343 @@
+// Crawler
+//
+// A crawl enumerates the agents of a swarm by walking neighbor tables breadth
+// first. Starting from a single address, every agent learned of is asked for
+// its neighbors with GET_NEIGHBORS, and the addresses in its NEIGHBORS answer
+// are queued in turn. At most max_in_flight requests are outstanding at a
+// time and at most max_rate requests are sent per second, so that a crawl does
+// not trip the rate limits of the agents it visits. Agents that do not answer
+// within the timeout are reported as unreachable.
+//
+// An answer may take several packets. Only the agent that was asked can
+// answer, and it can not name more than max_per_answer agents, so that a
+// single agent can not flood the crawl with made-up records.
+//
+// A Crawl only keeps the state of a crawl; SwarmNetwork::crawl sends the
+// requests and feeds it the answers.
+use agent::SwarmAgent;
+use byteid::ByteId;
+use swarm::topology::Topology;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::vec::Vec;
+use clock::now_ms;
+
+#[derive(Copy, Clone, Debug)]
+pub struct CrawlConfig {
+    pub max_in_flight: usize,
+    // Requests sent per second.
+    pub max_rate: u32,
+    pub timeout_ms: u64,
+    // The crawl stops following neighbors once this many agents are known.
+    pub max_agents: usize,
+    // Agents followed from the answer of a single agent.
+    pub max_per_answer: usize
+}
+
+impl Default for CrawlConfig {
+    fn default() -> CrawlConfig {
+        CrawlConfig::new()
+    }
+}
+
+impl CrawlConfig {
+    pub fn new() -> CrawlConfig {
+        CrawlConfig {
+            max_in_flight: 8,
+            max_rate: 20,
+            timeout_ms: 2000,
+            max_agents: 10000,
+            max_per_answer: 16
+        }
+    }
+
+    // A crawl with no requests in flight or no requests per second allowed
+    // would never finish.
+    pub fn is_valid(&self) -> bool {
+        self.max_in_flight > 0 && self.max_rate > 0
+    }
+}
+
+pub struct CrawlResult<Loc> {
+    agents: Vec<SwarmAgent<Loc>>,
+    // Neighbor table of each agent that answered.
+    tables: Vec<(SwarmAgent<Loc>, Vec<SwarmAgent<Loc>>)>,
+    unreachable: Vec<SocketAddr>
+}
+
+impl<Loc: Clone> CrawlResult<Loc> {
+    // Every agent that answered, in the order they answered.
+    pub fn agents(&self) -> &[SwarmAgent<Loc>] {
+        self.agents.as_slice()
+    }
+
+    pub fn unreachable(&self) -> &[SocketAddr] {
+        self.unreachable.as_slice()
+    }
+
+    pub fn topology(&self) -> Topology<Loc> {
+        let mut topology = Topology::new();
+        for (agent, neighbors) in self.tables.iter() {
+            topology.add_agent(agent, neighbors.as_slice());
+        }
+        topology
+    }
+}
+
+pub struct Crawl<Loc> {
+    config: CrawlConfig,
+    queue: VecDeque<SocketAddr>,
+    // Addresses queued so far, so that every agent is asked once.
+    queued: HashSet<SocketAddr>,
+    // When each outstanding request was sent.
+    in_flight: HashMap<SocketAddr, u64>,
+    // The agent that answered at each address, the index of its table and
+    // the number of agents it named.
+    answers: HashMap<SocketAddr, (ByteId, usize, usize)>,
+    // When the requests of the last second were sent, oldest first.
+    recent: VecDeque<u64>,
+    ids: HashSet<ByteId>,
+    result: CrawlResult<Loc>
+}
+
+impl<Loc: Clone> Crawl<Loc> {
+    pub fn new(start: SocketAddr, config: CrawlConfig) -> Crawl<Loc> {
+        let mut crawl = Crawl {
+            config,
+            queue: VecDeque::new(),
+            queued: HashSet::new(),
+            in_flight: HashMap::new(),
+            answers: HashMap::new(),
+            recent: VecDeque::new(),
+            ids: HashSet::new(),
+            result: CrawlResult {
+                agents: Vec::new(),
+                tables: Vec::new(),
+                unreachable: Vec::new()
+            }
+        };
+        crawl.enqueue(start);
+        crawl
+    }
+
+    fn enqueue(&mut self, addr: SocketAddr) {
+        if self.queued.insert(addr) {
+            self.queue.push_back(addr);
+        }
+    }
+
+    // The address to ask next, if the in-flight and rate limits allow another
+    // request right now.
+    pub fn next_request(&mut self) -> Option<SocketAddr> {
+        self.next_request_at(now_ms())
+    }
+
+    pub fn next_request_at(&mut self, now: u64) -> Option<SocketAddr> {
+        while self.recent.front().is_some_and(|&sent| now.saturating_sub(sent) >= 1000) {
+            self.recent.pop_front();
+        }
+
+        if self.in_flight.len() >= self.config.max_in_flight ||
+            self.recent.len() >= self.config.max_rate as usize {
+            return None
+        }
+
+        let addr = self.queue.pop_front()?;
+        self.in_flight.insert(addr, now);
+        self.recent.push_back(now);
+        Some(addr)
+    }
+
+    // Record a packet of the NEIGHBORS answer received from src. Answers to
+    // requests that were never sent, or that timed out, are ignored, as are
+    // later packets from another agent than the first. Agents named beyond
+    // max_per_answer are dropped.
+    pub fn answered(&mut self, src: SocketAddr, agent: SwarmAgent<Loc>,
+                    neighbors: Vec<SwarmAgent<Loc>>) -> bool {
+        let (table, named) = if self.in_flight.remove(&src).is_some() {
+            // The agent may be known under another address than the one it
+            // was asked at.
+            self.queued.insert(agent.address().socket_addr());
+            if self.ids.insert(agent.id().clone()) {
+                self.result.agents.push(agent.clone());
+            }
+            self.result.tables.push((agent.clone(), Vec::new()));
+            (self.result.tables.len() - 1, 0)
+        } else {
+            match self.answers.get(&src) {
+                Some(&(ref id, table, named)) if id == agent.id() => (table, named),
+                _ => return false
+            }
+        };
+
+        let room = self.config.max_per_answer.saturating_sub(named);
+        let neighbors: Vec<SwarmAgent<Loc>> = neighbors.into_iter().
+            filter(|n| n.id() != agent.id()).
+            take(room).
+            collect();
+        for neighbor in neighbors.iter() {
+            if self.queued.len() >= self.config.max_agents {
+                break
+            }
+            self.enqueue(neighbor.address().socket_addr());
+        }
+        self.answers.insert(src, (agent.id().clone(), table, named + neighbors.len()));
+        self.result.tables[table].1.extend(neighbors);
+        true
+    }
+
+    // Give up on the requests that have been outstanding for longer than the
+    // timeout.
+    pub fn expire(&mut self) {
+        self.expire_at(now_ms())
+    }
+
+    pub fn expire_at(&mut self, now: u64) {
+        let timeout = self.config.timeout_ms;
+        let expired: Vec<SocketAddr> = self.in_flight.iter().
+            filter(|&(_, &sent)| now.saturating_sub(sent) >= timeout).
+            map(|(&addr, _)| addr).
+            collect();
+
+        for addr in expired.into_iter() {
+            self.in_flight.remove(&addr);
+            self.result.unreachable.push(addr);
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.queue.is_empty() && self.in_flight.is_empty()
+    }
+
+    pub fn into_result(self) -> CrawlResult<Loc> {
+        self.result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+    use agent::SwarmAgent;
+    use super::{Crawl, CrawlConfig};
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn agent(port: u16) -> SwarmAgent<isize> {
+        SwarmAgent::new(port as isize, addr(port))
+    }
+
+    fn config() -> CrawlConfig {
+        CrawlConfig {
+            max_in_flight: 2,
+            max_rate: 3,
+            timeout_ms: 500,
+            max_agents: 100,
+            max_per_answer: 4
+        }
+    }
+
+    #[test]
+    fn breadth_first_test() {
+        let mut crawl = Crawl::new(addr(1), config());
+        assert_eq!(crawl.next_request_at(0), Some(addr(1)));
+        assert_eq!(crawl.next_request_at(0), None);
+
+        assert!(crawl.answered(addr(1), agent(1), vec![agent(2), agent(3)]));
+        assert_eq!(crawl.next_request_at(10), Some(addr(2)));
+        assert_eq!(crawl.next_request_at(10), Some(addr(3)));
+
+        // Agents already asked are not queued again.
+        assert!(crawl.answered(addr(2), agent(2), vec![agent(1), agent(3)]));
+        assert!(crawl.answered(addr(3), agent(3), vec![agent(2)]));
+        assert!(crawl.is_done());
+
+        let result = crawl.into_result();
+        assert_eq!(result.agents().len(), 3);
+        assert_eq!(result.unreachable().len(), 0);
+        assert_eq!(result.topology().edge_count(), 5);
+    }
+
+    #[test]
+    fn in_flight_test() {
+        let neighbors: Vec<SwarmAgent<isize>> = (2u16..6).map(agent).collect();
+        let mut crawl = Crawl::new(addr(1), config());
+        assert_eq!(crawl.next_request_at(0), Some(addr(1)));
+        assert!(crawl.answered(addr(1), agent(1), neighbors));
+
+        assert!(crawl.next_request_at(2000).is_some());
+        assert!(crawl.next_request_at(2000).is_some());
+        assert_eq!(crawl.next_request_at(2000), None);
+    }
+
+    #[test]
+    fn rate_limit_test() {
+        let neighbors: Vec<SwarmAgent<isize>> = (2u16..6).map(agent).collect();
+        let mut crawl = Crawl::new(addr(1), config());
+        assert_eq!(crawl.next_request_at(0), Some(addr(1)));
+        assert!(crawl.answered(addr(1), agent(1), neighbors));
+
+        let first = crawl.next_request_at(10).unwrap();
+        assert!(crawl.answered(first, agent(first.port()), Vec::new()));
+        let second = crawl.next_request_at(20).unwrap();
+        assert!(crawl.answered(second, agent(second.port()), Vec::new()));
+
+        // Three requests were sent within a second.
+        assert_eq!(crawl.next_request_at(30), None);
+        assert!(crawl.next_request_at(1000).is_some());
+    }
+
+    #[test]
+    fn split_answer_test() {
+        let answering = agent(1);
+        let mut crawl = Crawl::new(addr(1), config());
+        assert_eq!(crawl.next_request_at(0), Some(addr(1)));
+        assert!(crawl.answered(addr(1), answering.clone(), vec![agent(2), answering.clone()]));
+        assert!(crawl.answered(addr(1), answering.clone(), vec![agent(3)]));
+
+        // Later packets must come from the agent that answered first.
+        assert!(!crawl.answered(addr(1), agent(1), vec![agent(4)]));
+
+        let result = crawl.into_result();
+        assert_eq!(result.agents().len(), 1);
+        assert_eq!(result.topology().edge_count(), 2);
+    }
+
+    #[test]
+    fn max_per_answer_test() {
+        let answering = agent(1);
+        let neighbors: Vec<SwarmAgent<isize>> = (2u16..8).map(agent).collect();
+        let mut crawl = Crawl::new(addr(1), config());
+        assert_eq!(crawl.next_request_at(0), Some(addr(1)));
+        assert!(crawl.answered(addr(1), answering.clone(), neighbors[..3].to_vec()));
+        assert!(crawl.answered(addr(1), answering, neighbors[3..].to_vec()));
+
+        // Only four of the six agents named are followed.
+        let mut asked = 0;
+        for now in 0u64..10 {
+            while let Some(addr) = crawl.next_request_at(1000 * now) {
+                asked += 1;
+                assert!(crawl.answered(addr, agent(addr.port()), Vec::new()));
+            }
+        }
+        assert_eq!(asked, 4);
+        assert!(crawl.is_done());
+    }
+
+    #[test]
+    fn timeout_test() {
+        let mut crawl: Crawl<isize> = Crawl::new(addr(1), config());
+        assert_eq!(crawl.next_request_at(0), Some(addr(1)));
+        crawl.expire_at(100);
+        assert!(!crawl.is_done());
+
+        crawl.expire_at(600);
+        assert!(crawl.is_done());
+        assert!(!crawl.answered(addr(1), agent(1), Vec::new()));
+
+        let result = crawl.into_result();
+        assert_eq!(result.agents().len(), 0);
+        assert_eq!(result.unreachable(), [addr(1)].as_slice());
+    }
+}
@@ -0,0 +1,692 @@
+// Shared State
+//
+// A replicated key-value state made of conflict-free replicated data types.
+// Every agent updates its own replica, and replicas are merged with those of
+// neighbors by anti-entropy gossip with STATE RPCs. Merging is commutative,
+// associative and idempotent, so replicas converge on the same state no matter
+// the order in which updates arrive, how often they are repeated, or how long
+// a partition lasts.
+//
+// - LwwRegister: a single value, where the write with the latest timestamp
+//   wins. Ties are broken by the id of the writer. Writes are signed by their
+//   writer, so that nobody can make up a later write in its name.
+// - GCounter: a counter that only grows, keeping a count per agent.
+// - OrSet: a set where elements can be added and removed again. Every add is
+//   tagged with the adding agent and a sequence number, and a removal only
+//   removes the tags it has seen, so an add concurrent with a removal wins.
+//
+// Replicas of an entry that were given different types by different agents
+// cannot be merged. A register wins over the other types, as only registers are
+// signed, and a set wins over a counter, so that every agent ends up with the
+// same type.
+//
+// Replicas received from other agents are only trusted as far as their sender
+// can vouch for them. Registers must be signed by their writer and may not be
+// timestamped more than MAX_CLOCK_SKEW ahead of the local clock, or a single
+// write could win over every later one. Of counters and sets, only the counts
+// and adds tagged with the sender's own id are taken, together with the
+// removals of adds already known here, so that nobody can count or add in the
+// name of another agent. Counts and adds therefore spread from the agent that
+// made them to its neighbors, while signed writes travel further. Every sender
+// may create at most MAX_KEYS_PER_AGENT entries, and at most MAX_ENTRIES are
+// created from received replicas in total.
+use byteid::ByteId;
+use codec::{self, Codec, Bincode};
+use identity::{self, Keypair};
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet};
+use std::vec::Vec;
+use clock::wall_clock_ms;
+
+// How far ahead of the local clock a received write may be timestamped.
+pub const MAX_CLOCK_SKEW: u64 = 30_000;
+// Entries a single agent can create in the replica of another.
+pub const MAX_KEYS_PER_AGENT: usize = 256;
+// Entries created from received replicas.
+pub const MAX_ENTRIES: usize = 4096;
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct LwwRegister {
+    value: Vec<u8>,
+    timestamp: u64,
+    writer: ByteId,
+    // Public key and id nonce of the writer, and its signature of the write.
+    #[serde(with = "codec::bytes")]
+    public_key: Vec<u8>,
+    nonce: u64,
+    #[serde(with = "codec::bytes")]
+    signature: Vec<u8>
+}
+
+impl LwwRegister {
+    // An unsigned write, see signed.
+    pub fn new(value: Vec<u8>, timestamp: u64, writer: ByteId) -> LwwRegister {
+        LwwRegister {
+            value,
+            timestamp,
+            writer,
+            public_key: Vec::new(),
+            nonce: 0,
+            signature: Vec::new()
+        }
+    }
+
+    // A write signed by the agent holding keypair, to the entry key.
+    pub fn signed(key: &str, value: Vec<u8>, timestamp: u64, keypair: &Keypair) -> LwwRegister {
+        let mut reg = LwwRegister::new(value, timestamp, keypair.id());
+        reg.public_key = keypair.public_key().to_vec();
+        reg.nonce = keypair.nonce();
+        reg.signature = keypair.sign(reg.signed_data(key).as_slice());
+        reg
+    }
+
+    // Whether the writer's id belongs to its public key, and the write to the
+    // entry key was signed by it.
+    pub fn is_authentic(&self, key: &str) -> bool {
+        !self.public_key.is_empty() &&
+            ByteId::from_public_key(self.public_key.as_slice(), self.nonce) == self.writer &&
+            identity::verify(self.public_key.as_slice(),
+                             self.signed_data(key).as_slice(),
+                             self.signature.as_slice())
+    }
+
+    fn signed_data(&self, key: &str) -> Vec<u8> {
+        Bincode.encode(&(key, &self.value, &self.timestamp, &self.writer)).
+            unwrap_or_default()
+    }
+
+    pub fn value(&self) -> &[u8] {
+        self.value.as_slice()
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    pub fn merge(&mut self, other: &LwwRegister) {
+        if (other.timestamp, &other.writer) > (self.timestamp, &self.writer) {
+            *self = other.clone();
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct GCounter {
+    counts: BTreeMap<ByteId, u64>
+}
+
+impl Default for GCounter {
+    fn default() -> GCounter {
+        GCounter::new()
+    }
+}
+
+impl GCounter {
+    pub fn new() -> GCounter {
+        GCounter { counts: BTreeMap::new() }
+    }
+
+    pub fn increment(&mut self, agent: &ByteId, n: u64) {
+        let count = self.counts.get(agent).map_or(0, |&c| c);
+        self.counts.insert(agent.clone(), count.saturating_add(n));
+    }
+
+    pub fn value(&self) -> u64 {
+        self.counts.values().fold(0, |sum, &c| sum.saturating_add(c))
+    }
+
+    pub fn merge(&mut self, other: &GCounter) {
+        for (agent, &count) in other.counts.iter() {
+            let own = self.counts.get(agent).map_or(0, |&c| c);
+            self.counts.insert(agent.clone(), cmp::max(own, count));
+        }
+    }
+
+    // The count of agent alone.
+    fn of_agent(&self, agent: &ByteId) -> GCounter {
+        let mut counter = GCounter::new();
+        if let Some(&count) = self.counts.get(agent) {
+            counter.counts.insert(agent.clone(), count);
+        }
+        counter
+    }
+}
+
+// Tags an add with the agent that made it and its sequence number there.
+pub type Tag = (ByteId, u64);
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct OrSet {
+    // Tags of the adds of each element not removed yet.
+    adds: BTreeMap<Vec<u8>, BTreeSet<Tag>>,
+    // Tags of the adds that were removed.
+    removed: BTreeSet<Tag>
+}
+
+impl Default for OrSet {
+    fn default() -> OrSet {
+        OrSet::new()
+    }
+}
+
+impl OrSet {
+    pub fn new() -> OrSet {
+        OrSet {
+            adds: BTreeMap::new(),
+            removed: BTreeSet::new()
+        }
+    }
+
+    pub fn add(&mut self, element: Vec<u8>, tag: Tag) {
+        if self.removed.contains(&tag) {
+            return
+        }
+        if let Some(tags) = self.adds.get_mut(&element) {
+            tags.insert(tag);
+            return
+        }
+        let mut tags = BTreeSet::new();
+        tags.insert(tag);
+        self.adds.insert(element, tags);
+    }
+
+    // Remove the element as far as this replica has seen it added.
+    pub fn remove(&mut self, element: &[u8]) {
+        if let Some(tags) = self.adds.remove(element) {
+            for tag in tags.into_iter() {
+                self.removed.insert(tag);
+            }
+        }
+    }
+
+    pub fn contains(&self, element: &[u8]) -> bool {
+        self.adds.contains_key(element)
+    }
+
+    pub fn elements(&self) -> Vec<&[u8]> {
+        self.adds.keys().map(|e| e.as_slice()).collect()
+    }
+
+    pub fn merge(&mut self, other: &OrSet) {
+        for tag in other.removed.iter() {
+            self.removed.insert(tag.clone());
+        }
+        for (element, tags) in other.adds.iter() {
+            for tag in tags.iter() {
+                self.add(element.clone(), tag.clone());
+            }
+        }
+
+        let removed = &self.removed;
+        for tags in self.adds.values_mut() {
+            let live: BTreeSet<Tag> = tags.iter().
+                filter(|t| !removed.contains(*t)).
+                cloned().
+                collect();
+            *tags = live;
+        }
+        let gone: Vec<Vec<u8>> = self.adds.iter().
+            filter(|&(_, tags)| tags.is_empty()).
+            map(|(element, _)| element.clone()).
+            collect();
+        for element in gone.iter() {
+            self.adds.remove(element);
+        }
+    }
+
+    // The adds made by agent, and the removals of its own adds and of the
+    // adds known to the replica known.
+    fn of_agent(&self, agent: &ByteId, known: Option<&OrSet>) -> OrSet {
+        let mut set = OrSet::new();
+        for (element, tags) in self.adds.iter() {
+            for tag in tags.iter().filter(|t| t.0 == *agent) {
+                set.add(element.clone(), tag.clone());
+            }
+        }
+        set.removed = self.removed.iter().
+            filter(|t| {
+                t.0 == *agent ||
+                    known.is_some_and(|k| k.adds.values().any(|tags| tags.contains(*t)))
+            }).
+            cloned().
+            collect();
+        set
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Crdt {
+    Register(LwwRegister),
+    Counter(GCounter),
+    Set(OrSet)
+}
+
+impl Crdt {
+    // Merge other into this replica, returning whether they were of the same
+    // type. Of replicas of different types, the one of the type declared
+    // last is kept.
+    pub fn merge(&mut self, other: &Crdt) -> bool {
+        match (&mut *self, other) {
+            (&mut Crdt::Register(ref mut own), Crdt::Register(other)) => own.merge(other),
+            (&mut Crdt::Counter(ref mut own), Crdt::Counter(other)) => own.merge(other),
+            (&mut Crdt::Set(ref mut own), Crdt::Set(other)) => own.merge(other),
+            _ => {
+                if other.rank() > self.rank() {
+                    *self = other.clone();
+                }
+                return false
+            }
+        }
+        true
+    }
+
+    fn rank(&self) -> u8 {
+        match *self {
+            Crdt::Counter(_) => 0,
+            Crdt::Set(_) => 1,
+            Crdt::Register(_) => 2
+        }
+    }
+
+    // The part of a replica received from sender for the entry key that it
+    // can vouch for, see the top of the file. known is the replica it is to be
+    // merged into.
+    fn accepted(&self, key: &str, sender: &ByteId, known: Option<&Crdt>,
+                now: u64) -> Option<Crdt> {
+        match *self {
+            Crdt::Register(ref reg) => {
+                if reg.is_authentic(key) &&
+                    reg.timestamp() <= now.saturating_add(MAX_CLOCK_SKEW) {
+                    Some(self.clone())
+                } else {
+                    None
+                }
+            }
+            Crdt::Counter(ref counter) => Some(Crdt::Counter(counter.of_agent(sender))),
+            Crdt::Set(ref set) => {
+                let known = match known {
+                    Some(Crdt::Set(known)) => Some(known),
+                    _ => None
+                };
+                Some(Crdt::Set(set.of_agent(sender, known)))
+            }
+        }
+    }
+
+    // Whether a replica received for the entry key is authentic. Only
+    // registers are signed.
+    pub fn is_authentic(&self, key: &str) -> bool {
+        match *self {
+            Crdt::Register(ref reg) => reg.is_authentic(key),
+            _ => true
+        }
+    }
+}
+
+// The replica of the shared state held by one agent.
+pub struct SharedState {
+    local: ByteId,
+    // Signs the registers written here.
+    keypair: Keypair,
+    entries: BTreeMap<String, Crdt>,
+    // Entries created from the replicas of each agent, and in total.
+    created: BTreeMap<ByteId, usize>,
+    created_total: usize,
+    // Sequence number of the last add to a set by this agent. It starts from
+    // the wall clock, so that an agent that restarts does not reuse the tags
+    // of adds removed before.
+    sequence: u64
+}
+
+impl SharedState {
+    pub fn new(keypair: Keypair) -> SharedState {
+        SharedState {
+            local: keypair.id(),
+            keypair,
+            entries: BTreeMap::new(),
+            created: BTreeMap::new(),
+            created_total: 0,
+            sequence: wall_clock_ms()
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Crdt> {
+        self.entries.get(key)
+    }
+
+    pub fn register(&self, key: &str) -> Option<&[u8]> {
+        match self.entries.get(key) {
+            Some(Crdt::Register(reg)) => Some(reg.value()),
+            _ => None
+        }
+    }
+
+    pub fn counter(&self, key: &str) -> Option<u64> {
+        match self.entries.get(key) {
+            Some(Crdt::Counter(counter)) => Some(counter.value()),
+            _ => None
+        }
+    }
+
+    pub fn set(&self, key: &str) -> Option<&OrSet> {
+        match self.entries.get(key) {
+            Some(Crdt::Set(set)) => Some(set),
+            _ => None
+        }
+    }
+
+    // Write a register. The write is timestamped with the wall clock, or just
+    // after the value it replaces if that is later, so it always wins locally.
+    pub fn write(&mut self, key: &str, value: Vec<u8>) -> bool {
+        let timestamp = match self.entries.get(key) {
+            Some(Crdt::Register(reg)) => {
+                cmp::max(wall_clock_ms(), reg.timestamp().saturating_add(1))
+            }
+            Some(_) => return false,
+            None => wall_clock_ms()
+        };
+        let reg = LwwRegister::signed(key, value, timestamp, &self.keypair);
+        self.entries.insert(key.to_string(), Crdt::Register(reg));
+        true
+    }
+
+    pub fn increment(&mut self, key: &str, n: u64) -> bool {
+        if !self.entries.contains_key(key) {
+            self.entries.insert(key.to_string(), Crdt::Counter(GCounter::new()));
+        }
+        match self.entries.get_mut(key) {
+            Some(&mut Crdt::Counter(ref mut counter)) => {
+                counter.increment(&self.local, n);
+                true
+            }
+            _ => false
+        }
+    }
+
+    pub fn insert(&mut self, key: &str, element: Vec<u8>) -> bool {
+        if !self.entries.contains_key(key) {
+            self.entries.insert(key.to_string(), Crdt::Set(OrSet::new()));
+        }
+        match self.entries.get_mut(key) {
+            Some(&mut Crdt::Set(ref mut set)) => {
+                self.sequence += 1;
+                set.add(element, (self.local.clone(), self.sequence));
+                true
+            }
+            _ => false
+        }
+    }
+
+    pub fn remove(&mut self, key: &str, element: &[u8]) -> bool {
+        match self.entries.get_mut(key) {
+            Some(&mut Crdt::Set(ref mut set)) => {
+                set.remove(element);
+                true
+            }
+            _ => false
+        }
+    }
+
+    // Merge a replica of an entry received from sender, returning the merged
+    // entry, or None if the replicas were of different types or the replica
+    // was rejected.
+    pub fn merge(&mut self, key: &str, other: &Crdt, sender: &ByteId) -> Option<&Crdt> {
+        self.merge_at(key, other, sender, wall_clock_ms())
+    }
+
+    pub fn merge_at(&mut self, key: &str, other: &Crdt, sender: &ByteId,
+                    now: u64) -> Option<&Crdt> {
+        let accepted = other.accepted(key, sender, self.entries.get(key), now)?;
+        if !self.entries.contains_key(key) {
+            let created = self.created.get(sender).map_or(0, |&n| n);
+            if created >= MAX_KEYS_PER_AGENT || self.created_total >= MAX_ENTRIES {
+                return None
+            }
+            self.created.insert(sender.clone(), created + 1);
+            self.created_total += 1;
+            self.entries.insert(key.to_string(), accepted);
+        } else if !self.entries.get_mut(key).unwrap().merge(&accepted) {
+            return None
+        }
+        self.entries.get(key)
+    }
+
+    // The local replica of the entry key, if the agent that sent other would
+    // learn anything from it.
+    pub fn news_for(&self, key: &str, other: &Crdt, now: u64) -> Option<&Crdt> {
+        let own = self.entries.get(key)?;
+        let accepted = own.accepted(key, &self.local, Some(other), now)?;
+        let mut theirs = other.clone();
+        if theirs.merge(&accepted) && theirs != *other {
+            Some(own)
+        } else {
+            None
+        }
+    }
+
+    pub fn entries(&self) -> Vec<(String, Crdt)> {
+        self.entries.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use byteid::ByteId;
+    use identity::Keypair;
+    use super::{LwwRegister, GCounter, OrSet, Crdt, SharedState, MAX_CLOCK_SKEW,
+                MAX_KEYS_PER_AGENT};
+
+    #[test]
+    fn register_test() {
+        let a = ByteId::random_id();
+        let b = ByteId::random_id();
+        let mut reg = LwwRegister::new(vec![1], 10, a.clone());
+        reg.merge(&LwwRegister::new(vec![2], 5, b.clone()));
+        assert_eq!(reg.value(), [1u8].as_slice());
+        reg.merge(&LwwRegister::new(vec![3], 20, b.clone()));
+        assert_eq!(reg.value(), [3u8].as_slice());
+
+        // Concurrent writes are decided the same way at every replica.
+        let mut x = LwwRegister::new(vec![4], 30, a.clone());
+        let mut y = LwwRegister::new(vec![5], 30, b.clone());
+        let x_orig = x.clone();
+        x.merge(&y);
+        y.merge(&x_orig);
+        assert_eq!(x, y);
+    }
+
+    #[test]
+    fn counter_test() {
+        let a = ByteId::random_id();
+        let b = ByteId::random_id();
+        let mut x = GCounter::new();
+        let mut y = GCounter::new();
+        x.increment(&a, 2);
+        y.increment(&b, 3);
+        y.increment(&a, 1);
+
+        x.merge(&y);
+        assert_eq!(x.value(), 5);
+        // Merging again changes nothing.
+        x.merge(&y);
+        y.merge(&x);
+        assert_eq!(x, y);
+    }
+
+    #[test]
+    fn set_test() {
+        let a = ByteId::random_id();
+        let b = ByteId::random_id();
+        let mut x = OrSet::new();
+        x.add(b"hazard".to_vec(), (a.clone(), 1));
+        let mut y = x.clone();
+
+        // y removes the element while x adds it again, the add wins.
+        y.remove(b"hazard");
+        x.add(b"hazard".to_vec(), (b.clone(), 1));
+        let x_orig = x.clone();
+        x.merge(&y);
+        y.merge(&x_orig);
+        assert!(x.contains(b"hazard"));
+        assert_eq!(x, y);
+
+        x.remove(b"hazard");
+        y.merge(&x);
+        assert!(!y.contains(b"hazard"));
+        assert!(y.elements().is_empty());
+    }
+
+    #[test]
+    fn shared_state_test() {
+        let (x_keypair, y_keypair) = (Keypair::generate(), Keypair::generate());
+        let (x_id, y_id) = (x_keypair.id(), y_keypair.id());
+        let mut x = SharedState::new(x_keypair.clone());
+        let mut y = SharedState::new(y_keypair);
+        assert!(x.write("mode", b"explore".to_vec()));
+        assert!(x.increment("visits", 2));
+        assert!(y.increment("visits", 1));
+        assert!(y.insert("hazards", b"7,3".to_vec()));
+        // Entries keep their type.
+        assert!(!y.write("visits", Vec::new()));
+        assert!(!x.insert("mode", Vec::new()));
+
+        for (key, entry) in x.entries().into_iter() {
+            y.merge(key.as_str(), &entry, &x_id);
+        }
+        for (key, entry) in y.entries().into_iter() {
+            x.merge(key.as_str(), &entry, &y_id);
+        }
+
+        for state in [&x, &y].iter() {
+            assert_eq!(state.register("mode"), Some(b"explore".as_slice()));
+            assert_eq!(state.counter("visits"), Some(3));
+            assert!(state.set("hazards").unwrap().contains(b"7,3"));
+        }
+
+        // Of replicas of different types, the same one wins everywhere, and
+        // only a signed one replaces a register.
+        assert!(x.merge("mode", &Crdt::Counter(GCounter::new()), &y_id).is_none());
+        assert_eq!(x.register("mode"), Some(b"explore".as_slice()));
+        let register = Crdt::Register(
+            LwwRegister::signed("visits", b"fast".to_vec(), 10, &x_keypair));
+        assert!(y.merge("visits", &register, &x_id).is_none());
+        assert_eq!(y.register("visits"), Some(b"fast".as_slice()));
+        assert!(y.merge("hazards", &Crdt::Counter(GCounter::new()), &x_id).is_none());
+        assert!(y.set("hazards").unwrap().contains(b"7,3"));
+    }
+
+    #[test]
+    fn untrusted_merge_test() {
+        let writer = Keypair::generate();
+        let a = ByteId::random_id();
+        let b = ByteId::random_id();
+        let mut state = SharedState::new(Keypair::generate());
+
+        // Unsigned writes neither create nor replace a register.
+        let unsigned = Crdt::Register(LwwRegister::new(b"halt".to_vec(), 10, a.clone()));
+        assert!(state.merge_at("mode", &unsigned, &a, 1000).is_none());
+        assert!(state.get("mode").is_none());
+        let signed = Crdt::Register(LwwRegister::signed("mode", b"explore".to_vec(), 10, &writer));
+        assert!(state.merge_at("mode", &signed, &a, 1000).is_some());
+        let unsigned = Crdt::Register(LwwRegister::new(b"halt".to_vec(), 20, writer.id()));
+        assert!(state.merge_at("mode", &unsigned, &a, 1000).is_none());
+        assert_eq!(state.register("mode"), Some(b"explore".as_slice()));
+
+        // Nor do writes from far in the future.
+        let ahead = Crdt::Register(LwwRegister::signed(
+            "mode", b"halt".to_vec(), 1000 + MAX_CLOCK_SKEW + 1, &writer));
+        assert!(state.merge_at("mode", &ahead, &a, 1000).is_none());
+        assert_eq!(state.register("mode"), Some(b"explore".as_slice()));
+        assert!(state.merge_at("mode", &ahead, &a, 2000).is_some());
+        assert_eq!(state.register("mode"), Some(b"halt".as_slice()));
+
+        // Only the sender's own counts and adds are taken.
+        let mut counter = GCounter::new();
+        counter.increment(&a, 1);
+        counter.increment(&b, 100);
+        state.merge_at("visits", &Crdt::Counter(counter), &a, 1000);
+        assert_eq!(state.counter("visits"), Some(1));
+
+        let mut set = OrSet::new();
+        set.add(b"7,3".to_vec(), (a.clone(), 1));
+        set.add(b"8,3".to_vec(), (b.clone(), 1));
+        state.merge_at("hazards", &Crdt::Set(set.clone()), &a, 1000);
+        assert_eq!(state.set("hazards").unwrap().elements(), vec![b"7,3".as_slice()]);
+
+        // Removals are taken for the adds known here only, so that nobody
+        // can remove the adds of others before they are made.
+        set.remove(b"7,3");
+        set.remove(b"8,3");
+        state.merge_at("hazards", &Crdt::Set(set.clone()), &a, 1000);
+        assert!(state.set("hazards").unwrap().elements().is_empty());
+        let mut late = OrSet::new();
+        late.add(b"8,3".to_vec(), (b.clone(), 1));
+        state.merge_at("hazards", &Crdt::Set(late), &b, 1000);
+        assert!(state.set("hazards").unwrap().contains(b"8,3"));
+    }
+
+    #[test]
+    fn max_keys_test() {
+        let a = ByteId::random_id();
+        let b = ByteId::random_id();
+        let mut state = SharedState::new(Keypair::generate());
+        let counter = Crdt::Counter(GCounter::new());
+        for i in 0..MAX_KEYS_PER_AGENT {
+            assert!(state.merge(format!("key{}", i).as_str(), &counter, &a).is_some());
+        }
+        assert!(state.merge("one more", &counter, &a).is_none());
+        assert!(state.get("one more").is_none());
+        // Entries already known are still merged, and others still create.
+        assert!(state.merge("key0", &counter, &a).is_some());
+        assert!(state.merge("one more", &counter, &b).is_some());
+        // Local updates are not limited.
+        assert!(state.increment("local", 1));
+    }
+
+    #[test]
+    fn news_test() {
+        let mut x = SharedState::new(Keypair::generate());
+        let mut y = SharedState::new(Keypair::generate());
+        let (x_id, y_id) = (x.local.clone(), y.local.clone());
+        let mut counter = GCounter::new();
+        counter.increment(&ByteId::random_id(), 5);
+        y.merge_at("visits", &Crdt::Counter(counter), &y_id, 0);
+        assert!(x.increment("visits", 1));
+        assert!(y.increment("visits", 2));
+
+        // x pushes to y, which answers as long as x would take anything,
+        // although x never takes the counts y has from others.
+        let entry = x.get("visits").unwrap().clone();
+        y.merge_at("visits", &entry, &x_id, 0);
+        let answer = y.news_for("visits", &entry, 0).unwrap().clone();
+        x.merge_at("visits", &answer, &y_id, 0);
+        assert_eq!(x.counter("visits"), Some(3));
+        assert!(x.news_for("visits", &answer, 0).is_none());
+        assert!(y.news_for("visits", x.get("visits").unwrap(), 0).is_none());
+    }
+
+    #[test]
+    fn signed_register_test() {
+        let keypair = Keypair::generate();
+        let reg = LwwRegister::signed("mode", b"explore".to_vec(), 10, &keypair);
+        assert!(reg.is_authentic("mode"));
+        // Signed for another entry.
+        assert!(!reg.is_authentic("speed"));
+
+        let mut forged = reg.clone();
+        forged.timestamp = u64::MAX;
+        assert!(!forged.is_authentic("mode"));
+        let unsigned = LwwRegister::new(b"explore".to_vec(), 10, keypair.id());
+        assert!(!unsigned.is_authentic("mode"));
+        assert!(!Crdt::Register(unsigned).is_authentic("mode"));
+        assert!(Crdt::Counter(GCounter::new()).is_authentic("mode"));
+
+        // Writes keep going past the latest timestamp.
+        let mut state = SharedState::new(keypair);
+        let writer = Keypair::generate();
+        state.merge_at("mode", &Crdt::Register(
+            LwwRegister::signed("mode", Vec::new(), u64::MAX, &writer)), &writer.id(), u64::MAX);
+        assert!(state.write("mode", b"explore".to_vec()));
+        assert_eq!(state.register("mode"), Some(b"explore".as_slice()));
+    }
+}
@@ -0,0 +1,321 @@
+// Leader Election
+//
+// An agent taking part in elections considers every agent within radius of its
+// own location a candidate, and follows the candidate with the lowest id,
+// which may be itself. Agents tell their neighbors about themselves and about
+// the leader they follow with LEADER, so candidates further away than a
+// neighbor are learned of as well. Every agent signs the wall clock time of
+// its own announcements, and agents relay the latest announcement of their
+// leader rather than vouching for it themselves, so a candidate is never
+// heard of more recently than it last announced itself, however many agents
+// relay it. Candidates that have not been heard of within the timeout, or have
+// moved out of the radius, are dropped, and the next lowest id takes over.
+//
+// Each agent elects the leader of the region around itself, so agents near the
+// edge of a region may follow a different leader than agents in its middle.
+//
+// Ids are hashes of a public key and a nonce, and a lower id is one with more
+// leading zero bits, i.e. more proof-of-work. Anyone willing to spend more
+// work on an id than the candidates of a region did can become its leader;
+// elections only keep agents from posing as other agents or keeping a leader
+// alive that is gone. SwarmNetwork::set_required_work sets the least work a
+// candidate needs. At most MAX_CANDIDATES are tracked, the lowest ids first.
+use agent::SwarmAgent;
+use byteid::ByteId;
+use codec::{self, Codec, Bincode};
+use identity::{self, Keypair};
+use serde::Serialize;
+use std::collections::HashMap;
+use Location;
+
+pub const MAX_CANDIDATES: usize = 64;
+
+// An agent along with its signature of the time it announced itself at.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Announcement<Loc> {
+    agent: SwarmAgent<Loc>,
+    // Wall clock milliseconds.
+    timestamp: u64,
+    #[serde(with = "codec::bytes")]
+    signature: Vec<u8>
+}
+
+impl<Loc> Announcement<Loc> {
+    pub fn agent(&self) -> &SwarmAgent<Loc> {
+        &self.agent
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+}
+
+impl<Loc: Serialize> Announcement<Loc> {
+    pub fn new(agent: SwarmAgent<Loc>, keypair: &Keypair,
+               timestamp: u64) -> Announcement<Loc> {
+        let mut announcement = Announcement { agent, timestamp, signature: Vec::new() };
+        announcement.signature = keypair.sign(announcement.signed_data().as_slice());
+        announcement
+    }
+
+    // Whether the announcement was signed by the agent it announces.
+    pub fn verify(&self) -> bool {
+        self.agent.is_authentic() &&
+            identity::verify(self.agent.public_key(),
+                             self.signed_data().as_slice(),
+                             self.signature.as_slice())
+    }
+
+    fn signed_data(&self) -> Vec<u8> {
+        Bincode.encode(&("LEADER", self.agent.id(), self.timestamp)).
+            unwrap_or_default()
+    }
+}
+
+pub struct Election<Loc> {
+    radius: usize,
+    timeout_ms: u64,
+    // The latest announcement of every candidate, and when it was made by
+    // the local clock.
+    candidates: HashMap<ByteId, (Announcement<Loc>, u64)>,
+    leader: Option<SwarmAgent<Loc>>,
+    // Whether the leader changed since take_change was last called.
+    changed: bool
+}
+
+impl<Loc: Location + Clone> Election<Loc> {
+    pub fn new(radius: usize, timeout_ms: u64) -> Election<Loc> {
+        Election {
+            radius,
+            timeout_ms,
+            candidates: HashMap::new(),
+            leader: None,
+            changed: false
+        }
+    }
+
+    // Record an announcement made at the given time. An older announcement
+    // than the one known does not replace it. Once MAX_CANDIDATES are known,
+    // a new candidate replaces the one with the highest id, if its own id is
+    // lower.
+    pub fn observe_at(&mut self, local: &SwarmAgent<Loc>,
+                      announcement: Announcement<Loc>, heard: u64) {
+        let id = announcement.agent().id().clone();
+        if id == *local.id() {
+            return
+        }
+
+        if announcement.agent().location().distance(local.location()) > self.radius {
+            self.candidates.remove(&id);
+            return
+        }
+
+        match self.candidates.get(&id) {
+            Some(&(_, last)) if last > heard => return,
+            Some(_) => {}
+            None if self.candidates.len() >= MAX_CANDIDATES => {
+                let highest = match self.candidates.keys().max() {
+                    Some(highest) if *highest > id => highest.clone(),
+                    _ => return
+                };
+                self.candidates.remove(&highest);
+            }
+            None => {}
+        }
+        self.candidates.insert(id, (announcement, heard));
+    }
+
+    // When the candidate with the given id was last heard of.
+    #[cfg(test)]
+    pub fn heard(&self, id: &ByteId) -> Option<u64> {
+        self.candidates.get(id).map(|&(_, heard)| heard)
+    }
+
+    // The latest announcement of the candidate with the given id, to be
+    // relayed.
+    pub fn announcement(&self, id: &ByteId) -> Option<&Announcement<Loc>> {
+        self.candidates.get(id).map(|(announcement, _)| announcement)
+    }
+
+    // Drop the candidates that timed out or are out of range, and follow the
+    // lowest id left. Returns whether the leader changed.
+    pub fn update_at(&mut self, local: &SwarmAgent<Loc>, now: u64) -> bool {
+        let radius = self.radius;
+        let timeout = self.timeout_ms;
+        self.candidates.retain(|_, &mut (ref announcement, heard)| {
+            now.saturating_sub(heard) < timeout &&
+                announcement.agent().location().distance(local.location()) <= radius
+        });
+
+        let leader = {
+            let mut leader = local;
+            for (announcement, _) in self.candidates.values() {
+                if announcement.agent().id() < leader.id() {
+                    leader = announcement.agent();
+                }
+            }
+            leader.clone()
+        };
+
+        let changed = self.leader.as_ref().is_none_or(|l| l.id() != leader.id());
+        self.leader = Some(leader);
+        self.changed = self.changed || changed;
+        changed
+    }
+
+    pub fn leader(&self) -> Option<&SwarmAgent<Loc>> {
+        self.leader.as_ref()
+    }
+
+    pub fn is_leader(&self, local: &SwarmAgent<Loc>) -> bool {
+        self.leader.as_ref().is_some_and(|l| l.id() == local.id())
+    }
+
+    // The new leader, if it changed since the last call.
+    pub fn take_change(&mut self) -> Option<SwarmAgent<Loc>> {
+        if self.changed {
+            self.changed = false;
+            self.leader.clone()
+        } else {
+            None
+        }
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+    use agent::SwarmAgent;
+    use identity::Keypair;
+    use super::{Announcement, Election, MAX_CANDIDATES};
+
+    fn announce(loc: isize) -> Announcement<isize> {
+        let keypair = Keypair::generate();
+        let agent = SwarmAgent::with_keypair(loc, SocketAddr::from(([127, 0, 0, 1], 7000)),
+                                             &keypair);
+        Announcement::new(agent, &keypair, 0)
+    }
+
+    // Announcements of agents at the given locations, ordered by id.
+    fn candidates(locs: &[isize]) -> Vec<Announcement<isize>> {
+        let mut candidates: Vec<Announcement<isize>> = locs.iter().map(|&l| announce(l)).collect();
+        candidates.sort_by(|a, b| a.agent().id().cmp(b.agent().id()));
+        candidates
+    }
+
+    #[test]
+    fn announcement_test() {
+        let announcement = announce(0);
+        assert!(announcement.verify());
+
+        // The time can not be changed by anyone relaying it.
+        let mut relayed = announcement.clone();
+        relayed.timestamp += 1000;
+        assert!(!relayed.verify());
+
+        // Nor can an agent sign for another.
+        let keypair = Keypair::generate();
+        let other = Announcement::new(announcement.agent().clone(), &keypair, 0);
+        assert!(!other.verify());
+    }
+
+    #[test]
+    fn lowest_id_test() {
+        let candidates = candidates(&[0, 1, 2]);
+        let local = candidates[2].agent();
+        let mut election = Election::new(5, 1000);
+        assert!(election.leader().is_none());
+
+        // On its own, an agent leads itself.
+        assert!(election.update_at(local, 0));
+        assert!(election.is_leader(local));
+
+        election.observe_at(local, candidates[1].clone(), 0);
+        election.observe_at(local, candidates[0].clone(), 0);
+        assert!(election.update_at(local, 0));
+        assert_eq!(election.leader().unwrap().id(), candidates[0].agent().id());
+        assert!(!election.update_at(local, 10));
+    }
+
+    #[test]
+    fn radius_test() {
+        let local = announce(2);
+        let mut election = Election::new(5, 1000);
+
+        election.observe_at(local.agent(), announce(100), 0);
+        election.update_at(local.agent(), 0);
+        assert!(election.is_leader(local.agent()));
+        assert_eq!(election.len(), 0);
+    }
+
+    #[test]
+    fn timeout_test() {
+        let candidates = candidates(&[0, 1, 2]);
+        let local = candidates[2].agent();
+        let mut election = Election::new(5, 1000);
+        election.observe_at(local, candidates[0].clone(), 0);
+        election.observe_at(local, candidates[1].clone(), 500);
+        election.update_at(local, 500);
+        assert_eq!(election.leader().unwrap().id(), candidates[0].agent().id());
+
+        // The leader times out, the next lowest id takes over.
+        assert!(election.update_at(local, 1000));
+        assert_eq!(election.leader().unwrap().id(), candidates[1].agent().id());
+    }
+
+    #[test]
+    fn heard_test() {
+        let candidates = candidates(&[0, 1]);
+        let local = candidates[1].agent();
+        let id = candidates[0].agent().id();
+        let mut election = Election::new(5, 1000);
+        election.observe_at(local, candidates[0].clone(), 500);
+        assert_eq!(election.heard(id), Some(500));
+        assert_eq!(election.announcement(id), Some(&candidates[0]));
+
+        // An older announcement does not make the candidate older.
+        election.observe_at(local, candidates[0].clone(), 200);
+        assert_eq!(election.heard(id), Some(500));
+        assert_eq!(election.heard(local.id()), None);
+    }
+
+    #[test]
+    fn max_candidates_test() {
+        let local = announce(0);
+        let mut all = candidates(vec![0isize; MAX_CANDIDATES + 2].as_slice());
+        let lowest = all.remove(0);
+        let highest = all.pop().unwrap();
+        let mut election = Election::new(5, 1000);
+        for candidate in all.iter() {
+            election.observe_at(local.agent(), candidate.clone(), 0);
+        }
+        assert_eq!(election.len(), MAX_CANDIDATES);
+
+        // A higher id than every candidate is not taken, a lower one is, in
+        // place of the highest.
+        election.observe_at(local.agent(), highest.clone(), 0);
+        assert!(election.heard(highest.agent().id()).is_none());
+        election.observe_at(local.agent(), lowest.clone(), 0);
+        assert_eq!(election.len(), MAX_CANDIDATES);
+        assert!(election.heard(lowest.agent().id()).is_some());
+        assert!(election.heard(all[MAX_CANDIDATES - 1].agent().id()).is_none());
+    }
+
+    #[test]
+    fn take_change_test() {
+        let candidates = candidates(&[0, 1]);
+        let local = candidates[1].agent();
+        let mut election = Election::new(5, 1000);
+        election.update_at(local, 0);
+        election.observe_at(local, candidates[0].clone(), 0);
+        election.update_at(local, 0);
+
+        assert_eq!(election.take_change().unwrap().id(), candidates[0].agent().id());
+        assert!(election.take_change().is_none());
+    }
+}
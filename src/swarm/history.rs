@@ -0,0 +1,126 @@
+// Broadcast History
+//
+// A bounded log of the most recent broadcasts an agent has sent or received,
+// by id. It suppresses duplicates, and lets agents repair the broadcasts they
+// missed while offline or to a lost datagram: every so often an agent sends a
+// neighbor a DIGEST of the ids of its latest broadcasts, and the neighbor
+// PULLs the ones it has not seen, which are sent again as BROADCASTs. The ids
+// of broadcasts dropped from the history are forgotten, so a broadcast older
+// than the history of every agent that saw it is lost for good.
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::vec::Vec;
+
+pub type BroadcastId = u64;
+
+// Broadcasts are told apart by their signature. The id is taken from a hash
+// of the whole signature, so that signatures alike in part do not collide.
+pub fn broadcast_id(signature: &[u8]) -> BroadcastId {
+    let hash = Sha256::digest(signature);
+    hash.iter().take(8).fold(0u64, |id, &b| (id << 8) | b as u64)
+}
+
+pub struct BroadcastHistory<M> {
+    capacity: usize,
+    // Ids of the broadcasts held, oldest first.
+    order: VecDeque<BroadcastId>,
+    // Broadcasts held and whether they are offered in digests.
+    messages: HashMap<BroadcastId, (M, bool)>
+}
+
+impl<M> BroadcastHistory<M> {
+    pub fn new(capacity: usize) -> BroadcastHistory<M> {
+        BroadcastHistory {
+            capacity,
+            order: VecDeque::new(),
+            messages: HashMap::new()
+        }
+    }
+
+    // Record a broadcast, returning false if it was seen before. Broadcasts
+    // meant for a single agent are kept out of digests.
+    pub fn insert(&mut self, id: BroadcastId, msg: M, gossip: bool) -> bool {
+        if self.messages.contains_key(&id) {
+            return false
+        }
+
+        if self.order.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.messages.remove(&oldest);
+            }
+        }
+        self.order.push_back(id);
+        self.messages.insert(id, (msg, gossip));
+        true
+    }
+
+    pub fn contains(&self, id: BroadcastId) -> bool {
+        self.messages.contains_key(&id)
+    }
+
+    pub fn get(&self, id: BroadcastId) -> Option<&M> {
+        self.messages.get(&id).map(|(msg, _)| msg)
+    }
+
+    // Ids of up to max of the latest broadcasts offered to neighbors, newest
+    // first.
+    pub fn digest(&self, max: usize) -> Vec<BroadcastId> {
+        let messages = &self.messages;
+        self.order.iter().rev().
+            filter(|id| messages.get(*id).is_some_and(|&(_, gossip)| gossip)).
+            take(max).
+            cloned().
+            collect()
+    }
+
+    // The ids of a neighbor's digest that have not been seen here.
+    pub fn missing(&self, digest: &[BroadcastId]) -> Vec<BroadcastId> {
+        digest.iter().filter(|&&id| !self.contains(id)).cloned().collect()
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BroadcastHistory, broadcast_id};
+
+    #[test]
+    fn broadcast_id_test() {
+        assert_eq!(broadcast_id(&[1, 2]), broadcast_id(&[1, 2]));
+        assert!(broadcast_id(&[1, 2]) != broadcast_id(&[2, 1]));
+        // Every byte of the signature counts.
+        assert!(broadcast_id(&[0, 0, 0, 0, 0, 0, 0, 0, 1]) !=
+                broadcast_id(&[0, 0, 0, 0, 0, 0, 0, 0, 2]));
+    }
+
+    #[test]
+    fn insert_test() {
+        let mut history = BroadcastHistory::new(2);
+        assert!(history.insert(1, "a", true));
+        assert!(!history.insert(1, "a", true));
+        assert!(history.insert(2, "b", true));
+        assert!(history.insert(3, "c", true));
+
+        // The oldest broadcast is dropped.
+        assert_eq!(history.len(), 2);
+        assert!(!history.contains(1));
+        assert_eq!(history.get(3), Some(&"c"));
+    }
+
+    #[test]
+    fn digest_test() {
+        let mut history = BroadcastHistory::new(10);
+        for id in 0u64..5 {
+            history.insert(id, (), id != 3);
+        }
+        assert_eq!(history.digest(3), vec![4, 2, 1]);
+
+        let mut other = BroadcastHistory::new(10);
+        other.insert(2, (), true);
+        assert_eq!(other.missing(history.digest(3).as_slice()), vec![4, 1]);
+    }
+}
@@ -0,0 +1,384 @@
+// Network Metrics
+//
+// A SwarmNetwork counts the packets it sends and receives by RPC, the packets
+// it fails to decode or has to drop, and the changes to its neighbor list, and
+// keeps histograms of the number of hops INFO messages travel and of the time
+// it takes to join the swarm. The metrics can be read through the API, or
+// served in the Prometheus text format over HTTP on localhost by a
+// MetricsEndpoint.
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::vec::Vec;
+
+const INFO_HOP_BOUNDS: [f64; 6] = [1.0, 2.0, 4.0, 8.0, 16.0, 32.0];
+const JOIN_LATENCY_BOUNDS: [f64; 7] = [10.0, 50.0, 100.0, 500.0, 1000.0, 5000.0, 10000.0];
+const ENDPOINT_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    // Upper bounds of the buckets, in increasing order.
+    bounds: Vec<f64>,
+    // Number of observations per bucket, the last bucket has no upper bound.
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64
+}
+
+impl Histogram {
+    pub fn new(bounds: &[f64]) -> Histogram {
+        Histogram {
+            bounds: bounds.to_vec(),
+            counts: vec![0; bounds.len() + 1],
+            sum: 0.0,
+            count: 0
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        let bucket = self.bounds.
+            iter().
+            position(|&b| value <= b).
+            unwrap_or(self.bounds.len());
+        self.counts[bucket] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    // Number of observations less than or equal to each bound.
+    pub fn cumulative_counts(&self) -> Vec<(f64, u64)> {
+        let mut total = 0;
+        self.bounds.
+            iter().
+            zip(self.counts.iter()).
+            map(|(&bound, &count)| {
+                total += count;
+                (bound, total)
+            }).collect()
+    }
+
+    fn render(&self, name: &str, help: &str, out: &mut String) {
+        out.push_str(format!("# HELP {} {}\n", name, help).as_str());
+        out.push_str(format!("# TYPE {} histogram\n", name).as_str());
+        for (bound, count) in self.cumulative_counts().into_iter() {
+            out.push_str(format!("{}_bucket{{le=\"{}\"}} {}\n", name, bound, count).as_str());
+        }
+        out.push_str(format!("{}_bucket{{le=\"+Inf\"}} {}\n", name, self.count).as_str());
+        out.push_str(format!("{}_sum {}\n", name, self.sum).as_str());
+        out.push_str(format!("{}_count {}\n", name, self.count).as_str());
+    }
+}
+
+fn render_counter(name: &str, help: &str, value: u64, out: &mut String) {
+    out.push_str(format!("# HELP {} {}\n", name, help).as_str());
+    out.push_str(format!("# TYPE {} counter\n", name).as_str());
+    out.push_str(format!("{} {}\n", name, value).as_str());
+}
+
+fn render_by_rpc(name: &str, help: &str, values: &BTreeMap<String, u64>, out: &mut String) {
+    out.push_str(format!("# HELP {} {}\n", name, help).as_str());
+    out.push_str(format!("# TYPE {} counter\n", name).as_str());
+    for (rpc, value) in values.iter() {
+        out.push_str(format!("{}{{rpc=\"{}\"}} {}\n", name, rpc, value).as_str());
+    }
+}
+
+fn increment(values: &mut BTreeMap<String, u64>, rpc: &str) {
+    let count = values.get(rpc).copied().unwrap_or(0);
+    values.insert(rpc.to_string(), count + 1);
+}
+
+#[derive(Clone, Debug)]
+pub struct Metrics {
+    sent: BTreeMap<String, u64>,
+    received: BTreeMap<String, u64>,
+    decode_failures: u64,
+    oversized_packets: u64,
+    send_failures: u64,
+    broadcast_duplicates: u64,
+    neighbors_added: u64,
+    neighbors_removed: u64,
+    info_hops: Histogram,
+    // Milliseconds from sending a JOIN to gaining the first neighbor.
+    join_latency: Histogram
+}
+
+impl Default for Metrics {
+    fn default() -> Metrics {
+        Metrics::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            sent: BTreeMap::new(),
+            received: BTreeMap::new(),
+            decode_failures: 0,
+            oversized_packets: 0,
+            send_failures: 0,
+            broadcast_duplicates: 0,
+            neighbors_added: 0,
+            neighbors_removed: 0,
+            info_hops: Histogram::new(&INFO_HOP_BOUNDS),
+            join_latency: Histogram::new(&JOIN_LATENCY_BOUNDS)
+        }
+    }
+
+    pub fn packet_sent(&mut self, rpc: &str) {
+        increment(&mut self.sent, rpc);
+    }
+
+    pub fn packet_received(&mut self, rpc: &str) {
+        increment(&mut self.received, rpc);
+    }
+
+    pub fn decode_failure(&mut self) {
+        self.decode_failures += 1;
+    }
+
+    pub fn oversized_packet(&mut self) {
+        self.oversized_packets += 1;
+    }
+
+    pub fn send_failure(&mut self) {
+        self.send_failures += 1;
+    }
+
+    pub fn broadcast_duplicate(&mut self) {
+        self.broadcast_duplicates += 1;
+    }
+
+    pub fn neighbors_added(&mut self, count: usize) {
+        self.neighbors_added += count as u64;
+    }
+
+    pub fn neighbors_removed(&mut self, count: usize) {
+        self.neighbors_removed += count as u64;
+    }
+
+    pub fn info_delivered(&mut self, hops: u8) {
+        self.info_hops.observe(hops as f64);
+    }
+
+    pub fn joined(&mut self, latency_ms: u64) {
+        self.join_latency.observe(latency_ms as f64);
+    }
+
+    pub fn sent(&self, rpc: &str) -> u64 {
+        self.sent.get(rpc).copied().unwrap_or(0)
+    }
+
+    pub fn received(&self, rpc: &str) -> u64 {
+        self.received.get(rpc).copied().unwrap_or(0)
+    }
+
+    pub fn decode_failures(&self) -> u64 {
+        self.decode_failures
+    }
+
+    pub fn oversized_packets(&self) -> u64 {
+        self.oversized_packets
+    }
+
+    pub fn send_failures(&self) -> u64 {
+        self.send_failures
+    }
+
+    pub fn broadcast_duplicates(&self) -> u64 {
+        self.broadcast_duplicates
+    }
+
+    // Neighbors gained and lost.
+    pub fn neighbor_churn(&self) -> (u64, u64) {
+        (self.neighbors_added, self.neighbors_removed)
+    }
+
+    pub fn info_hops(&self) -> &Histogram {
+        &self.info_hops
+    }
+
+    pub fn join_latency(&self) -> &Histogram {
+        &self.join_latency
+    }
+
+    // Render the metrics in the Prometheus text exposition format. The number
+    // of packets rejected by the socket is passed in, as the socket keeps its
+    // own count.
+    pub fn to_prometheus(&self, rejected_packets: u64) -> String {
+        let mut out = String::new();
+        render_by_rpc("swarm_packets_sent_total", "Packets sent by RPC.",
+                      &self.sent, &mut out);
+        render_by_rpc("swarm_packets_received_total", "Packets received by RPC.",
+                      &self.received, &mut out);
+        render_counter("swarm_decode_failures_total",
+                       "Packets that failed to decrypt, decode or verify.",
+                       self.decode_failures, &mut out);
+        render_counter("swarm_rejected_packets_total",
+                       "Packets of another swarm or protocol version.",
+                       rejected_packets, &mut out);
+        render_counter("swarm_oversized_packets_total",
+                       "Packets not sent because they exceed the maximum size.",
+                       self.oversized_packets, &mut out);
+        render_counter("swarm_send_failures_total",
+                       "Packets the socket failed to send.",
+                       self.send_failures, &mut out);
+        render_counter("swarm_broadcast_duplicates_total",
+                       "Duplicate broadcasts suppressed.",
+                       self.broadcast_duplicates, &mut out);
+        render_counter("swarm_neighbors_added_total", "Neighbors gained.",
+                       self.neighbors_added, &mut out);
+        render_counter("swarm_neighbors_removed_total", "Neighbors lost.",
+                       self.neighbors_removed, &mut out);
+        self.info_hops.render("swarm_info_hops",
+                              "Hops travelled by INFO messages delivered here.",
+                              &mut out);
+        self.join_latency.render("swarm_join_latency_ms",
+                                 "Milliseconds from JOIN to the first neighbor.",
+                                 &mut out);
+        out
+    }
+}
+
+// Serves metrics to Prometheus over HTTP. Scrapes are not authenticated, so
+// the endpoint only binds to loopback addresses.
+pub struct MetricsEndpoint {
+    listener: TcpListener,
+    address: SocketAddr
+}
+
+impl MetricsEndpoint {
+    pub fn bind<A: ToSocketAddrs>(address: A) -> io::Result<MetricsEndpoint> {
+        let addrs: Vec<SocketAddr> = address.to_socket_addrs()?.collect();
+        if let Some(addr) = addrs.iter().find(|a| !a.ip().is_loopback()) {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied,
+                                      format!("Metrics are only served on localhost, not {}",
+                                              addr)))
+        }
+        let listener = TcpListener::bind(addrs.as_slice())?;
+        let address = listener.local_addr()?;
+        listener.set_nonblocking(true)?;
+        Ok(MetricsEndpoint { listener, address })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    // Answer the next scrape with body, if one arrives within a short timeout.
+    // Every request is answered with the metrics, whatever its path.
+    pub fn serve(&mut self, body: &str) -> io::Result<()> {
+        let start = Instant::now();
+        let mut stream = loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => break stream,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock &&
+                    start.elapsed() < ENDPOINT_TIMEOUT => {
+                    thread::sleep(Duration::from_millis(5));
+                }
+                Err(e) => return Err(e)
+            }
+        };
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(ENDPOINT_TIMEOUT))?;
+        stream.set_write_timeout(Some(ENDPOINT_TIMEOUT))?;
+
+        let mut request = [0u8; 1024];
+        let _ = stream.read(&mut request)?;
+        stream.write_all(b"HTTP/1.0 200 OK\r\n")?;
+        stream.write_all(b"Content-Type: text/plain; version=0.0.4\r\n")?;
+        stream.write_all(format!("Content-Length: {}\r\n\r\n", body.len()).as_bytes())?;
+        stream.write_all(body.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use std::thread;
+    use super::{Histogram, Metrics, MetricsEndpoint};
+
+    #[test]
+    fn histogram_test() {
+        let mut hist = Histogram::new(&[1.0, 5.0]);
+        hist.observe(0.5);
+        hist.observe(1.0);
+        hist.observe(3.0);
+        hist.observe(100.0);
+
+        assert_eq!(hist.count(), 4);
+        assert_eq!(hist.sum(), 104.5);
+        assert_eq!(hist.cumulative_counts(), vec![(1.0, 2), (5.0, 3)]);
+    }
+
+    #[test]
+    fn counters_test() {
+        let mut metrics = Metrics::new();
+        metrics.packet_sent("JOIN");
+        metrics.packet_sent("JOIN");
+        metrics.packet_received("HRTBT");
+        metrics.neighbors_added(2);
+        metrics.neighbors_removed(1);
+        metrics.send_failure();
+
+        assert_eq!(metrics.sent("JOIN"), 2);
+        assert_eq!(metrics.sent("HRTBT"), 0);
+        assert_eq!(metrics.received("HRTBT"), 1);
+        assert_eq!(metrics.neighbor_churn(), (2, 1));
+        assert_eq!(metrics.send_failures(), 1);
+    }
+
+    #[test]
+    fn prometheus_test() {
+        let mut metrics = Metrics::new();
+        metrics.packet_sent("INFO");
+        metrics.info_delivered(3);
+        let text = metrics.to_prometheus(4);
+
+        assert!(text.contains("swarm_packets_sent_total{rpc=\"INFO\"} 1\n"));
+        assert!(text.contains("swarm_rejected_packets_total 4\n"));
+        assert!(text.contains("swarm_info_hops_bucket{le=\"2\"} 0\n"));
+        assert!(text.contains("swarm_info_hops_bucket{le=\"4\"} 1\n"));
+        assert!(text.contains("swarm_info_hops_count 1\n"));
+    }
+
+    #[test]
+    fn endpoint_test() {
+        let local = SocketAddr::from(([127, 0, 0, 1], 0));
+        let mut endpoint = MetricsEndpoint::bind(local).ok().unwrap();
+        let addr = endpoint.address();
+
+        let scraper = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).ok().unwrap();
+            stream.write_all(b"GET /metrics HTTP/1.0\r\n\r\n").ok().unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).ok().unwrap();
+            response
+        });
+
+        let mut served = false;
+        for _ in 0usize..20 {
+            if endpoint.serve("swarm_decode_failures_total 0\n").is_ok() {
+                served = true;
+                break
+            }
+        }
+        assert!(served);
+        let response = scraper.join().unwrap();
+        assert!(response.starts_with("HTTP/1.0 200 OK"));
+        assert!(response.ends_with("swarm_decode_failures_total 0\n"));
+
+        assert!(MetricsEndpoint::bind(SocketAddr::from(([0, 0, 0, 0], 0))).is_err());
+    }
+}
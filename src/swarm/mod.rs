@@ -13,189 +13,389 @@
 // - JOIN
 // - INFO
 // - BROADCAST
+// - PUT
+// - STORE
+// - GET
+// - ARTIFACTS
+// - GET_NEIGHBORS
+// - NEIGHBORS
+// - AGGREGATE
+// - LEADER
+// - STATE
+// - DIGEST
+// - PULL
 use agent::{SwarmAgent};
 use artifact::{SwarmArtifact};
+use registry::ArtifactRegistry;
 use Location;
 use ReactToSwarm;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use codec::{self, Codec, Bincode};
+use swarm::socket::MAX_PACKET_SIZE;
+use swarm::network::SwarmNetwork;
+use identity::{self, Keypair};
+use swarm::task::TaskMsg;
 
 mod socket;
+pub mod session;
+pub mod capture;
+pub mod reputation;
+pub mod admission;
+pub mod admin;
+pub mod metrics;
+pub mod trace;
+pub mod topology;
+pub mod crawler;
+pub mod aggregate;
+pub mod election;
+pub mod task;
+pub mod crdt;
+pub mod history;
 pub mod network;
 
-pub struct SwarmController<T, Loc> {
-    actor: T
+pub struct SwarmController<T, Loc, P> {
+    actor: T,
+    artifacts: ArtifactRegistry<Loc, P>
 }
 
-impl<T: ReactToSwarm<Loc>, Loc: Location> SwarmController<T, Loc>
+impl<T: ReactToSwarm<Loc, P>, Loc: Location + Clone, P: Clone> SwarmController<T, Loc, P>
 {
-    pub fn new(act: T) -> SwarmController<T, Loc> {
-        SwarmController { actor: act }
+    pub fn new(act: T) -> SwarmController<T, Loc, P> {
+        SwarmController { actor: act, artifacts: ArtifactRegistry::new() }
     }
 
-    fn send_msg(&mut self, msg: &SwarmMsg<Loc>) {
-        self.actor.react(msg);
+    pub fn with_artifact_expiry(act: T, expiry_ms: u64) -> SwarmController<T, Loc, P> {
+        SwarmController {
+            actor: act,
+            artifacts: ArtifactRegistry::with_expiry(expiry_ms)
+        }
     }
 
-    fn send_artifact(&mut self, agent: SwarmAgent<Loc>,
-                     art: SwarmArtifact<Loc>) {
-        let msg: SwarmMsg<Loc> =
-            SwarmMsg::new_artifact_msg(agent, art);
-
-        self.send_msg(&msg);
+    pub fn artifacts(&self) -> &ArtifactRegistry<Loc, P> {
+        &self.artifacts
     }
 
-    fn send_artifact_gone(&mut self, agent: SwarmAgent<Loc>,
-                          art: SwarmArtifact<Loc>) {
-        let msg: SwarmMsg<Loc> =
-            SwarmMsg::new_artifact_gone_msg(agent, art);
-
-        self.send_msg(&msg);
+    // Hand a message received from the swarm to the actor, keeping track of
+    // the artifacts it carries.
+    pub fn deliver(&mut self, msg: &SwarmMsg<Loc, P>) {
+        self.artifacts.update(msg);
+        self.actor.react(msg);
     }
 
-    fn send_avoid_location(&mut self, agent: SwarmAgent<Loc>, loc: Loc) {
-        let msg: SwarmMsg<Loc> =
-            SwarmMsg::new_avoid_loc_msg(agent, loc);
-
-        self.send_msg(&msg);
+    fn send_leader_changed(&mut self, leader: &SwarmAgent<Loc>) {
+        self.actor.leader_changed(leader);
     }
 
-    fn send_converge(&mut self, agent: SwarmAgent<Loc>, loc: Loc) {
-        let msg: SwarmMsg<Loc> =
-            SwarmMsg::new_converge_msg(agent, loc);
-
-        self.send_msg(&msg);
-    }
-
-    fn send_malicious_agent(&mut self, agent: SwarmAgent<Loc>,
-                                       mal: SwarmAgent<Loc>) {
-        let msg: SwarmMsg<Loc> =
-            SwarmMsg::new_malicious_agent_msg(agent, mal);
-
-        self.send_msg(&msg);
+    // Tell the actor when the leader elected by network changes, see
+    // SwarmNetwork::enable_election. Should be called after the network has
+    // handled its RPCs or announced its leader.
+    pub fn follow_leader(&mut self, network: &mut SwarmNetwork<Loc, P>) {
+        if let Some(leader) = network.take_leader_change() {
+            self.send_leader_changed(&leader);
+        }
     }
 
 }
 
-#[derive(Clone, Eq, PartialEq, Show, RustcDecodable, RustcEncodable)]
-pub enum SwarmEvent<Loc> {
-    Artifact(SwarmArtifact<Loc>),
-    ArtifactGone(SwarmArtifact<Loc>),
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum SwarmEvent<Loc, P> {
+    Artifact(SwarmArtifact<Loc, P>),
+    ArtifactUpdate(SwarmArtifact<Loc, P>),
+    ArtifactGone(SwarmArtifact<Loc, P>),
     AvoidLocation(Loc),
     Converge(Loc),
-    MaliciousAgent(SwarmAgent<Loc>)
+    MaliciousAgent(SwarmAgent<Loc>),
+    // Application defined event, see SwarmMsg::new_typed_msg.
+    Custom(Vec<u8>),
+    // Contract net task allocation, see swarm::task.
+    Task(TaskMsg<Loc>),
+    // A marker deposited at a location, see pheromone.
+    Pheromone(Loc, f64)
 }
 
-#[derive(Clone, Eq, PartialEq, Show, RustcDecodable, RustcEncodable)]
-pub struct SwarmMsg<Loc> {
+impl<Loc, P> SwarmEvent<Loc, P> {
+    // Decode the application defined event carried by a Custom event.
+    pub fn decode_custom<E: DeserializeOwned>(&self) -> Option<E> {
+        match self {
+            SwarmEvent::Custom(data) => {
+                Bincode.decode(data.as_slice()).ok()
+            }
+            _ => None
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct SwarmMsg<Loc, P> {
     from_agent: SwarmAgent<Loc>,
-    event: SwarmEvent<Loc>
+    event: SwarmEvent<Loc, P>,
+    // Signature of the originating agent over from_agent and event.
+    #[serde(with = "codec::bytes")]
+    signature: Vec<u8>
 }
 
-impl<Loc> SwarmMsg<Loc> {
-    fn new_artifact_msg(agent: SwarmAgent<Loc>,
-                        art: SwarmArtifact<Loc>) -> SwarmMsg<Loc> {
+impl<Loc, P> SwarmMsg<Loc, P> {
+    pub fn new_artifact_msg(agent: SwarmAgent<Loc>,
+                        art: SwarmArtifact<Loc, P>) -> SwarmMsg<Loc, P> {
+        SwarmMsg {
+            from_agent: agent,
+            event: SwarmEvent::Artifact(art),
+            signature: Vec::new()
+        }
+    }
+
+    pub fn new_artifact_update_msg(agent: SwarmAgent<Loc>,
+                                   art: SwarmArtifact<Loc, P>) -> SwarmMsg<Loc, P> {
+        SwarmMsg {
+            from_agent: agent,
+            event: SwarmEvent::ArtifactUpdate(art),
+            signature: Vec::new()
+        }
+    }
+
+    pub fn new_artifact_gone_msg(agent: SwarmAgent<Loc>,
+                             art: SwarmArtifact<Loc, P>) -> SwarmMsg<Loc, P> {
         SwarmMsg {
             from_agent: agent,
-            event: SwarmEvent::Artifact(art)
+            event: SwarmEvent::ArtifactGone(art),
+            signature: Vec::new()
         }
     }
 
-    fn new_artifact_gone_msg(agent: SwarmAgent<Loc>,
-                             art: SwarmArtifact<Loc>) -> SwarmMsg<Loc> {
+    pub fn new_avoid_loc_msg(agent: SwarmAgent<Loc>, loc: Loc) -> SwarmMsg<Loc, P> {
         SwarmMsg {
             from_agent: agent,
-            event: SwarmEvent::ArtifactGone(art)
+            event: SwarmEvent::AvoidLocation(loc),
+            signature: Vec::new()
         }
     }
 
-    fn new_avoid_loc_msg(agent: SwarmAgent<Loc>, loc: Loc) -> SwarmMsg<Loc> {
+    pub fn new_converge_msg(agent: SwarmAgent<Loc>, loc: Loc) -> SwarmMsg<Loc, P> {
         SwarmMsg {
             from_agent: agent,
-            event: SwarmEvent::AvoidLocation(loc)
+            event: SwarmEvent::Converge(loc),
+            signature: Vec::new()
         }
     }
 
-    fn new_converge_msg(agent: SwarmAgent<Loc>, loc: Loc) -> SwarmMsg<Loc> {
+    pub fn new_malicious_agent_msg(agent: SwarmAgent<Loc>,
+                               mal: SwarmAgent<Loc>) -> SwarmMsg<Loc, P> {
         SwarmMsg {
             from_agent: agent,
-            event: SwarmEvent::Converge(loc)
+            event: SwarmEvent::MaliciousAgent(mal),
+            signature: Vec::new()
         }
     }
 
-    fn new_malicious_agent_msg(agent: SwarmAgent<Loc>,
-                               mal: SwarmAgent<Loc>) -> SwarmMsg<Loc> {
+    pub fn new_custom_msg(agent: SwarmAgent<Loc>, data: Vec<u8>) -> SwarmMsg<Loc, P> {
         SwarmMsg {
             from_agent: agent,
-            event: SwarmEvent::MaliciousAgent(mal)
+            event: SwarmEvent::Custom(data),
+            signature: Vec::new()
         }
     }
 
-    pub fn event<'a>(&'a self) -> &'a SwarmEvent<Loc> {
+    pub fn new_task_msg(agent: SwarmAgent<Loc>, task_msg: TaskMsg<Loc>) -> SwarmMsg<Loc, P> {
+        SwarmMsg {
+            from_agent: agent,
+            event: SwarmEvent::Task(task_msg),
+            signature: Vec::new()
+        }
+    }
+
+    pub fn new_pheromone_msg(agent: SwarmAgent<Loc>, loc: Loc,
+                             amount: f64) -> SwarmMsg<Loc, P> {
+        SwarmMsg {
+            from_agent: agent,
+            event: SwarmEvent::Pheromone(loc, amount),
+            signature: Vec::new()
+        }
+    }
+
+    // Create a Custom event from an application defined event type. Returns
+    // None if the event cannot be encoded within the maximum packet size.
+    pub fn new_typed_msg<E: Serialize>(agent: SwarmAgent<Loc>,
+                                       event: &E) -> Option<SwarmMsg<Loc, P>> {
+        match Bincode.encode(event) {
+            Ok(ref data) if data.len() > MAX_PACKET_SIZE => None,
+            Ok(data) => Some(SwarmMsg::new_custom_msg(agent, data)),
+            Err(_) => None
+        }
+    }
+
+    pub fn event(&self) -> &SwarmEvent<Loc, P> {
         &self.event
     }
 
-    pub fn from_agent<'a>(&'a self) -> &'a SwarmAgent<Loc> {
+    // The agent the message originates from, not a conversion.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn from_agent(&self) -> &SwarmAgent<Loc> {
         &self.from_agent
     }
 }
 
+impl<Loc: Serialize, P: Serialize> SwarmMsg<Loc, P> {
+    // Sign the message as the agent it originates from.
+    pub fn sign(&mut self, keypair: &Keypair) {
+        self.signature = keypair.sign(self.signed_data().as_slice());
+    }
+
+    // Whether the message was signed by the agent it claims to be from.
+    pub fn verify(&self) -> bool {
+        self.from_agent.is_authentic() &&
+            identity::verify(self.from_agent.public_key(),
+                             self.signed_data().as_slice(),
+                             self.signature.as_slice())
+    }
+
+    fn signed_data(&self) -> Vec<u8> {
+        Bincode.encode(&(&self.from_agent, &self.event)).
+            unwrap_or_default()
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use Location;
-    use ReactToSwarm;
-    use swarm::{SwarmMsg, SwarmController};
+    use {Location, ReactToSwarm};
+    use swarm::{SwarmMsg, SwarmController, SwarmEvent};
     use swarm::SwarmEvent::*;
     use agent::{SwarmAgent};
     use artifact::{SwarmArtifact};
-    use std::io::pipe::PipeStream;
-    use std::io::IoResult;
-    use std::io::net::ip::{SocketAddr, Ipv4Addr};
+    use identity::Keypair;
+    use std::io::{self, PipeReader, PipeWriter, Read, Write};
+    use std::net::SocketAddr;
+
+    // Deliver messages of each kind.
+    impl<T: ReactToSwarm<Loc, P>, Loc: Location + Clone, P: Clone> SwarmController<T, Loc, P> {
+        fn send_artifact(&mut self, agent: SwarmAgent<Loc>,
+                         art: SwarmArtifact<Loc, P>) {
+            let msg: SwarmMsg<Loc, P> =
+                SwarmMsg::new_artifact_msg(agent, art);
+
+            self.deliver(&msg);
+        }
+
+        fn send_artifact_update(&mut self, agent: SwarmAgent<Loc>,
+                                art: SwarmArtifact<Loc, P>) {
+            let msg: SwarmMsg<Loc, P> =
+                SwarmMsg::new_artifact_update_msg(agent, art);
+
+            self.deliver(&msg);
+        }
+
+        fn send_artifact_gone(&mut self, agent: SwarmAgent<Loc>,
+                              art: SwarmArtifact<Loc, P>) {
+            let msg: SwarmMsg<Loc, P> =
+                SwarmMsg::new_artifact_gone_msg(agent, art);
+
+            self.deliver(&msg);
+        }
+
+        fn send_avoid_location(&mut self, agent: SwarmAgent<Loc>, loc: Loc) {
+            let msg: SwarmMsg<Loc, P> =
+                SwarmMsg::new_avoid_loc_msg(agent, loc);
+
+            self.deliver(&msg);
+        }
+
+        fn send_converge(&mut self, agent: SwarmAgent<Loc>, loc: Loc) {
+            let msg: SwarmMsg<Loc, P> =
+                SwarmMsg::new_converge_msg(agent, loc);
+
+            self.deliver(&msg);
+        }
+
+        fn send_malicious_agent(&mut self, agent: SwarmAgent<Loc>,
+                                           mal: SwarmAgent<Loc>) {
+            let msg: SwarmMsg<Loc, P> =
+                SwarmMsg::new_malicious_agent_msg(agent, mal);
+
+            self.deliver(&msg);
+        }
+
+        fn send_custom(&mut self, agent: SwarmAgent<Loc>, data: Vec<u8>) {
+            let msg: SwarmMsg<Loc, P> =
+                SwarmMsg::new_custom_msg(agent, data);
+
+            self.deliver(&msg);
+        }
+    }
 
     const ART_EVENT_RECV: u8 = 1 << 0;
     const ART_GONE_EVENT_RECV: u8 = 1 << 1;
     const AVOID_LOC_EVENT_RECV: u8 = 1 << 2;
     const CONV_EVENT_RECV: u8 = 1 << 3;
     const MAL_AGN_EVENT_RECV: u8 = 1 << 4;
+    const ART_UPD_EVENT_RECV: u8 = 1 << 5;
+    const CUSTOM_EVENT_RECV: u8 = 1 << 6;
+    const LEADER_CHANGED_RECV: u8 = 1 << 7;
 
     const ART_LOC: isize = 1 << 0;
     const AGN_LOC: isize = 1 << 1;
     const AVD_LOC: isize = 1 << 2;
     const CNV_LOC: isize = 1 << 3;
 
+    const ART_QTY: u32 = 100;
+    const SENSOR_VALUE: u32 = 42;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct SensorReading {
+        sensor: u8,
+        value: u32
+    }
+
     struct Tester {
-        react_writer: PipeStream
+        react_writer: PipeWriter
     }
 
-    impl ReactToSwarm<isize> for Tester {
+    impl ReactToSwarm<isize, u32> for Tester {
         fn react(&mut self,
-            msg: &SwarmMsg<isize>) {
+            msg: &SwarmMsg<isize, u32>) {
             match msg.event() {
-                &Artifact(ref art) => {
+                Artifact(art) => {
+                    assert_eq!(*art.location(), ART_LOC);
+                    handle_io_result(self.react_writer.write_all(&[ART_EVENT_RECV]))
+                }
+                ArtifactUpdate(art) => {
                     assert_eq!(*art.location(), ART_LOC);
-                    handle_io_result(self.react_writer.write_u8(ART_EVENT_RECV))
+                    assert!(*art.payload() < ART_QTY);
+                    handle_io_result(self.react_writer.write_all(&[ART_UPD_EVENT_RECV]))
                 }
-                &ArtifactGone(ref art) => {
+                ArtifactGone(art) => {
                     assert_eq!(*art.location(), ART_LOC);
-                    handle_io_result(self.react_writer.write_u8(ART_GONE_EVENT_RECV))
+                    handle_io_result(self.react_writer.write_all(&[ART_GONE_EVENT_RECV]))
                 }
-                &AvoidLocation(ref loc) => {
+                AvoidLocation(loc) => {
                     assert_eq!(*loc, AVD_LOC);
-                    handle_io_result(self.react_writer.write_u8(AVOID_LOC_EVENT_RECV))
+                    handle_io_result(self.react_writer.write_all(&[AVOID_LOC_EVENT_RECV]))
                 }
-                &Converge(ref loc) => {
+                Converge(loc) => {
                     assert_eq!(*loc, CNV_LOC);
-                    handle_io_result(self.react_writer.write_u8(CONV_EVENT_RECV))
+                    handle_io_result(self.react_writer.write_all(&[CONV_EVENT_RECV]))
                 }
-                &MaliciousAgent(ref agn) => {
+                MaliciousAgent(agn) => {
                     assert_eq!(*agn.location(), AGN_LOC);
-                    handle_io_result(self.react_writer.write_u8(MAL_AGN_EVENT_RECV))
+                    handle_io_result(self.react_writer.write_all(&[MAL_AGN_EVENT_RECV]))
                 }
+                &Custom(_) => {
+                    let reading: SensorReading = msg.event().decode_custom().unwrap();
+                    assert_eq!(reading.value, SENSOR_VALUE);
+                    handle_io_result(self.react_writer.write_all(&[CUSTOM_EVENT_RECV]))
+                }
+                &Task(_) | &Pheromone(..) => {}
             }
 
             assert_eq!(*msg.from_agent().location(), AGN_LOC);
         }
+
+        fn leader_changed(&mut self, leader: &SwarmAgent<isize>) {
+            assert_eq!(*leader.location(), AGN_LOC);
+            handle_io_result(self.react_writer.write_all(&[LEADER_CHANGED_RECV]))
+        }
     }
 
-    fn handle_io_result<T>(res: IoResult<T>) -> T {
+    fn handle_io_result<T>(res: io::Result<T>) -> T {
         match res {
             Ok(ret) => ret,
             Err(err) => panic!("{}\n", err)
@@ -203,14 +403,20 @@ mod test {
     }
 
     fn test_addr() -> SocketAddr {
-        SocketAddr{ ip: Ipv4Addr(127,0,0,0), port: 55555 }
+        SocketAddr::from(([127,0,0,0], 55555))
     }
 
-    fn swarm_tester() -> (SwarmController<Tester,isize>, PipeStream)
+    fn read_byte(reader: &mut PipeReader) -> io::Result<u8> {
+        let mut byte = [0u8];
+        reader.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    fn swarm_tester() -> (SwarmController<Tester, isize, u32>, PipeReader)
     {
-        let pair = handle_io_result(PipeStream::pair());
-        let tester = Tester { react_writer: pair.writer };
-        (SwarmController::new(tester), pair.reader)
+        let (reader, writer) = handle_io_result(io::pipe());
+        let tester = Tester { react_writer: writer };
+        (SwarmController::new(tester), reader)
     }
 
     #[test]
@@ -218,10 +424,44 @@ mod test {
         let (mut swarm, mut reader) = swarm_tester();
 
         let agent = SwarmAgent::new(AGN_LOC, test_addr());
-        let artifact = SwarmArtifact::new(ART_LOC);
+        let artifact = SwarmArtifact::new(ART_LOC, ART_QTY);
 
         swarm.send_artifact(agent, artifact);
-        assert_eq!(handle_io_result(reader.read_byte()), ART_EVENT_RECV);
+        assert_eq!(handle_io_result(read_byte(&mut reader)), ART_EVENT_RECV);
+    }
+
+    #[test]
+    fn artifact_registry_test() {
+        let (mut swarm, mut reader) = swarm_tester();
+
+        let agent = SwarmAgent::new(AGN_LOC, test_addr());
+        let artifact = SwarmArtifact::new(ART_LOC, ART_QTY);
+        let id = artifact.id().clone();
+
+        swarm.send_artifact(agent.clone(), artifact.clone());
+        assert_eq!(handle_io_result(read_byte(&mut reader)), ART_EVENT_RECV);
+        assert!(swarm.artifacts().get(&id).is_some());
+
+        swarm.send_artifact_gone(agent, artifact);
+        assert_eq!(handle_io_result(read_byte(&mut reader)), ART_GONE_EVENT_RECV);
+        assert!(swarm.artifacts().get(&id).is_none());
+    }
+
+    #[test]
+    fn send_artifact_update_msg_test() {
+        let (mut swarm, mut reader) = swarm_tester();
+
+        let agent = SwarmAgent::new(AGN_LOC, test_addr());
+        let mut artifact = SwarmArtifact::new(ART_LOC, ART_QTY);
+        let id = artifact.id().clone();
+
+        swarm.send_artifact(agent.clone(), artifact.clone());
+        assert_eq!(handle_io_result(read_byte(&mut reader)), ART_EVENT_RECV);
+
+        artifact.update_payload(ART_QTY / 2);
+        swarm.send_artifact_update(agent, artifact);
+        assert_eq!(handle_io_result(read_byte(&mut reader)), ART_UPD_EVENT_RECV);
+        assert_eq!(*swarm.artifacts().get(&id).unwrap().payload(), ART_QTY / 2);
     }
 
     #[test]
@@ -229,10 +469,10 @@ mod test {
         let (mut swarm, mut reader) = swarm_tester();
 
         let agent = SwarmAgent::new(AGN_LOC, test_addr());
-        let artifact = SwarmArtifact::new(ART_LOC);
+        let artifact = SwarmArtifact::new(ART_LOC, ART_QTY);
 
         swarm.send_artifact_gone(agent, artifact);
-        assert_eq!(handle_io_result(reader.read_byte()), ART_GONE_EVENT_RECV);
+        assert_eq!(handle_io_result(read_byte(&mut reader)), ART_GONE_EVENT_RECV);
     }
 
     #[test]
@@ -242,7 +482,7 @@ mod test {
         let agent = SwarmAgent::new(AGN_LOC, test_addr());
 
         swarm.send_avoid_location(agent, AVD_LOC);
-        assert_eq!(handle_io_result(reader.read_byte()), AVOID_LOC_EVENT_RECV);
+        assert_eq!(handle_io_result(read_byte(&mut reader)), AVOID_LOC_EVENT_RECV);
     }
 
     #[test]
@@ -252,7 +492,7 @@ mod test {
         let agent = SwarmAgent::new(AGN_LOC, test_addr());
 
         swarm.send_converge(agent, CNV_LOC);
-        assert_eq!(handle_io_result(reader.read_byte()), CONV_EVENT_RECV);
+        assert_eq!(handle_io_result(read_byte(&mut reader)), CONV_EVENT_RECV);
     }
 
     #[test]
@@ -263,6 +503,68 @@ mod test {
         let mal_agent = SwarmAgent::new(AGN_LOC, test_addr());
 
         swarm.send_malicious_agent(agent, mal_agent);
-        assert_eq!(handle_io_result(reader.read_byte()), MAL_AGN_EVENT_RECV);
+        assert_eq!(handle_io_result(read_byte(&mut reader)), MAL_AGN_EVENT_RECV);
+    }
+
+    #[test]
+    fn send_leader_changed_test() {
+        let (mut swarm, mut reader) = swarm_tester();
+
+        let leader = SwarmAgent::new(AGN_LOC, test_addr());
+        swarm.send_leader_changed(&leader);
+        assert_eq!(handle_io_result(read_byte(&mut reader)), LEADER_CHANGED_RECV);
+    }
+
+    #[test]
+    fn send_custom_msg_test() {
+        let (mut swarm, mut reader) = swarm_tester();
+
+        let agent = SwarmAgent::new(AGN_LOC, test_addr());
+        let reading = SensorReading { sensor: 1, value: SENSOR_VALUE };
+        let msg: SwarmMsg<isize, u32> =
+            SwarmMsg::new_typed_msg(agent.clone(), &reading).unwrap();
+
+        match msg.event() {
+            Custom(data) => swarm.send_custom(agent, data.clone()),
+            _ => panic!("expected a custom event")
+        }
+        assert_eq!(handle_io_result(read_byte(&mut reader)), CUSTOM_EVENT_RECV);
+    }
+
+    #[test]
+    fn decode_custom_test() {
+        let event: SwarmEvent<isize, u32> = Converge(CNV_LOC);
+        assert!(event.decode_custom::<SensorReading>().is_none());
+
+        let garbage: SwarmEvent<isize, u32> = Custom(vec![1u8]);
+        assert!(garbage.decode_custom::<SensorReading>().is_none());
+    }
+
+    #[test]
+    fn sign_verify_msg_test() {
+        let keypair = Keypair::generate();
+        let agent = SwarmAgent::with_keypair(AGN_LOC, test_addr(), &keypair);
+        let mut msg: SwarmMsg<isize, u32> =
+            SwarmMsg::new_converge_msg(agent.clone(), CNV_LOC);
+        assert!(!msg.verify());
+
+        msg.sign(&keypair);
+        assert!(msg.verify());
+
+        // Signing with a key that does not belong to from_agent.
+        let mut forged: SwarmMsg<isize, u32> =
+            SwarmMsg::new_converge_msg(agent.clone(), CNV_LOC);
+        forged.sign(&Keypair::generate());
+        assert!(!forged.verify());
+
+        // Tampering with the event invalidates the signature.
+        let mut tampered = msg.clone();
+        tampered.event = Converge(AVD_LOC);
+        assert!(!tampered.verify());
+
+        let mut unauthentic: SwarmMsg<isize, u32> =
+            SwarmMsg::new_converge_msg(SwarmAgent::new(AGN_LOC, test_addr()), CNV_LOC);
+        unauthentic.sign(&keypair);
+        assert!(!unauthentic.verify());
     }
 }
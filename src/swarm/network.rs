@@ -1,52 +1,559 @@
-use rustc_serialize::{Decodable, Encodable};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
 use std::vec::Vec;
-use swarm::socket::{SwarmResult, SwarmSocket};
-use swarm::SwarmMsg;
+use swarm::socket::{self, SwarmResult, SwarmSocket, SwarmError, PacketType, MAX_PACKET_SIZE};
+use swarm::{SwarmMsg, SwarmEvent};
+use swarm::session::{SessionLayer, Encryption};
+use swarm::capture::{CaptureWriter, CapturedPacket, Direction};
+use swarm::reputation::{Reputation, Violation};
+use swarm::admission::{Admission, AllowAll, Blocklist, RateLimiter};
+use swarm::admin::{AdminSocket, AdminRequest, AdminResponse, Neighbor, Counters};
+use swarm::metrics::{Metrics, MetricsEndpoint};
+use swarm::trace::{self, Tracer, NoTrace, TraceId, TraceEvent, TraceDecision};
+use swarm::crawler::{Crawl, CrawlConfig, CrawlResult};
+use swarm::aggregate::{Aggregates, Estimate, Mass};
+use swarm::election::{Announcement, Election};
+use swarm::crdt::{Crdt, SharedState};
+use swarm::history::{BroadcastHistory, BroadcastId, broadcast_id};
+use swarm::task::{Task, TaskBoard, TaskId, TaskMsg, TaskOutcome, TaskStatus};
+use byteid::ByteId;
 use agent::{SwarmAddr, SwarmAgent};
+use artifact::SwarmArtifact;
+use registry::ArtifactRegistry;
+use identity::{self, Keypair};
+use codec::{self, Codec, Bincode, WireFormat};
 use Location;
-use std::io::net::ip::{SocketAddr, ToSocketAddr};
-use bincode::DecoderReader;
-use bincode::EncoderWriter;
-use std::io::{BufReader};
-use std::io::MemWriter;
-use std::fmt::Show;
-
-#[derive(Clone, Eq, PartialEq, Show, RustcDecodable, RustcEncodable)]
-pub enum IronSwarmRPC<Loc> {
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::fmt::Debug;
+use std::mem;
+use rand::{thread_rng, Rng};
+use clock::{now_ms, wall_clock_ms};
+
+// RPCs are named as they are on the wire.
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum IronSwarmRPC<Loc, P> {
     HRTBT(SwarmAgent<Loc>),
     HRTBTACK(Vec<SwarmAgent<Loc>>),
     JOIN(SwarmAgent<Loc>),
-    INFO(Loc, SwarmMsg<Loc>),
-    BROADCAST(SwarmMsg<Loc>),
+    // Location, number of hops travelled so far and message.
+    INFO(Loc, u8, SwarmMsg<Loc, P>),
+    // Number of hops travelled so far and message.
+    BROADCAST(u8, SwarmMsg<Loc, P>),
+    PUT(SwarmArtifact<Loc, P>),
+    STORE(SwarmArtifact<Loc, P>),
+    GET(Loc, usize, SwarmAgent<Loc>),
+    // The id of the agent that sent the GET and artifacts found for it. Sent
+    // back along the path the GET took, in as many packets as it takes.
+    ARTIFACTS(ByteId, Vec<SwarmArtifact<Loc, P>>),
+    // Ask for the neighbor table of an agent, answered with NEIGHBORS.
+    GET_NEIGHBORS(SwarmAgent<Loc>),
+    // The answering agent and its neighbors.
+    NEIGHBORS(SwarmAgent<Loc>, Vec<SwarmAgent<Loc>>),
+    // Half of the mass of the named aggregate.
+    AGGREGATE(String, Mass),
+    // The announcement of the sending agent, or the latest announcement of
+    // the leader it follows.
+    LEADER(Announcement<Loc>),
+    // The key and replica of an entry of the shared state.
+    STATE(String, Crdt),
+    // Ids of the latest broadcasts seen by the sender, answered with a PULL
+    // of those missing.
+    DIGEST(Vec<BroadcastId>),
+    // Ids of broadcasts to send again, and padding. The broadcasts sent again
+    // are no larger in total than the request.
+    PULL(Vec<BroadcastId>, Vec<u8>),
 }
 
-pub struct SwarmNetwork<Loc> {
+// An RPC along with the public key and signature of the agent sending it, and
+// the id of the trace it belongs to. The signature covers the RPC and the wall
+// clock time it was signed at, so that old RPCs can not be sent again.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+struct SignedRPC<Loc, P> {
+    #[serde(with = "codec::bytes")]
+    public_key: Vec<u8>,
+    #[serde(with = "codec::bytes")]
+    signature: Vec<u8>,
+    timestamp: u64,
+    trace_id: TraceId,
+    rpc: IronSwarmRPC<Loc, P>
+}
+
+impl<Loc: Serialize, P: Serialize> SignedRPC<Loc, P> {
+    fn signed_data(&self) -> SwarmResult<Vec<u8>> {
+        Ok(Bincode.encode(&(self.timestamp, &self.rpc))?)
+    }
+}
+
+impl<Loc, P> PacketType for IronSwarmRPC<Loc, P> {
+    fn packet_type(&self) -> u8 {
+        match *self {
+            IronSwarmRPC::HRTBT(..) => 1,
+            IronSwarmRPC::HRTBTACK(..) => 2,
+            IronSwarmRPC::JOIN(..) => 3,
+            IronSwarmRPC::INFO(..) => 4,
+            IronSwarmRPC::BROADCAST(..) => 5,
+            IronSwarmRPC::PUT(..) => 6,
+            IronSwarmRPC::STORE(..) => 7,
+            IronSwarmRPC::GET(..) => 8,
+            IronSwarmRPC::ARTIFACTS(..) => 9,
+            IronSwarmRPC::GET_NEIGHBORS(..) => 10,
+            IronSwarmRPC::NEIGHBORS(..) => 11,
+            IronSwarmRPC::AGGREGATE(..) => 12,
+            IronSwarmRPC::LEADER(..) => 13,
+            IronSwarmRPC::STATE(..) => 14,
+            IronSwarmRPC::DIGEST(..) => 15,
+            IronSwarmRPC::PULL(..) => 16,
+        }
+    }
+}
+
+impl<Loc, P> IronSwarmRPC<Loc, P> {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            IronSwarmRPC::HRTBT(..) => "HRTBT",
+            IronSwarmRPC::HRTBTACK(..) => "HRTBTACK",
+            IronSwarmRPC::JOIN(..) => "JOIN",
+            IronSwarmRPC::INFO(..) => "INFO",
+            IronSwarmRPC::BROADCAST(..) => "BROADCAST",
+            IronSwarmRPC::PUT(..) => "PUT",
+            IronSwarmRPC::STORE(..) => "STORE",
+            IronSwarmRPC::GET(..) => "GET",
+            IronSwarmRPC::ARTIFACTS(..) => "ARTIFACTS",
+            IronSwarmRPC::GET_NEIGHBORS(..) => "GET_NEIGHBORS",
+            IronSwarmRPC::NEIGHBORS(..) => "NEIGHBORS",
+            IronSwarmRPC::AGGREGATE(..) => "AGGREGATE",
+            IronSwarmRPC::LEADER(..) => "LEADER",
+            IronSwarmRPC::STATE(..) => "STATE",
+            IronSwarmRPC::DIGEST(..) => "DIGEST",
+            IronSwarmRPC::PULL(..) => "PULL",
+        }
+    }
+}
+
+impl<Loc, P> PacketType for SignedRPC<Loc, P> {
+    fn packet_type(&self) -> u8 {
+        self.rpc.packet_type()
+    }
+}
+
+// Pretty-print a captured UDP payload as JSON, for inspecting the traffic of a
+// swarm. Only packets sent without encryption can be decoded.
+pub fn packet_to_json<Loc, P>(payload: &[u8]) -> SwarmResult<String>
+where Loc: Serialize + DeserializeOwned, P: Serialize + DeserializeOwned {
+    match socket::open_packet(&mut SessionLayer::disabled(), payload) {
+        Some(data) => socket::packet_to_json::<SignedRPC<Loc, P>>(data.as_slice()),
+        None => Err(SwarmError::DecryptionFailed)
+    }
+}
+
+pub struct SwarmNetwork<Loc, P> {
     socket: SwarmSocket,
+    keypair: Keypair,
     local_agent: SwarmAgent<Loc>,
-    neighbors: Vec<SwarmAgent<Loc>>
+    neighbors: Vec<SwarmAgent<Loc>>,
+    // Artifacts this agent is responsible for storing.
+    store: ArtifactRegistry<Loc, P>,
+    // Artifacts returned from GET requests made by this agent.
+    found: Vec<SwarmArtifact<Loc, P>>,
+    reputation: Reputation,
+    // Largest distance a neighbor may move between two heartbeats.
+    max_location_jump: Option<usize>,
+    admission: Box<dyn Admission<Loc> + 'static>,
+    blocklist: Blocklist,
+    rate_limiter: RateLimiter,
+    // Leading zero bits required of the ids of agents admitted.
+    required_work: u32,
+    // When a packet was last received from each neighbor, in milliseconds.
+    last_seen: HashMap<ByteId, u64>,
+    admin: Option<AdminSocket>,
+    metrics: Metrics,
+    metrics_endpoint: Option<MetricsEndpoint>,
+    // When this agent sent its JOIN, until it gains its first neighbor.
+    join_started: Option<u64>,
+    // The most recent broadcasts sent and received.
+    broadcasts: BroadcastHistory<SwarmMsg<Loc, P>>,
+    tracer: Box<dyn Tracer + 'static>,
+    // Trace id of the RPC being handled, which RPCs sent in response keep.
+    current_trace: Option<TraceId>,
+    // Address the RPC being handled was received from.
+    current_source: Option<SocketAddr>,
+    // Id of the neighbor that signed the RPC being handled.
+    current_signer: Option<ByteId>,
+    crawl: Option<Crawl<Loc>>,
+    aggregates: Aggregates,
+    election: Option<Election<Loc>>,
+    tasks: TaskBoard<Loc>,
+    state: SharedState,
+    // Number of neighbors an artifact is replicated to.
+    replication: usize,
+    // GETs sent or passed on by this agent, by the id of the agent that sent
+    // them, whose ARTIFACTS may still arrive.
+    finds: HashMap<ByteId, PendingFind>,
+    // Time the packet being replayed was captured at, in milliseconds, which
+    // stands in for the clock during a replay.
+    replay_time: Option<u64>
 }
 
 const MAX_NEIGHBORS: u8 = 3;
+// Number of neighbors an artifact is replicated to, unless set otherwise.
+const REPLICATION: usize = 2;
+// Milliseconds the ARTIFACTS for a GET are waited for.
+const FIND_TIMEOUT: u64 = 5000;
+// GETs waited for at the same time, see PendingFind.
+const MAX_PENDING_FINDS: usize = 256;
+// JOINs and HRTBTs accepted from a single address per second.
+const RATE_LIMIT: u32 = 50;
+// Number of broadcasts remembered to suppress duplicates and answer PULLs.
+const BROADCAST_HISTORY: usize = 1024;
+// Broadcast ids sent in a DIGEST, and pulled at once.
+const DIGEST_SIZE: usize = 64;
+// Tasks an agent takes on at the same time, unless set otherwise.
+const TASK_CAPACITY: usize = 1;
+// Hops a task announcement is passed on within the radius of the task.
+const MAX_TASK_HOPS: u8 = 8;
+// Room left in a padded PULL for the length of the padding to grow.
+const PULL_SLACK: usize = 16;
+// Milliseconds the signing time of an RPC may be off the wall clock, either
+// way, for clock skew between agents and the delay of the packet.
+const MAX_RPC_AGE: u64 = 30_000;
+
+// A GET whose ARTIFACTS are passed on to the agent the GET came from, or kept
+// if this agent sent it.
+struct PendingFind {
+    reply_to: Option<SocketAddr>,
+    expires: u64
+}
+
+// Whether a broadcast is offered to neighbors in digests. Task messages other
+// than announcements are meant for a single agent, and pheromones for the
+// agents around the one depositing them.
+fn is_gossiped<Loc, P>(msg: &SwarmMsg<Loc, P>) -> bool {
+    match *msg.event() {
+        SwarmEvent::Task(TaskMsg::Announce(_)) => true,
+        SwarmEvent::Task(_) | SwarmEvent::Pheromone(..) => false,
+        _ => true
+    }
+}
+
+impl<Loc: Location + Serialize + Clone, P: Clone> SwarmNetwork<Loc, P> {
+    pub fn new<A: ToSocketAddrs>(loc: Loc, address: A) -> SwarmNetwork<Loc, P> {
+        SwarmNetwork::with_keypair(loc, address, Keypair::generate())
+    }
 
-impl<Loc: Location + Clone> SwarmNetwork<Loc> {
-    fn new<A: ToSocketAddr>(loc: Loc, address: A) -> SwarmNetwork<Loc> {
+    pub fn with_keypair<A: ToSocketAddrs>(loc: Loc, address: A,
+                                         keypair: Keypair) -> SwarmNetwork<Loc, P> {
         let mut socket = SwarmSocket::new(address);
         let addr = socket.socket_name();
-        let agent = SwarmAgent::new(loc, addr);
+        let agent = SwarmAgent::with_keypair(loc, addr, &keypair);
+        let state = SharedState::new(keypair.clone());
 
         SwarmNetwork {
-            socket: socket,
+            socket,
+            keypair,
             local_agent: agent,
-            neighbors: Vec::new()
+            neighbors: Vec::new(),
+            store: ArtifactRegistry::new(),
+            found: Vec::new(),
+            reputation: Reputation::new(),
+            max_location_jump: None,
+            admission: Box::new(AllowAll),
+            blocklist: Blocklist::new(),
+            rate_limiter: RateLimiter::new(RATE_LIMIT, 1000),
+            required_work: 0,
+            last_seen: HashMap::new(),
+            admin: None,
+            metrics: Metrics::new(),
+            metrics_endpoint: None,
+            join_started: None,
+            broadcasts: BroadcastHistory::new(BROADCAST_HISTORY),
+            tracer: Box::new(NoTrace),
+            current_trace: None,
+            current_source: None,
+            current_signer: None,
+            crawl: None,
+            aggregates: Aggregates::new(),
+            election: None,
+            tasks: TaskBoard::new(TASK_CAPACITY),
+            state,
+            replication: REPLICATION,
+            finds: HashMap::new(),
+            replay_time: None
         }
     }
 
-    fn address(&mut self) -> SocketAddr {
+    pub fn update_location(&mut self, location: Loc) {
+        self.local_agent.update_location(location);
+        self.local_agent.sign(&self.keypair);
+    }
+
+}
+
+impl<Loc: Location + Clone, P: Clone> SwarmNetwork<Loc, P> {
+    pub fn address(&mut self) -> SocketAddr {
         self.socket.socket_name()
     }
 
-    fn update_location(&mut self, location: Loc) {
-        self.local_agent.update_location(location);
+    // The current time in milliseconds, or the time of the packet being
+    // replayed.
+    fn now(&self) -> u64 {
+        self.replay_time.unwrap_or_else(now_ms)
+    }
+
+    // The wall clock, or the time a replayed packet was captured at.
+    fn wall_now(&self) -> u64 {
+        self.replay_time.unwrap_or_else(wall_clock_ms)
+    }
+
+    // Whether an RPC signed at timestamp is recent enough to be accepted.
+    fn is_fresh(&self, timestamp: u64) -> bool {
+        let now = self.wall_now();
+        timestamp.saturating_add(MAX_RPC_AGE) >= now &&
+            timestamp <= now.saturating_add(MAX_RPC_AGE)
+    }
+
+    // Only packets carrying the same swarm id are accepted, so that several
+    // swarms can share a network.
+    pub fn set_swarm_id(&mut self, swarm_id: u32) {
+        self.socket.set_swarm_id(swarm_id);
+    }
+
+    pub fn set_wire_format(&mut self, format: WireFormat) {
+        self.socket.set_wire_format(format);
+    }
+
+    // Record every packet this agent sends and receives, see replay.
+    pub fn set_capture(&mut self, capture: Option<CaptureWriter>) {
+        self.socket.set_capture(capture);
+    }
+
+    pub fn rejected_packets(&self) -> u64 {
+        self.socket.rejected_packets()
+    }
+
+    pub fn reputation(&self) -> &Reputation {
+        &self.reputation
+    }
+
+    pub fn set_admission(&mut self, admission: Box<dyn Admission<Loc> + 'static>) {
+        self.admission = admission;
+    }
+
+    pub fn set_tracer(&mut self, tracer: Box<dyn Tracer + 'static>) {
+        self.tracer = tracer;
+    }
+
+    fn trace(&mut self, rpc: &'static str, decision: TraceDecision) {
+        let event = TraceEvent {
+            trace_id: self.current_trace.unwrap_or(0),
+            agent: self.local_agent.id().clone(),
+            rpc,
+            source: self.current_source.map(SwarmAddr::new),
+            decision
+        };
+        self.tracer.trace(&event);
+    }
+
+    pub fn blocklist(&mut self) -> &mut Blocklist {
+        &mut self.blocklist
+    }
+
+    pub fn set_rate_limit(&mut self, max_requests: u32, window_ms: u64) {
+        self.rate_limiter = RateLimiter::new(max_requests, window_ms);
+    }
+
+    // Require the ids of joining agents to be a proof-of-work of at least
+    // bits leading zero bits, see Keypair::generate_with_work.
+    pub fn set_required_work(&mut self, bits: u32) {
+        self.required_work = bits;
+    }
+
+    // Whether a JOIN or HRTBT for agn, received from the last peer, is
+    // accepted.
+    fn admit(&mut self, agn: &SwarmAgent<Loc>, join: bool) -> bool {
+        if self.blocklist.is_blocked_id(agn.id()) ||
+            agn.id().leading_zeros() < self.required_work {
+            return false
+        }
+
+        if self.blocklist.is_blocked_ip(&agn.address().socket_addr().ip()) {
+            return false
+        }
+
+        let now = self.now();
+        if let Some(src) = self.socket.last_peer() {
+            if self.blocklist.is_blocked_ip(&src.ip()) ||
+                !self.rate_limiter.allow_at(src.ip(), now) {
+                return false
+            }
+        }
+
+        if join {
+            self.admission.admit_join(agn)
+        } else {
+            self.admission.admit_heartbeat(agn)
+        }
+    }
+
+    pub fn set_max_location_jump(&mut self, max: usize) {
+        self.max_location_jump = Some(max);
+    }
+
+    // Listen for admin requests on a separate socket, see poll_admin. Only
+    // loopback addresses are accepted. Returns the address the admin socket
+    // is bound to.
+    pub fn enable_admin<A: ToSocketAddrs>(&mut self, address: A) -> io::Result<SocketAddr> {
+        let mut admin = AdminSocket::bind(address)?;
+        let addr = admin.socket_name()?;
+        self.admin = Some(admin);
+        Ok(addr)
+    }
+
+    pub fn counters(&self) -> Counters {
+        Counters {
+            neighbors: self.neighbors.len(),
+            stored_artifacts: self.store.len(),
+            found_artifacts: self.found.len(),
+            rejected_packets: self.socket.rejected_packets()
+        }
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    // Serve the metrics in the Prometheus text format over HTTP, see
+    // poll_metrics. Only loopback addresses are accepted. Returns the address
+    // the endpoint is bound to.
+    pub fn enable_metrics_endpoint<A: ToSocketAddrs>(&mut self,
+                                                    address: A) -> io::Result<SocketAddr> {
+        let endpoint = MetricsEndpoint::bind(address)?;
+        let addr = endpoint.address();
+        self.metrics_endpoint = Some(endpoint);
+        Ok(addr)
+    }
+
+    // Answer the next scrape, if one arrives within a short timeout.
+    pub fn poll_metrics(&mut self) -> io::Result<()> {
+        let body = self.metrics.to_prometheus(self.socket.rejected_packets());
+        match self.metrics_endpoint {
+            Some(ref mut endpoint) => endpoint.serve(body.as_str()),
+            None => Ok(())
+        }
+    }
+
+    pub fn local_agent(&self) -> &SwarmAgent<Loc> {
+        &self.local_agent
+    }
+
+    pub fn neighbors(&self) -> &[SwarmAgent<Loc>] {
+        self.neighbors.as_slice()
+    }
+
+    // Elect the agent with the lowest id within radius of this agent as its
+    // leader, see announce_leader. Candidates not heard of for timeout_ms
+    // are dropped.
+    pub fn enable_election(&mut self, radius: usize, timeout_ms: u64) {
+        self.election = Some(Election::new(radius, timeout_ms));
+    }
+
+    pub fn leader(&self) -> Option<&SwarmAgent<Loc>> {
+        self.election.as_ref().and_then(|e| e.leader())
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.election.as_ref().is_some_and(|e| e.is_leader(&self.local_agent))
+    }
+
+    // The new leader, if it changed since the last call, to be passed on to
+    // ReactToSwarm::leader_changed, see SwarmController::follow_leader.
+    pub fn take_leader_change(&mut self) -> Option<SwarmAgent<Loc>> {
+        self.election.as_mut().and_then(|e| e.take_change())
+    }
+
+    pub fn neighbor_table(&self) -> Vec<Neighbor<Loc>> {
+        let now = self.now();
+        self.neighbors.
+            iter().
+            map(|n| {
+                Neighbor {
+                    agent: n.clone(),
+                    score: self.reputation.score(n.id()),
+                    last_seen_ms_ago: self.last_seen.get(n.id()).map(|&t| now - t)
+                }
+            }).collect()
+    }
+
+    // Forget all neighbors. The agent stops sending heartbeats, and has to
+    // join the swarm again to take part in it.
+    pub fn leave(&mut self) {
+        self.metrics.neighbors_removed(self.neighbors.len());
+        self.neighbors.clear();
+        self.last_seen.clear();
+    }
+
+    fn add_neighbor(&mut self, agn: SwarmAgent<Loc>) {
+        self.neighbors.push(agn);
+        self.metrics.neighbors_added(1);
+
+        let now = self.now();
+        if let Some(start) = self.join_started.take() {
+            self.metrics.joined(now.saturating_sub(start));
+        }
+    }
+
+    // Remember when the neighbor that sent the last packet was heard from.
+    fn mark_last_peer_seen(&mut self) {
+        let now = self.now();
+        let peer = self.socket.last_peer().and_then(|addr| self.neighbor_at(&addr));
+        if let Some(id) = peer {
+            self.last_seen.insert(id, now);
+        }
+    }
+
+    // Stop routing through agents that are no longer trusted.
+    fn prune_untrusted(&mut self) {
+        let before = self.neighbors.len();
+        {
+            let reputation = &self.reputation;
+            self.neighbors.retain(|n| reputation.is_trusted(n.id()));
+        }
+        self.metrics.neighbors_removed(before - self.neighbors.len());
+    }
+
+    fn neighbor_at(&self, addr: &SocketAddr) -> Option<ByteId> {
+        self.neighbors.
+            iter().
+            filter(|n| {
+                n.address().socket_addr() == *addr
+            }).
+            map(|n| n.id().clone()).
+            next()
+    }
+
+    // Penalize a neighbor whose new location is further away from its last
+    // known location than any agent can move between two heartbeats.
+    fn check_location_jump(&mut self, agn: &SwarmAgent<Loc>) {
+        let max = match self.max_location_jump {
+            Some(max) => max,
+            None => return
+        };
+
+        let jumped = self.neighbors.
+            iter().
+            any(|n| n.id() == agn.id() && n.location().distance(agn.location()) > max);
+
+        if jumped {
+            self.reputation.violation(agn.id(), Violation::LocationJump);
+            self.prune_untrusted();
+        }
+    }
+
+    pub fn set_encryption(&mut self, mode: Encryption) {
+        let session = SessionLayer::new(mode, Some(self.keypair.clone()));
+        self.socket.set_session_layer(session);
     }
 
     fn self_loc_is_closer(&mut self, agn: &SwarmAgent<Loc>, loc: &Loc) -> bool {
@@ -59,318 +566,2266 @@ impl<Loc: Location + Clone> SwarmNetwork<Loc> {
         self.neighbors.
             iter().
             cloned().
-            min_by(|a| a.location().distance(loc))
+            min_by_key(|a| a.location().distance(loc))
+    }
+
+    // The neighbor an rpc for loc should be forwarded to, or None if this
+    // agent is the closest one to loc. The decision is traced.
+    fn next_hop(&mut self, rpc: &'static str, loc: &Loc) -> Option<SwarmAgent<Loc>> {
+        let self_distance = self.local_agent.location().distance(loc);
+        match self.find_closest_neighbor(loc) {
+            Some(agn) => {
+                let next_hop_distance = agn.location().distance(loc);
+                if self.self_loc_is_closer(&agn, loc) {
+                    self.trace(rpc, TraceDecision::Delivered {
+                        self_distance,
+                        closest_neighbor_distance: Some(next_hop_distance)
+                    });
+                    None
+                } else {
+                    self.trace(rpc, TraceDecision::Forwarded {
+                        next_hop: agn.address().clone(),
+                        self_distance,
+                        next_hop_distance
+                    });
+                    Some(agn)
+                }
+            }
+            None => {
+                self.trace(rpc, TraceDecision::Delivered {
+                    self_distance,
+                    closest_neighbor_distance: None
+                });
+                None
+            }
+        }
+    }
+
+    fn find_nearest_neighbors(&self, loc: &Loc, k: usize) -> Vec<SwarmAgent<Loc>> {
+        let mut nearest = self.neighbors.clone();
+        nearest.sort_by(|a, b| {
+            a.location().distance(loc).cmp(&b.location().distance(loc))
+        });
+        nearest.truncate(k);
+        nearest
+    }
+
+    pub fn stored_artifacts(&self) -> &ArtifactRegistry<Loc, P> {
+        &self.store
+    }
+
+    // Store the artifacts this agent is closest to at its k nearest
+    // neighbors as well.
+    pub fn set_replication(&mut self, k: usize) {
+        self.replication = k;
+    }
+
+    // Remember that the ARTIFACTS for a GET sent by requester are to be
+    // passed on to reply_to, or kept if None. Returns false if too many GETs
+    // are waited for already.
+    fn expect_artifacts(&mut self, requester: &ByteId, reply_to: Option<SocketAddr>) -> bool {
+        let now = self.now();
+        self.finds.retain(|_, find| find.expires > now);
+        if !self.finds.contains_key(requester) && self.finds.len() >= MAX_PENDING_FINDS {
+            return false
+        }
+        self.finds.insert(requester.clone(), PendingFind {
+            reply_to,
+            expires: now + FIND_TIMEOUT
+        });
+        true
+    }
+
+    // Take the artifacts returned by previous calls to find_artifacts.
+    pub fn take_found_artifacts(&mut self) -> Vec<SwarmArtifact<Loc, P>> {
+        let mut found = Vec::new();
+        mem::swap(&mut found, &mut self.found);
+        found
     }
 }
 
 // Send RPC's.
-impl<Loc: Location + Encodable + Clone> SwarmNetwork<Loc> {
-    fn send_heartbeat<A: ToSocketAddr>(&mut self,
+impl<Loc: Location + Serialize + Clone, P: Serialize + Clone> SwarmNetwork<Loc, P> {
+    // Sign the RPC with this agent's key and send it.
+    fn send_rpc<A: ToSocketAddrs>(&mut self,
+                                 rpc: IronSwarmRPC<Loc, P>,
+                                 dest: A) -> SwarmResult<()> {
+        let signed = self.sign_rpc(rpc)?;
+        self.send_signed(signed, dest)
+    }
+
+    fn sign_rpc(&self, rpc: IronSwarmRPC<Loc, P>) -> SwarmResult<SignedRPC<Loc, P>> {
+        self.sign_rpc_at(rpc, self.wall_now())
+    }
+
+    fn sign_rpc_at(&self, rpc: IronSwarmRPC<Loc, P>,
+                   timestamp: u64) -> SwarmResult<SignedRPC<Loc, P>> {
+        let mut signed = SignedRPC {
+            public_key: self.keypair.public_key().to_vec(),
+            signature: Vec::new(),
+            timestamp,
+            trace_id: self.current_trace.unwrap_or_else(trace::new_trace_id),
+            rpc
+        };
+        signed.signature = self.keypair.sign(signed.signed_data()?.as_slice());
+        Ok(signed)
+    }
+
+    fn send_signed<A: ToSocketAddrs>(&mut self,
+                                    signed: SignedRPC<Loc, P>,
+                                    dest: A) -> SwarmResult<()> {
+        let name = signed.rpc.name();
+        let res = self.socket.send_packet(signed, dest);
+        match res {
+            Ok(()) => self.metrics.packet_sent(name),
+            Err(SwarmError::PacketTooLarge) => self.metrics.oversized_packet(),
+            Err(_) => self.metrics.send_failure()
+        }
+        res
+    }
+
+    fn send_heartbeat<A: ToSocketAddrs>(&mut self,
                                        hrtbt: SwarmAgent<Loc>,
                                        dest: A) -> SwarmResult<()> {
         let rpc = IronSwarmRPC::HRTBT(hrtbt);
-        self.socket.send_packet(rpc, dest)
+        self.send_rpc(rpc, dest)
     }
 
-    fn send_heartbeat_ack<A: ToSocketAddr>(&mut self,
-                                           neighbors: Vec<SwarmAgent<Loc>>,
-                                           dest: A) -> SwarmResult<()> {
-        let rpc = IronSwarmRPC::HRTBTACK(neighbors);
-        self.socket.send_packet(rpc, dest)
+    // Send the neighbors in as many packets as they take, with the local
+    // agent added to every one of them.
+    fn send_heartbeat_ack<A: ToSocketAddrs + Copy>(&mut self,
+                                                  neighbors: Vec<SwarmAgent<Loc>>,
+                                                  dest: A) -> SwarmResult<()> {
+        let local_agent = self.local_agent.clone();
+        let packets = self.sign_in_packets(neighbors, |mut agns| {
+            agns.push(local_agent.clone());
+            IronSwarmRPC::HRTBTACK(agns)
+        })?;
+        for signed in packets.into_iter() {
+            self.send_signed(signed, dest)?;
+        }
+        Ok(())
     }
 
-    fn send_info<A: ToSocketAddr>(&mut self,
+    fn send_info<A: ToSocketAddrs>(&mut self,
                                   loc: Loc,
-                                  msg: SwarmMsg<Loc>,
+                                  hops: u8,
+                                  msg: SwarmMsg<Loc, P>,
                                   dest: A) -> SwarmResult<()> {
-        let rpc = IronSwarmRPC::INFO(loc, msg);
-        self.socket.send_packet(rpc, dest)
+        let rpc = IronSwarmRPC::INFO(loc, hops, msg);
+        self.send_rpc(rpc, dest)
     }
 
-    fn send_broadcast<A: ToSocketAddr>(&mut self,
-                                       msg: SwarmMsg<Loc>,
+    fn send_broadcast<A: ToSocketAddrs>(&mut self,
+                                       hops: u8,
+                                       msg: SwarmMsg<Loc, P>,
                                        dest: A) -> SwarmResult<()> {
-        let rpc = IronSwarmRPC::BROADCAST(msg);
-        self.socket.send_packet(rpc, dest)
+        let rpc = IronSwarmRPC::BROADCAST(hops, msg);
+        self.send_rpc(rpc, dest)
     }
 
-    fn send_join<A: ToSocketAddr>(&mut self,
+    fn send_join<A: ToSocketAddrs>(&mut self,
                                   agn: SwarmAgent<Loc>,
                                   dest: A) -> SwarmResult<()> {
         let rpc = IronSwarmRPC::JOIN(agn);
-        self.socket.send_packet(rpc, dest)
+        self.send_rpc(rpc, dest)
     }
 
-    fn join<A: ToSocketAddr>(&mut self, address: A) {
-        let agn = self.local_agent.clone();
-        self.send_join(agn, address);
+    fn send_put<A: ToSocketAddrs>(&mut self,
+                                 art: SwarmArtifact<Loc, P>,
+                                 dest: A) -> SwarmResult<()> {
+        let rpc = IronSwarmRPC::PUT(art);
+        self.send_rpc(rpc, dest)
     }
 
-    fn heartbeat(&mut self) -> SwarmResult<()> {
-        let addresses: Vec<SwarmAddr> = self.neighbors.iter().
-            map(|n| n.address().clone()).collect();
+    fn send_store<A: ToSocketAddrs>(&mut self,
+                                   art: SwarmArtifact<Loc, P>,
+                                   dest: A) -> SwarmResult<()> {
+        let rpc = IronSwarmRPC::STORE(art);
+        self.send_rpc(rpc, dest)
+    }
 
-        let agn = self.local_agent.clone();
-        for dest in addresses.iter() {
-            try!(self.send_heartbeat(agn.clone(), dest));
+    fn send_get<A: ToSocketAddrs>(&mut self,
+                                 loc: Loc,
+                                 radius: usize,
+                                 requester: SwarmAgent<Loc>,
+                                 dest: A) -> SwarmResult<()> {
+        let rpc = IronSwarmRPC::GET(loc, radius, requester);
+        self.send_rpc(rpc, dest)
+    }
+
+    fn send_artifacts(&mut self, requester: ByteId, arts: Vec<SwarmArtifact<Loc, P>>,
+                      dest: SocketAddr) -> SwarmResult<()> {
+        let packets = self.sign_in_packets(arts, |arts| {
+            IronSwarmRPC::ARTIFACTS(requester.clone(), arts)
+        })?;
+        for signed in packets.into_iter() {
+            self.send_signed(signed, dest)?;
         }
         Ok(())
     }
-}
 
-
-// React to received RPC's.
-impl<Loc: Show + Location + Encodable + Decodable + PartialEq + Clone> SwarmNetwork<Loc> {
-    fn next_msg(&mut self) -> SwarmResult<IronSwarmRPC<Loc>> {
-        self.socket.recv_msg()
+    // Send to every destination, carrying on past the ones that fail, which
+    // send_signed counts. Returns the first error.
+    fn send_each<F>(&mut self, dests: &[SwarmAddr], mut send: F) -> SwarmResult<()>
+    where F: FnMut(&mut Self, &SwarmAddr) -> SwarmResult<()> {
+        let mut res = Ok(());
+        for dest in dests.iter() {
+            res = res.and(send(self, dest));
+        }
+        res
     }
 
-    fn dispatch_rpc(&mut self) -> SwarmResult<()> {
-        let rpc = try!(self.next_msg());
-
-        match rpc {
-            IronSwarmRPC::HRTBT(agn) => {
-                self.respond_to_heartbeat(agn);
-                Ok(())
-            }
-            IronSwarmRPC::HRTBTACK(ack_vec) => {
-                let new_neighbors: Vec<SwarmAgent<Loc>> = {
-                    let niter = self.neighbors.iter();
-                    ack_vec.
-                        into_iter().
-                        filter(|n| {
-                            *n != self.local_agent && niter.all(|old| *old != *n)
-                        }).collect()
-                };
-                self.neighbors.push_all(new_neighbors.as_slice());
-                Ok(())
-            }
-            IronSwarmRPC::JOIN(join_agn) => {
-                self.route_join_request(join_agn);
-                Ok(())
+    // Sign the RPCs made by rpc from the items, as many to a packet as fit.
+    // An item too large for a packet of its own is left out. At least one
+    // RPC is made, even if there are no items.
+    fn sign_in_packets<T, F>(&mut self, items: Vec<T>,
+                             rpc: F) -> SwarmResult<Vec<SignedRPC<Loc, P>>>
+    where T: Clone, F: Fn(Vec<T>) -> IronSwarmRPC<Loc, P> {
+        let mut packets = Vec::new();
+        let mut chunk: Vec<T> = Vec::new();
+        let mut signed = None;
+        for item in items.into_iter() {
+            chunk.push(item);
+            let next = self.sign_rpc(rpc(chunk.clone()))?;
+            if self.socket.packet_size(&next)? <= MAX_PACKET_SIZE {
+                signed = Some(next);
+                continue
             }
-            IronSwarmRPC::INFO(loc, msg) => {
-                self.route_info_msg(loc, msg)
-            }
-            IronSwarmRPC::BROADCAST(msg) => {
-                Ok(())
+
+            let item = chunk.pop().unwrap();
+            packets.extend(signed.take());
+            chunk = vec![item];
+            let next = self.sign_rpc(rpc(chunk.clone()))?;
+            if self.socket.packet_size(&next)? <= MAX_PACKET_SIZE {
+                signed = Some(next);
+            } else {
+                self.metrics.oversized_packet();
+                chunk.clear();
             }
         }
+        packets.extend(signed);
+        if packets.is_empty() {
+            packets.push(self.sign_rpc(rpc(Vec::new()))?);
+        }
+        Ok(packets)
     }
 
-    fn route_info_msg(&mut self, loc: Loc, msg: SwarmMsg<Loc>) -> SwarmResult<()> {
-        let next_agent = self.find_closest_neighbor(&loc);
-        match next_agent {
-            Some(agn) => {
-                if self.self_loc_is_closer(&agn, &loc) {
-                    // Send up to controller
-                    Ok(())
-                } else {
-                    self.send_info(loc, msg, agn.address())
-                }
-            }
-            None => {
-                // Send up to controller
-                Ok(())
-            }
-        }
+    fn send_get_neighbors<A: ToSocketAddrs>(&mut self, dest: A) -> SwarmResult<()> {
+        let rpc = IronSwarmRPC::GET_NEIGHBORS(self.local_agent.clone());
+        self.send_rpc(rpc, dest)
     }
 
-    fn respond_to_heartbeat(&mut self, agn: SwarmAgent<Loc>) {
-        if self.neighbors.len() >= MAX_NEIGHBORS as usize {
-            return
+    fn send_aggregate<A: ToSocketAddrs>(&mut self, name: String, mass: Mass,
+                                       dest: A) -> SwarmResult<()> {
+        let rpc = IronSwarmRPC::AGGREGATE(name, mass);
+        self.send_rpc(rpc, dest)
+    }
+
+    // Ask for broadcasts to be sent again. The request is padded to a full
+    // packet, as the answer is no larger than the request.
+    fn send_pull<A: ToSocketAddrs>(&mut self, ids: Vec<BroadcastId>,
+                                  dest: A) -> SwarmResult<()> {
+        let unpadded = self.sign_rpc(IronSwarmRPC::PULL(ids.clone(), Vec::new()))?;
+        let size = self.socket.packet_size(&unpadded)?;
+        let padding = vec![0u8; MAX_PACKET_SIZE.saturating_sub(size + PULL_SLACK)];
+        self.send_rpc(IronSwarmRPC::PULL(ids, padding), dest)
+    }
+
+    fn send_state<A: ToSocketAddrs>(&mut self, key: String, entry: Crdt,
+                                   dest: A) -> SwarmResult<()> {
+        let rpc = IronSwarmRPC::STATE(key, entry);
+        self.send_rpc(rpc, dest)
+    }
+
+    // Offer a random neighbor the ids of the latest broadcasts seen here, so
+    // that it can PULL those it missed. Should be called periodically.
+    pub fn sync_broadcasts(&mut self) -> SwarmResult<()> {
+        let dest = match self.random_neighbor() {
+            Some(dest) => dest,
+            None => return Ok(())
+        };
+        let rpc = IronSwarmRPC::DIGEST(self.broadcasts.digest(DIGEST_SIZE));
+        self.send_rpc(rpc, &dest)
+    }
+
+    // The address of a neighbor picked at random, for gossip.
+    fn random_neighbor(&self) -> Option<SwarmAddr> {
+        if self.neighbors.is_empty() {
+            return None
         }
+        let i = thread_rng().gen_range(0..self.neighbors.len());
+        Some(self.neighbors[i].address().clone())
+    }
 
-        {
-            let dest = agn.address();
-            let mut neighbors = self.neighbors.clone();
-            // Add the sending agent to the list of neighbors.
-            neighbors.push(self.local_agent.clone());
-            let res = self.send_heartbeat_ack(neighbors, dest);
+    pub fn shared_state(&self) -> &SharedState {
+        &self.state
+    }
+
+    // Updates made here reach the rest of the swarm through sync_state.
+    pub fn shared_state_mut(&mut self) -> &mut SharedState {
+        &mut self.state
+    }
 
-            if res.is_err() {
-                panic!("Err: {:?}", res.err().unwrap());
+    // Run one round of anti-entropy: send every entry of the shared state to
+    // a random neighbor, which merges it and sends back the merged entry if it
+    // knew more. An entry that fails to send does not hold up the others; the
+    // last error is returned.
+    pub fn sync_state(&mut self) -> SwarmResult<()> {
+        let dest = match self.random_neighbor() {
+            Some(dest) => dest,
+            None => return Ok(())
+        };
+        let mut res = Ok(());
+        for (key, entry) in self.state.entries().into_iter() {
+            match self.send_state(key, entry, &dest) {
+                Ok(()) => {}
+                Err(err) => res = Err(err)
             }
         }
+        res
+    }
 
-        if self.neighbors.iter().all(|n| *n != agn) {
-            self.neighbors.push(agn);
-        }
+    // Start computing the named aggregate, see gossip_aggregates. Every agent
+    // that contributes a value to it counts towards the result.
+    pub fn start_aggregate(&mut self, name: &str) {
+        self.aggregates.start(name);
     }
 
-    fn route_join_request(&mut self, join_agn: SwarmAgent<Loc>) {
-        let closest_agent = self.find_closest_neighbor(join_agn.location());
+    pub fn contribute(&mut self, name: &str, value: f64) {
+        self.aggregates.contribute(name, value);
+    }
 
-        match closest_agent {
-            Some(send_agn) => {
-                if !self.self_loc_is_closer(&send_agn, join_agn.location()) ||
-                    self.neighbors.len() >= MAX_NEIGHBORS as usize {
-                        self.send_join(join_agn, send_agn.address());
-                    } else {
-                        self.neighbors.push(join_agn);
+    // This agent's current estimate of the named aggregate, if it has heard
+    // of it.
+    pub fn aggregate(&self, name: &str) -> Option<Estimate> {
+        self.aggregates.estimate(name)
+    }
+
+    // Run one round of push-sum: send half of the mass of every aggregate to
+    // a random neighbor. The estimates converge as the rounds go on.
+    pub fn gossip_aggregates(&mut self) -> SwarmResult<()> {
+        let dest = match self.random_neighbor() {
+            Some(dest) => dest,
+            None => return Ok(())
+        };
+        let mut halves = self.aggregates.split().into_iter();
+        while let Some((name, mass)) = halves.next() {
+            match self.send_aggregate(name.clone(), mass, &dest) {
+                Ok(()) => {}
+                Err(err) => {
+                    // Take back the halves that were not sent, so that their
+                    // mass is not lost.
+                    self.aggregates.receive(name.as_str(), mass);
+                    for (name, mass) in halves {
+                        self.aggregates.receive(name.as_str(), mass);
                     }
+                    return Err(err)
+                }
             }
-            // TODO: Already have too many neighbors
-            None => {
-                self.neighbors.push(join_agn);
+        }
+        Ok(())
+    }
+
+    // Tell every neighbor about this agent and the leader it follows. Should
+    // be called periodically, well within the election timeout.
+    pub fn announce_leader(&mut self) -> SwarmResult<()> {
+        let now = self.now();
+        let mut announcements = vec![
+            Announcement::new(self.local_agent.clone(), &self.keypair, self.wall_now())
+        ];
+        match self.election {
+            Some(ref mut election) => {
+                election.update_at(&self.local_agent, now);
+                let relayed = election.leader().and_then(|l| election.announcement(l.id()));
+                announcements.extend(relayed.cloned());
             }
+            None => return Ok(())
         }
+
+        let addresses: Vec<SwarmAddr> = self.neighbors.iter().
+            map(|n| n.address().clone()).collect();
+        let mut res = Ok(());
+        for announcement in announcements.into_iter() {
+            res = res.and(self.send_each(addresses.as_slice(), |network, dest| {
+                network.send_rpc(IronSwarmRPC::LEADER(announcement.clone()), dest)
+            }));
+        }
+        res
     }
-}
 
-#[cfg(test)]
-mod test {
-    extern crate bincode;
-    use std::io::net::ip::{SocketAddr, Ipv4Addr};
-    use std::io::test::next_test_port;
-    use agent::{SwarmAgent};
-    use artifact::SwarmArtifact;
-    use swarm::socket::SwarmResult;
-    use swarm::SwarmMsg;
-    use Location;
-    use swarm::network::IronSwarmRPC;
-    use super::SwarmNetwork;
-    use bincode::{decode, encode};
+    fn send_task_msg<A: ToSocketAddrs>(&mut self, task_msg: TaskMsg<Loc>,
+                                      dest: A) -> SwarmResult<()> {
+        let mut msg = SwarmMsg::new_task_msg(self.local_agent.clone(), task_msg);
+        msg.sign(&self.keypair);
+        self.send_broadcast(0, msg, dest)
+    }
 
-    fn construct_artifact() -> SwarmArtifact<isize> {
-        let loc = 9is;
+    pub fn set_task_capacity(&mut self, capacity: usize) {
+        self.tasks.set_capacity(capacity);
+    }
 
-        SwarmArtifact::new(loc)
+    // Announce a task to the agents within radius of loc, who answer with
+    // bids. Bidding stays open until award_task is called.
+    pub fn announce_task(&mut self, loc: Loc, radius: usize,
+                         description: Vec<u8>) -> SwarmResult<TaskId> {
+        let task = Task::new(loc.clone(), radius, self.local_agent.clone(), description);
+        let id = task.id();
+        self.tasks.announce(&task);
+
+        let mut msg = SwarmMsg::new_task_msg(self.local_agent.clone(),
+                                             TaskMsg::Announce(task));
+        msg.sign(&self.keypair);
+
+        self.current_trace = Some(trace::new_trace_id());
+        let res = match self.next_hop("INFO", &loc) {
+            Some(agn) => self.send_info(loc, 0, msg, agn.address()),
+            None => self.spread_task(msg, 0)
+        };
+        self.current_trace = None;
+        res.map(|_| id)
     }
 
-    fn construct_agent() -> SwarmAgent<isize> {
-        SwarmAgent::new(9, local_socket())
+    // Award a task announced by this agent to the lowest bid, telling every
+    // bidder. Returns None while no bids have arrived.
+    pub fn award_task(&mut self, id: TaskId) -> SwarmResult<Option<SwarmAgent<Loc>>> {
+        let winner = match self.tasks.award(id) {
+            Some(winner) => winner,
+            None => return Ok(None)
+        };
+
+        // The award stands even if some bidders could not be told.
+        let addresses: Vec<SwarmAddr> = self.tasks.bidders(id).iter().
+            map(|b| b.address().clone()).collect();
+        let _ = self.send_each(addresses.as_slice(), |network, dest| {
+            network.send_task_msg(TaskMsg::Award(id, winner.clone()), dest)
+        });
+        Ok(Some(winner))
+    }
+
+    // Tasks awarded to this agent since the last call.
+    pub fn take_assigned_tasks(&mut self) -> Vec<Task<Loc>> {
+        self.tasks.take_assignments()
+    }
+
+    // Report the outcome of a task awarded to this agent to its announcer.
+    pub fn report_task(&mut self, id: TaskId, outcome: TaskOutcome) -> SwarmResult<()> {
+        match self.tasks.finish(id) {
+            Some(task) => {
+                let dest = task.announcer().address().clone();
+                self.send_task_msg(TaskMsg::Report(id, outcome), &dest)
+            }
+            None => Ok(())
+        }
+    }
+
+    pub fn task_status(&self, id: TaskId) -> Option<&TaskStatus<Loc>> {
+        self.tasks.status(id)
+    }
+
+    // Pass an announcement on to every neighbor, after hops hops.
+    fn spread_task(&mut self, msg: SwarmMsg<Loc, P>, hops: u8) -> SwarmResult<()> {
+        let addresses: Vec<SwarmAddr> = self.neighbors.iter().
+            map(|n| n.address().clone()).collect();
+        self.send_each(addresses.as_slice(), |network, dest| {
+            network.send_broadcast(hops, msg.clone(), dest)
+        })
+    }
+
+    // The neighbor table is sent in as many packets as it takes.
+    fn send_neighbors<A: ToSocketAddrs + Copy>(&mut self, dest: A) -> SwarmResult<()> {
+        let local_agent = self.local_agent.clone();
+        let neighbors = self.neighbors.clone();
+        let packets = self.sign_in_packets(neighbors, |agns| {
+            IronSwarmRPC::NEIGHBORS(local_agent.clone(), agns)
+        })?;
+        for signed in packets.into_iter() {
+            self.send_signed(signed, dest)?;
+        }
+        Ok(())
+    }
+
+    // Start enumerating the swarm from the agent at start, see poll_crawl.
+    // This agent need not be part of the swarm itself. A crawl in progress is
+    // abandoned.
+    pub fn start_crawl<A: ToSocketAddrs>(&mut self, start: A,
+                                        config: CrawlConfig) -> SwarmResult<()> {
+        if !config.is_valid() {
+            return Err(SwarmError::IoError(
+                io::Error::new(io::ErrorKind::InvalidInput, "crawl limits must not be zero")))
+        }
+        let addr = match start.to_socket_addrs()?.next() {
+            Some(addr) => addr,
+            None => return Err(SwarmError::IoError(
+                io::Error::new(io::ErrorKind::InvalidInput, "no address to crawl from")))
+        };
+        self.crawl = Some(Crawl::new(addr, config));
+        self.send_crawl_requests()
+    }
+
+    // Send as many GET_NEIGHBORS as the limits of the crawl allow.
+    fn send_crawl_requests(&mut self) -> SwarmResult<()> {
+        loop {
+            let now = self.now();
+            let next = match self.crawl {
+                Some(ref mut crawl) => crawl.next_request_at(now),
+                None => None
+            };
+            match next {
+                Some(addr) => self.send_get_neighbors(addr)?,
+                None => return Ok(())
+            }
+        }
+    }
+
+    // Advance the crawl, returning its result once every agent found has
+    // answered or timed out. The answers themselves arrive through
+    // dispatch_rpc.
+    pub fn poll_crawl(&mut self) -> SwarmResult<Option<CrawlResult<Loc>>> {
+        let now = self.now();
+        match self.crawl {
+            Some(ref mut crawl) => crawl.expire_at(now),
+            None => return Ok(None)
+        }
+        self.send_crawl_requests()?;
+
+        if self.crawl.as_ref().is_some_and(|c| c.is_done()) {
+            Ok(self.crawl.take().map(|c| c.into_result()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Store an artifact at the agent(s) closest to its location.
+    pub fn put_artifact(&mut self, art: SwarmArtifact<Loc, P>) -> SwarmResult<()> {
+        self.current_trace = Some(trace::new_trace_id());
+        let res = self.route_put(art);
+        self.current_trace = None;
+        res
+    }
+
+    // Ask the agent closest to loc for all artifacts within radius of loc. The
+    // answer is available from take_found_artifacts once it has arrived.
+    pub fn find_artifacts(&mut self, loc: Loc, radius: usize) -> SwarmResult<()> {
+        let agn = self.local_agent.clone();
+        self.current_trace = Some(trace::new_trace_id());
+        let res = self.route_get(loc, radius, agn, None);
+        self.current_trace = None;
+        res
+    }
+
+    fn route_put(&mut self, art: SwarmArtifact<Loc, P>) -> SwarmResult<()> {
+        match self.next_hop("PUT", art.location()) {
+            Some(agn) => self.send_put(art, agn.address()),
+            None => {
+                let replicas = self.find_nearest_neighbors(art.location(),
+                                                           self.replication);
+                for agn in replicas.iter() {
+                    self.send_store(art.clone(), agn.address())?;
+                }
+                self.store.insert_if_newer(art);
+                Ok(())
+            }
+        }
+    }
+
+    // Route a GET received from source, or sent by this agent. The answer
+    // goes back to where the GET came from, never to the address the
+    // requester claims, so that it can not be aimed at a third party.
+    fn route_get(&mut self, loc: Loc, radius: usize, requester: SwarmAgent<Loc>,
+                 source: Option<SocketAddr>) -> SwarmResult<()> {
+        let local = *requester.id() == *self.local_agent.id();
+        match self.next_hop("GET", &loc) {
+            Some(agn) => {
+                let reply_to = if local { None } else { source };
+                if (local || reply_to.is_some()) &&
+                    self.expect_artifacts(requester.id(), reply_to) {
+                    self.send_get(loc, radius, requester, agn.address())
+                } else {
+                    Ok(())
+                }
+            }
+            None => {
+                let arts: Vec<SwarmArtifact<Loc, P>> = self.store.
+                    within(&loc, radius).
+                    into_iter().
+                    cloned().
+                    collect();
+
+                if local {
+                    self.found.extend_from_slice(arts.as_slice());
+                    return Ok(())
+                }
+                match source {
+                    Some(src) => self.send_artifacts(requester.id().clone(), arts, src),
+                    None => Ok(())
+                }
+            }
+        }
+    }
+
+    pub fn join<A: ToSocketAddrs>(&mut self, address: A) {
+        let agn = self.local_agent.clone();
+        if self.neighbors.is_empty() {
+            self.join_started = Some(self.now());
+        }
+        let _ = self.send_join(agn, address);
+    }
+
+    // Broadcast a Custom event from this agent to its neighbors.
+    pub fn broadcast_custom(&mut self, data: Vec<u8>) -> SwarmResult<()> {
+        let mut msg = SwarmMsg::new_custom_msg(self.local_agent.clone(), data);
+        msg.sign(&self.keypair);
+        self.broadcasts.insert(broadcast_id(msg.signature.as_slice()), msg.clone(), true);
+
+        let addresses: Vec<SwarmAddr> = self.neighbors.iter().
+            map(|n| n.address().clone()).collect();
+        self.send_each(addresses.as_slice(), |network, dest| {
+            network.send_broadcast(0, msg.clone(), dest)
+        })
+    }
+
+    // Tell the neighbors of this agent, which are the agents around it, of a
+    // pheromone deposited at loc, see PheromoneField::update. The agent's own
+    // field is up to the caller.
+    pub fn deposit_pheromone(&mut self, loc: Loc, amount: f64) -> SwarmResult<()> {
+        let mut msg = SwarmMsg::new_pheromone_msg(self.local_agent.clone(), loc, amount);
+        msg.sign(&self.keypair);
+        self.broadcasts.insert(broadcast_id(msg.signature.as_slice()), msg.clone(), false);
+
+        let addresses: Vec<SwarmAddr> = self.neighbors.iter().
+            map(|n| n.address().clone()).collect();
+        self.send_each(addresses.as_slice(), |network, dest| {
+            network.send_broadcast(0, msg.clone(), dest)
+        })
+    }
+
+    pub fn heartbeat(&mut self) -> SwarmResult<()> {
+        let now = self.now();
+        self.reputation.decay_at(now);
+        let addresses: Vec<SwarmAddr> = self.neighbors.iter().
+            map(|n| n.address().clone()).collect();
+
+        let agn = self.local_agent.clone();
+        self.send_each(addresses.as_slice(), |network, dest| {
+            network.send_heartbeat(agn.clone(), dest)
+        })
+    }
+}
+
+
+// React to received RPC's.
+impl<Loc, P> SwarmNetwork<Loc, P>
+where Loc: Debug + Location + Serialize + DeserializeOwned + PartialEq + Clone,
+      P: Debug + Serialize + DeserializeOwned + PartialEq + Clone {
+    // Receive the next RPC, dropping it if it turns out to be forged.
+    fn next_msg(&mut self) -> SwarmResult<IronSwarmRPC<Loc, P>> {
+        self.current_trace = None;
+        self.current_source = None;
+        self.current_signer = None;
+        let signed: SignedRPC<Loc, P> = self.socket.recv_msg()?;
+        self.accept_rpc(signed)
+    }
+
+    fn accept_rpc(&mut self, signed: SignedRPC<Loc, P>) -> SwarmResult<IronSwarmRPC<Loc, P>> {
+        self.current_trace = Some(signed.trace_id);
+        self.current_source = self.socket.last_peer();
+        self.current_signer = self.neighbors.iter().
+            find(|n| n.public_key() == signed.public_key.as_slice()).
+            map(|n| n.id().clone());
+        if self.verify_rpc(&signed) {
+            self.trace(signed.rpc.name(), TraceDecision::Received);
+            self.learn_peers(&signed);
+            Ok(signed.rpc)
+        } else {
+            self.trace(signed.rpc.name(),
+                       TraceDecision::Rejected("invalid signature".to_string()));
+            self.penalize_signer(&signed);
+            Err(SwarmError::InvalidSignature)
+        }
+    }
+
+    // Remember the exchange key of the agent that signed an RPC, so that
+    // packets to it can be encrypted. Only the key of an agent describing itself in
+    // the RPC is bound, and only to the address the packet came from, so that
+    // nobody can divert the session keys of others.
+    fn learn_peers(&mut self, signed: &SignedRPC<Loc, P>) {
+        let signer = signed.public_key.as_slice();
+        let agents: Vec<&SwarmAgent<Loc>> = match signed.rpc {
+            IronSwarmRPC::HRTBT(ref agn) |
+            IronSwarmRPC::JOIN(ref agn) |
+            IronSwarmRPC::GET(_, _, ref agn) |
+            IronSwarmRPC::GET_NEIGHBORS(ref agn) |
+            IronSwarmRPC::NEIGHBORS(ref agn, _) => vec![agn],
+            IronSwarmRPC::LEADER(ref announcement) => vec![announcement.agent()],
+            IronSwarmRPC::HRTBTACK(ref agns) => agns.iter().collect(),
+            _ => Vec::new()
+        };
+
+        let src = match self.socket.last_peer() {
+            Some(src) => src,
+            None => return
+        };
+        let named = agents.into_iter().find(|agn| {
+            agn.public_key() == signer && agn.address().socket_addr() == src
+        });
+        if let Some(agn) = named {
+            let exchange_key = agn.exchange_key().to_vec();
+            self.socket.session_layer().add_peer(src, exchange_key.as_slice());
+        }
+    }
+
+    // Check the signature of the sending agent, that it is allowed to send the
+    // RPC, and that the agents and messages carried by the RPC are authentic.
+    // Only neighbors, or the agent an RPC is about, are listened to, and only
+    // RPCs signed recently.
+    fn verify_rpc(&self, signed: &SignedRPC<Loc, P>) -> bool {
+        let data = match signed.signed_data() {
+            Ok(data) => data,
+            Err(_) => return false
+        };
+
+        let signer = signed.public_key.as_slice();
+        if !identity::verify(signer, data.as_slice(), signed.signature.as_slice()) ||
+            !self.is_fresh(signed.timestamp) {
+            return false
+        }
+
+        match signed.rpc {
+            IronSwarmRPC::HRTBT(ref agn) |
+            IronSwarmRPC::GET_NEIGHBORS(ref agn) => {
+                agn.is_authentic() && agn.public_key() == signer
+            }
+            IronSwarmRPC::HRTBTACK(ref agns) => {
+                agns.iter().all(|a| a.is_authentic()) &&
+                    (self.is_neighbor_key(signer) ||
+                     agns.iter().any(|a| a.public_key() == signer))
+            }
+            // Forwarded on behalf of the agent they name.
+            IronSwarmRPC::JOIN(ref agn) |
+            IronSwarmRPC::GET(_, _, ref agn) => {
+                agn.is_authentic() &&
+                    (agn.public_key() == signer || self.is_neighbor_key(signer))
+            }
+            // Relayed announcements can not be altered.
+            IronSwarmRPC::LEADER(ref announcement) => announcement.verify(),
+            IronSwarmRPC::NEIGHBORS(ref agn, ref agns) => {
+                agn.is_authentic() &&
+                    agn.public_key() == signer &&
+                    agns.iter().all(|a| a.is_authentic())
+            }
+            // Signed by the agent they originate from.
+            IronSwarmRPC::INFO(_, _, ref msg) | IronSwarmRPC::BROADCAST(_, ref msg) => {
+                msg.verify()
+            }
+            IronSwarmRPC::ARTIFACTS(..) |
+            IronSwarmRPC::PUT(_) |
+            IronSwarmRPC::STORE(_) |
+            IronSwarmRPC::AGGREGATE(..) |
+            IronSwarmRPC::DIGEST(_) |
+            IronSwarmRPC::PULL(..) => {
+                self.is_neighbor_key(signer)
+            }
+            IronSwarmRPC::STATE(ref key, ref entry) => {
+                self.is_neighbor_key(signer) && entry.is_authentic(key.as_str())
+            }
+        }
+    }
+
+    fn is_neighbor_key(&self, key: &[u8]) -> bool {
+        self.neighbors.iter().any(|n| n.public_key() == key)
+    }
+
+    pub fn dispatch_rpc(&mut self) -> SwarmResult<()> {
+        let res = self.next_msg();
+        self.handle_rpc(res)
+    }
+
+    // Feed the packets received in a capture through this agent, in order, as
+    // if they arrived from the network. Packets that the agent sends in
+    // response are not sent but returned, so that the routing decisions of the
+    // captured agent can be reproduced. The agent should start out at the
+    // location and with the neighbors the captured agent had when the capture
+    // started. The times the packets were captured at stand in for the clock,
+    // so that rate limits, timeouts and last-seen times play out as they did.
+    pub fn replay(&mut self, capture: &[CapturedPacket]) -> Vec<CapturedPacket> {
+        self.socket.start_replay();
+        for packet in capture.iter() {
+            if packet.direction() != Direction::Received {
+                continue
+            }
+
+            self.replay_time = Some(packet.timestamp());
+            let res = match self.socket.decode_msg(packet.peer(), packet.data()) {
+                Ok(signed) => self.accept_rpc(signed),
+                Err(err) => Err(err)
+            };
+            let _ = self.handle_rpc(res);
+        }
+        self.replay_time = None;
+        self.socket.stop_replay()
+    }
+
+    // Crawl the swarm from the agent at start, handling the RPCs that arrive
+    // in the meantime, until the crawl is done.
+    pub fn crawl<A: ToSocketAddrs>(&mut self, start: A,
+                                  config: CrawlConfig) -> SwarmResult<CrawlResult<Loc>> {
+        self.start_crawl(start, config)?;
+        loop {
+            let _ = self.dispatch_rpc();
+            if let Some(result) = self.poll_crawl()? {
+                return Ok(result)
+            }
+        }
+    }
+
+    // Answer the next admin request, if one arrives within a short timeout.
+    pub fn poll_admin(&mut self) -> io::Result<()> {
+        let (req, src) = match self.admin {
+            Some(ref mut admin) => admin.recv_request()?,
+            None => return Ok(())
+        };
+
+        let response = self.admin_response(req);
+        match self.admin {
+            Some(ref mut admin) => admin.send_response(&response, src),
+            None => Ok(())
+        }
+    }
+
+    fn admin_response(&mut self, req: AdminRequest) -> AdminResponse<Loc, P> {
+        match req {
+            AdminRequest::Agent => AdminResponse::Agent(self.local_agent.clone()),
+            AdminRequest::Neighbors => AdminResponse::Neighbors(self.neighbor_table()),
+            AdminRequest::Artifacts => {
+                let arts = self.store.artifacts().into_iter().cloned().collect();
+                AdminResponse::Artifacts(arts)
+            }
+            AdminRequest::Counters => AdminResponse::Counters(self.counters()),
+            AdminRequest::Join(addr) => {
+                match addr.parse::<SocketAddr>() {
+                    Ok(addr) => {
+                        self.join(addr);
+                        AdminResponse::Done
+                    }
+                    Err(_) => AdminResponse::Error(format!("invalid address: {}", addr))
+                }
+            }
+            AdminRequest::Leave => {
+                self.leave();
+                AdminResponse::Done
+            }
+            AdminRequest::Broadcast(data) => {
+                match self.broadcast_custom(data) {
+                    Ok(()) => AdminResponse::Done,
+                    Err(e) => AdminResponse::Error(format!("{:?}", e))
+                }
+            }
+        }
+    }
+
+    fn handle_rpc(&mut self, res: SwarmResult<IronSwarmRPC<Loc, P>>) -> SwarmResult<()> {
+        let rpc = match res {
+            Ok(rpc) => rpc,
+            Err(err) => {
+                self.record_bad_packet(&err);
+                self.current_trace = None;
+                self.current_source = None;
+        self.current_signer = None;
+                return Err(err)
+            }
+        };
+
+        self.metrics.packet_received(rpc.name());
+        let res = self.route_rpc(rpc);
+        self.mark_last_peer_seen();
+        self.current_trace = None;
+        self.current_source = None;
+        self.current_signer = None;
+        res
+    }
+
+    fn route_rpc(&mut self, rpc: IronSwarmRPC<Loc, P>) -> SwarmResult<()> {
+        match rpc {
+            IronSwarmRPC::HRTBT(agn) => {
+                self.respond_to_heartbeat(agn);
+                Ok(())
+            }
+            // An ack may come in several packets, each carrying the agent
+            // that sent it.
+            IronSwarmRPC::HRTBTACK(ack_vec) => {
+                for agn in ack_vec.into_iter() {
+                    let known = agn.id() == self.local_agent.id() ||
+                        self.neighbors.iter().any(|old| old.id() == agn.id());
+                    if known || !self.reputation.is_trusted(agn.id()) {
+                        continue
+                    }
+                    if self.admit(&agn, false) {
+                        self.add_neighbor(agn);
+                    }
+                }
+                Ok(())
+            }
+            IronSwarmRPC::JOIN(join_agn) => {
+                self.route_join_request(join_agn);
+                Ok(())
+            }
+            IronSwarmRPC::INFO(loc, hops, msg) => {
+                self.observe_msg(&msg);
+                self.route_info_msg(loc, hops, msg)
+            }
+            IronSwarmRPC::BROADCAST(hops, msg) => {
+                if !self.record_broadcast(&msg) {
+                    self.metrics.broadcast_duplicate();
+                    return Ok(())
+                }
+                self.observe_msg(&msg);
+                self.deliver_task_msg(&msg, false, hops)
+            }
+            IronSwarmRPC::PUT(art) => {
+                self.route_put(art)
+            }
+            IronSwarmRPC::STORE(art) => {
+                self.store.insert_if_newer(art);
+                Ok(())
+            }
+            IronSwarmRPC::GET(loc, radius, requester) => {
+                let src = self.socket.last_peer();
+                self.route_get(loc, radius, requester, src)
+            }
+            // Only answers to GETs waited for are taken, and passed on to
+            // where the GET came from.
+            IronSwarmRPC::ARTIFACTS(requester, arts) => {
+                let now = self.now();
+                let reply_to = match self.finds.get(&requester) {
+                    Some(find) if find.expires > now => find.reply_to,
+                    _ => return Ok(())
+                };
+                match reply_to {
+                    Some(dest) => self.send_artifacts(requester, arts, dest),
+                    None => {
+                        self.found.extend_from_slice(arts.as_slice());
+                        Ok(())
+                    }
+                }
+            }
+            IronSwarmRPC::GET_NEIGHBORS(requester) => {
+                self.respond_to_get_neighbors(requester)
+            }
+            IronSwarmRPC::LEADER(announcement) => {
+                self.observe_candidate(announcement);
+                let now = self.now();
+                if let Some(ref mut election) = self.election {
+                    election.update_at(&self.local_agent, now);
+                }
+                Ok(())
+            }
+            // A replica of another type is not answered, or two agents that
+            // disagree on the type of an entry would answer each other forever.
+            // For the same reason the merged entry is only sent back if the
+            // sender would take anything from it.
+            IronSwarmRPC::STATE(key, entry) => {
+                let sender = match self.current_signer.clone() {
+                    Some(sender) => sender,
+                    None => return Ok(())
+                };
+                let now = self.wall_now();
+                if self.state.merge_at(key.as_str(), &entry, &sender, now).is_none() {
+                    return Ok(())
+                }
+                let news = self.state.news_for(key.as_str(), &entry, now).cloned();
+                match (self.socket.last_peer(), news) {
+                    (Some(src), Some(merged)) => self.send_state(key, merged, src),
+                    _ => Ok(())
+                }
+            }
+            IronSwarmRPC::DIGEST(ids) => {
+                let missing = self.broadcasts.missing(ids.as_slice());
+                match self.socket.last_peer() {
+                    Some(src) if !missing.is_empty() => {
+                        self.send_pull(missing, src)
+                    }
+                    _ => Ok(())
+                }
+            }
+            // The broadcasts sent again are no larger in total than the
+            // request, so that a PULL can not be used to amplify traffic.
+            IronSwarmRPC::PULL(ids, _) => {
+                let src = match self.socket.last_peer() {
+                    Some(src) => src,
+                    None => return Ok(())
+                };
+                let msgs: Vec<SwarmMsg<Loc, P>> = ids.iter().
+                    take(DIGEST_SIZE).
+                    filter_map(|&id| self.broadcasts.get(id)).
+                    filter(|msg| is_gossiped(*msg)).
+                    cloned().
+                    collect();
+                let mut budget = self.socket.last_packet_size();
+                for msg in msgs.into_iter() {
+                    let signed = self.sign_rpc(IronSwarmRPC::BROADCAST(0, msg))?;
+                    let size = self.socket.packet_size(&signed)?;
+                    if size > budget {
+                        break
+                    }
+                    budget -= size;
+                    self.send_signed(signed, src)?;
+                }
+                Ok(())
+            }
+            IronSwarmRPC::AGGREGATE(name, mass) => {
+                if mass.is_valid() {
+                    self.aggregates.receive(name.as_str(), mass);
+                }
+                Ok(())
+            }
+            // Only answers to a crawl in progress are of interest.
+            IronSwarmRPC::NEIGHBORS(agn, neighbors) => {
+                let src = self.socket.last_peer();
+                match (self.crawl.as_mut(), src) {
+                    (Some(crawl), Some(src)) => { crawl.answered(src, agn, neighbors); }
+                    _ => return Ok(())
+                }
+                self.send_crawl_requests()
+            }
+        }
+    }
+
+    // Neighbor tables are given out to anyone who asks, subject to the same
+    // blocklist and rate limit as JOINs. The answer goes to the address the
+    // request came from, so that it can not be aimed at a third party.
+    fn respond_to_get_neighbors(&mut self, requester: SwarmAgent<Loc>) -> SwarmResult<()> {
+        if self.blocklist.is_blocked_id(requester.id()) {
+            return Ok(())
+        }
+        let src = match self.socket.last_peer() {
+            Some(src) => src,
+            None => return Ok(())
+        };
+        let now = self.now();
+        if self.blocklist.is_blocked_ip(&src.ip()) ||
+            !self.rate_limiter.allow_at(src.ip(), now) {
+            return Ok(())
+        }
+
+        self.send_neighbors(src)
+    }
+
+    // Packets of another swarm or protocol version are only counted by the
+    // socket. The UDP source of a packet is easily forged, so packets that
+    // fail to decrypt or decode count against nobody, see penalize_signer.
+    fn record_bad_packet(&mut self, err: &SwarmError) {
+        match *err {
+            SwarmError::IoError(_) | SwarmError::IncompatiblePacket => return,
+            _ => {}
+        }
+        self.metrics.decode_failure();
+    }
+
+    // A rejected RPC that was signed nonetheless counts against the neighbor
+    // holding the key it was signed with.
+    fn penalize_signer(&mut self, signed: &SignedRPC<Loc, P>) {
+        let data = match signed.signed_data() {
+            Ok(data) => data,
+            Err(_) => return
+        };
+        let signer = signed.public_key.as_slice();
+        if !identity::verify(signer, data.as_slice(), signed.signature.as_slice()) {
+            return
+        }
+
+        let peer = self.neighbors.iter().
+            find(|n| n.public_key() == signer).
+            map(|n| n.id().clone());
+        if let Some(id) = peer {
+            self.reputation.violation(&id, Violation::BadPacket);
+            self.prune_untrusted();
+        }
+    }
+
+    // Record a broadcast in the history, returning whether it is new.
+    fn record_broadcast(&mut self, msg: &SwarmMsg<Loc, P>) -> bool {
+        let id = broadcast_id(msg.signature.as_slice());
+        self.broadcasts.insert(id, msg.clone(), is_gossiped(msg))
+    }
+
+    // Reports only count from neighbors, as anyone can make up identities to
+    // report from.
+    fn observe_msg(&mut self, msg: &SwarmMsg<Loc, P>) {
+        if let SwarmEvent::MaliciousAgent(ref mal) = *msg.event() {
+            let reporter = msg.from_agent().id();
+            if self.neighbors.iter().any(|n| n.id() == reporter) {
+                let now = self.now();
+                self.reputation.report_at(reporter, mal.id(), now);
+                self.prune_untrusted();
+            }
+        }
+    }
+
+    // Take part in task allocation. The agent an announcement is routed to
+    // floods it to its neighbors, and agents within the radius of the task
+    // pass it on until it has travelled MAX_TASK_HOPS hops.
+    fn deliver_task_msg(&mut self, msg: &SwarmMsg<Loc, P>,
+                        routed: bool, hops: u8) -> SwarmResult<()> {
+        let task_msg = match *msg.event() {
+            SwarmEvent::Task(ref task_msg) => task_msg,
+            _ => return Ok(())
+        };
+        let from = msg.from_agent();
+
+        match *task_msg {
+            TaskMsg::Announce(ref task) => {
+                if task.announcer().id() != from.id() || !self.tasks.first_seen(task) {
+                    return Ok(())
+                }
+                let within = self.local_agent.location().
+                    distance(task.location()) <= task.radius();
+                let spread = if routed {
+                    self.spread_task(msg.clone(), 0)
+                } else if within && hops < MAX_TASK_HOPS {
+                    self.spread_task(msg.clone(), hops + 1)
+                } else {
+                    Ok(())
+                };
+                let bid = match self.tasks.bid(&self.local_agent, task) {
+                    Some(cost) => self.send_bid(task, cost),
+                    None => Ok(())
+                };
+                spread.and(bid)
+            }
+            TaskMsg::Bid(id, cost) => {
+                self.tasks.receive_bid(id, from.clone(), cost);
+                Ok(())
+            }
+            TaskMsg::Award(id, ref winner) => {
+                self.tasks.receive_award(&self.local_agent, from, id, winner);
+                Ok(())
+            }
+            TaskMsg::Report(id, ref outcome) => {
+                self.tasks.receive_report(from, id, outcome.clone());
+                Ok(())
+            }
+        }
+    }
+
+    // A bid goes straight to the announcer only if the address of the
+    // announcer is known to be its own, because it is a neighbor or sent the
+    // announcement itself. Otherwise it is routed towards the location of the
+    // announcer, so that bids can not be drawn to a made-up address.
+    fn send_bid(&mut self, task: &Task<Loc>, cost: u64) -> SwarmResult<()> {
+        let announcer = task.announcer();
+        let verified = self.socket.last_peer() == Some(announcer.address().socket_addr()) ||
+            self.neighbors.iter().any(|n| n.id() == announcer.id() &&
+                                      n.address() == announcer.address());
+        if verified {
+            return self.send_task_msg(TaskMsg::Bid(task.id(), cost), announcer.address())
+        }
+
+        let mut msg = SwarmMsg::new_task_msg(self.local_agent.clone(),
+                                             TaskMsg::Bid(task.id(), cost));
+        msg.sign(&self.keypair);
+        self.route_info_msg(announcer.location().clone(), 0, msg)
+    }
+
+    fn route_info_msg(&mut self, loc: Loc, hops: u8,
+                      msg: SwarmMsg<Loc, P>) -> SwarmResult<()> {
+        match self.next_hop("INFO", &loc) {
+            Some(agn) => self.send_info(loc, hops.saturating_add(1), msg, agn.address()),
+            None => {
+                // Send up to controller
+                self.metrics.info_delivered(hops);
+                self.deliver_task_msg(&msg, true, 0)
+            }
+        }
+    }
+
+    fn respond_to_heartbeat(&mut self, agn: SwarmAgent<Loc>) {
+        self.check_location_jump(&agn);
+        if !self.reputation.is_trusted(agn.id()) || !self.admit(&agn, false) {
+            return
+        }
+
+        // Keep the neighbor's location up to date, even when no new neighbors
+        // are taken, so that the next jump is measured from here.
+        let known = self.neighbors.iter().position(|n| n.id() == agn.id());
+        match known {
+            Some(i) => self.neighbors[i] = agn.clone(),
+            None if self.neighbors.len() >= MAX_NEIGHBORS as usize => return,
+            None => {}
+        }
+
+        // An ack that could not be sent is counted and dropped; the neighbor
+        // asks again with its next heartbeat.
+        let neighbors = self.neighbors.clone();
+        let _ = self.send_heartbeat_ack(neighbors, agn.address());
+
+        if known.is_none() {
+            self.add_neighbor(agn);
+        }
+    }
+
+    // Candidates are held to the same work as joining agents. A candidate is
+    // heard of when its announcement was made, and announcements dated
+    // further ahead than the clocks of two agents may differ are dropped.
+    fn observe_candidate(&mut self, announcement: Announcement<Loc>) {
+        let agn = announcement.agent();
+        if agn.id().leading_zeros() < self.required_work ||
+            self.blocklist.is_blocked_id(agn.id()) ||
+            !self.reputation.is_trusted(agn.id()) {
+            return
+        }
+
+        let wall_now = self.wall_now();
+        if announcement.timestamp() > wall_now.saturating_add(MAX_RPC_AGE) {
+            return
+        }
+        let age = wall_now.saturating_sub(announcement.timestamp());
+        let heard = self.now().saturating_sub(age);
+        if let Some(ref mut election) = self.election {
+            election.observe_at(&self.local_agent, announcement, heard);
+        }
+    }
+
+    fn route_join_request(&mut self, join_agn: SwarmAgent<Loc>) {
+        if !self.reputation.is_trusted(join_agn.id()) ||
+            !self.admit(&join_agn, true) {
+            return
+        }
+
+        let closest_agent = self.find_closest_neighbor(join_agn.location());
+
+        match closest_agent {
+            Some(send_agn) if !self.self_loc_is_closer(&send_agn, join_agn.location()) ||
+                self.neighbors.len() >= MAX_NEIGHBORS as usize => {
+                let _ = self.send_join(join_agn, send_agn.address());
+            }
+            // TODO: Already have too many neighbors
+            _ => {
+                self.add_neighbor(join_agn);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+    use std::net::UdpSocket;
+    use agent::{SwarmAgent};
+    use artifact::SwarmArtifact;
+    use swarm::socket::SwarmResult;
+    use swarm::SwarmMsg;
+    
+    use identity::Keypair;
+    use swarm::network::IronSwarmRPC;
+    use swarm::socket::SwarmError;
+    use swarm::session::Encryption;
+    use swarm::capture::{CaptureWriter, CapturedPacket, read_capture};
+    use swarm::admin::{self, AdminRequest, AdminResponse};
+    use swarm::trace::{TraceLog, TraceDecision};
+    use swarm::topology::Topology;
+    use swarm::crawler::CrawlConfig;
+    use swarm::aggregate::Mass;
+    use swarm::crdt::{Crdt, GCounter, LwwRegister};
+    use swarm::election::Announcement;
+    use swarm::task::{Task, TaskMsg, TaskStatus, TaskOutcome};
+    use Coordinates;
+    use pheromone::PheromoneField;
+    use byteid::ByteId;
+    use swarm::{SwarmEvent, SwarmController};
+    use ReactToSwarm;
+    use tempfile::TempDir;
+    use std::thread;
+    use swarm::admission::Admission;
+    use super::{SwarmNetwork, SignedRPC, packet_to_json, MAX_TASK_HOPS, MAX_RPC_AGE};
+    use clock::wall_clock_ms;
+    use codec::{Codec, Bincode, Json, MessagePack, WireFormat};
+
+    fn construct_artifact() -> SwarmArtifact<isize, ()> {
+        let loc = 9isize;
+
+        SwarmArtifact::new(loc, ())
+    }
+
+    fn construct_agent() -> SwarmAgent<isize> {
+        SwarmAgent::with_keypair(9, local_socket(), &Keypair::generate())
+    }
+
+    fn construct_swarm_msg() -> SwarmMsg<isize, ()> {
+        let keypair = Keypair::generate();
+        let agent = SwarmAgent::with_keypair(9, local_socket(), &keypair);
+        let mut msg = SwarmMsg::new_artifact_msg(agent, construct_artifact());
+        msg.sign(&keypair);
+        msg
+    }
+
+    fn construct_custom_msg() -> SwarmMsg<isize, ()> {
+        let keypair = Keypair::generate();
+        let agent = SwarmAgent::with_keypair(9, local_socket(), &keypair);
+        let mut msg = SwarmMsg::new_typed_msg(agent, &(7u8, 1000u32)).unwrap();
+        msg.sign(&keypair);
+        msg
+    }
+
+    fn construct_task_msg() -> SwarmMsg<isize, ()> {
+        let keypair = Keypair::generate();
+        let agent = SwarmAgent::with_keypair(9, local_socket(), &keypair);
+        let task = Task::new(10, 2, agent.clone(), vec![1u8]);
+        let mut msg = SwarmMsg::new_task_msg(agent, TaskMsg::Announce(task));
+        msg.sign(&keypair);
+        msg
+    }
+
+    fn construct_network(loc: isize) -> SwarmNetwork<isize, ()> {
+        SwarmNetwork::new(loc, local_socket())
+    }
+
+    fn local_socket() -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], 0))
+    }
+
+    fn codec_rpc_tester<C: Codec>(codec: &C, rpc: IronSwarmRPC<isize, ()>) {
+        let orig_rpc = rpc.clone();
+        let encoded = codec.encode(&rpc).ok().unwrap();
+        let dec_rpc: IronSwarmRPC<isize, ()> =
+            codec.decode(encoded.as_slice()).ok().unwrap();
+        assert_eq!(orig_rpc, dec_rpc);
+    }
+
+    fn codec_tester<C: Codec>(codec: C) {
+        let ack_vec = vec![construct_agent()];
+
+        codec_rpc_tester(&codec, IronSwarmRPC::HRTBT(construct_agent()));
+        codec_rpc_tester(&codec, IronSwarmRPC::HRTBTACK(ack_vec));
+        codec_rpc_tester(&codec, IronSwarmRPC::JOIN(construct_agent()));
+        codec_rpc_tester(&codec, IronSwarmRPC::INFO(10, 0, construct_swarm_msg()));
+        codec_rpc_tester(&codec, IronSwarmRPC::BROADCAST(0, construct_swarm_msg()));
+        codec_rpc_tester(&codec, IronSwarmRPC::INFO(10, 0, construct_custom_msg()));
+        codec_rpc_tester(&codec, IronSwarmRPC::BROADCAST(0, construct_custom_msg()));
+        codec_rpc_tester(&codec, IronSwarmRPC::INFO(10, 0, construct_task_msg()));
+        codec_rpc_tester(&codec, IronSwarmRPC::PUT(construct_artifact()));
+        codec_rpc_tester(&codec, IronSwarmRPC::STORE(construct_artifact()));
+        codec_rpc_tester(&codec, IronSwarmRPC::GET(10, 2, construct_agent()));
+        codec_rpc_tester(&codec, IronSwarmRPC::ARTIFACTS(ByteId::random_id(),
+                                                         vec![construct_artifact()]));
+        codec_rpc_tester(&codec, IronSwarmRPC::GET_NEIGHBORS(construct_agent()));
+        let keypair = Keypair::generate();
+        let agent = SwarmAgent::with_keypair(9, local_socket(), &keypair);
+        let announcement = Announcement::new(agent, &keypair, 0);
+        codec_rpc_tester(&codec, IronSwarmRPC::LEADER(announcement));
+        codec_rpc_tester(&codec, IronSwarmRPC::AGGREGATE("battery".to_string(), Mass {
+            value: 0.75,
+            count: 1.0,
+            weight: 0.5
+        }));
+        codec_rpc_tester(&codec, IronSwarmRPC::STATE("visits".to_string(),
+                                                     Crdt::Counter(GCounter::new())));
+        codec_rpc_tester(&codec, IronSwarmRPC::DIGEST(vec![1u64, 2, 3]));
+        codec_rpc_tester(&codec, IronSwarmRPC::PULL(vec![2u64], vec![0u8; 4]));
+        codec_rpc_tester(&codec, IronSwarmRPC::NEIGHBORS(construct_agent(),
+                                                         vec![construct_agent()]));
+    }
+
+    fn send_broadcast_tester(from_nework: &mut SwarmNetwork<isize, ()>,
+                           to_network: &mut SwarmNetwork<isize, ()>) -> SwarmResult<()> {
+        let msg = construct_swarm_msg();
+        let exp_rpc = IronSwarmRPC::BROADCAST(0, msg.clone());
+
+        from_nework.send_broadcast(0, msg, to_network.address())?;
+        let recv_rpc = to_network.next_msg()?;
+
+        assert_eq!(exp_rpc, recv_rpc);
+        Ok(())
+    }
+
+    fn next_msg_rpc_tester(from_network: &mut SwarmNetwork<isize, ()>,
+                           to_network: &mut SwarmNetwork<isize, ()>,
+                           rpc: IronSwarmRPC<isize, ()>) -> SwarmResult<()> {
+        let orig_rpc = rpc.clone();
+        let net_sock = to_network.address();
+        from_network.send_rpc(rpc, net_sock)?;
+
+        let dec_rpc = to_network.next_msg()?;
+        assert_eq!(orig_rpc, dec_rpc);
+        Ok(())
+    }
+
+    #[test]
+    fn next_msg_test() {
+        let ack_vec = vec![construct_agent()];
+        let mut from_network = construct_network(0isize);
+        let mut to_network = construct_network(1isize);
+
+        let hrtbt_agent = from_network.local_agent.clone();
+        let res = next_msg_rpc_tester(&mut from_network, &mut to_network,
+                                      IronSwarmRPC::HRTBT(hrtbt_agent));
+        assert!(res.is_ok());
+
+        // Only neighbors send RPCs about other agents, or about the swarm.
+        let res = next_msg_rpc_tester(&mut from_network, &mut to_network,
+                                      IronSwarmRPC::JOIN(construct_agent()));
+        assert!(res.is_err());
+        let res = next_msg_rpc_tester(&mut from_network, &mut to_network,
+                                      IronSwarmRPC::PUT(construct_artifact()));
+        assert!(res.is_err());
+        to_network.neighbors.push(from_network.local_agent.clone());
+
+        let res = next_msg_rpc_tester(&mut from_network, &mut to_network,
+                                      IronSwarmRPC::HRTBTACK(ack_vec));
+        assert!(res.is_ok());
+        let res = next_msg_rpc_tester(&mut from_network, &mut to_network,
+                                      IronSwarmRPC::JOIN(construct_agent()));
+        assert!(res.is_ok());
+        let res = next_msg_rpc_tester(&mut from_network, &mut to_network,
+                                      IronSwarmRPC::INFO(10, 0, construct_swarm_msg()));
+        assert!(res.is_ok());
+        let res = next_msg_rpc_tester(&mut from_network, &mut to_network,
+                                      IronSwarmRPC::BROADCAST(0, construct_swarm_msg()));
+        assert!(res.is_ok());
+        let res = next_msg_rpc_tester(&mut from_network, &mut to_network,
+                                      IronSwarmRPC::PUT(construct_artifact()));
+        assert!(res.is_ok());
+        let res = next_msg_rpc_tester(&mut from_network, &mut to_network,
+                                      IronSwarmRPC::GET(10, 2, construct_agent()));
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn codec_test() {
+        codec_tester(Bincode);
+        codec_tester(Json);
+        codec_tester(MessagePack);
+    }
+
+    #[test]
+    fn send_rpc_test() {
+        let mut network_to = construct_network(0isize);
+        let mut network_from = construct_network(10isize);
+
+        let res = send_broadcast_tester(&mut network_from, &mut network_to);
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn wire_format_test() {
+        let mut network_to = construct_network(0isize);
+        let mut network_from = construct_network(10isize);
+        network_to.set_wire_format(WireFormat::MessagePack);
+        network_from.set_wire_format(WireFormat::MessagePack);
+
+        // Signatures are independent of the wire format.
+        let res = send_broadcast_tester(&mut network_from, &mut network_to);
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn json_heartbeat_test() {
+        let mut network1 = construct_network(0isize);
+        let mut network2 = construct_network(10isize);
+        let others = [construct_agent(), construct_agent()];
+        for network in [&mut network1, &mut network2].iter_mut() {
+            network.set_wire_format(WireFormat::Json);
+            network.set_encryption(Encryption::PreShared(b"swarm key".to_vec()));
+        }
+        network1.neighbors.extend(others.iter().cloned());
+        network1.neighbors.push(network2.local_agent.clone());
+        network2.neighbors.push(network1.local_agent.clone());
+
+        // A full list of neighbors is acked in JSON, in one packet or more.
+        assert!(network2.heartbeat().is_ok());
+        assert!(network1.dispatch_rpc().is_ok());
+        assert!(network1.metrics.sent("HRTBTACK") >= 1);
+        assert_eq!(network1.metrics.oversized_packets(), 0);
+        for _ in 0..network1.metrics.sent("HRTBTACK") {
+            assert!(network2.dispatch_rpc().is_ok());
+        }
+        assert_eq!(network2.neighbors.len(), 3);
+        for other in others.iter() {
+            assert!(network2.neighbors.iter().any(|n| n.id() == other.id()));
+        }
+    }
+
+    #[test]
+    fn packet_to_json_test() {
+        let mut network = construct_network(10isize);
+        let capture = UdpSocket::bind(local_socket()).ok().unwrap();
+        let dest = capture.local_addr().ok().unwrap();
+        network.set_wire_format(WireFormat::MessagePack);
+
+        let agent = network.local_agent.clone();
+        assert!(network.send_join(agent, dest).is_ok());
+        let mut buf = [0u8; 1024];
+        let (amt, _) = capture.recv_from(&mut buf).ok().unwrap();
+
+        let json = packet_to_json::<isize, ()>(&buf[..amt]).ok().unwrap();
+        assert!(json.contains("\"JOIN\""));
+        assert!(json.contains("\"format\": \"MessagePack\""));
+
+        network.set_encryption(Encryption::PreShared(b"swarm key".to_vec()));
+        let agent = network.local_agent.clone();
+        assert!(network.send_join(agent, dest).is_ok());
+        let (amt, _) = capture.recv_from(&mut buf).ok().unwrap();
+        match packet_to_json::<isize, ()>(&buf[..amt]) {
+            Err(SwarmError::DecryptionFailed) => {}
+            _ => panic!("expected decryption to fail")
+        }
+    }
+
+    #[test]
+    fn join_self_closest_test() {
+        let mut network1 = construct_network(0isize);
+        let network2 = construct_network(10isize);
+        let mut joining = construct_network(2isize);
+        network1.neighbors.push(network2.local_agent.clone());
+
+        joining.join(network1.address());
+        let res = network1.dispatch_rpc();
+        assert!(res.is_ok());
+
+        assert_eq!(network1.neighbors.len(), 2);
+    }
+
+    #[test]
+    fn capture_replay_test() {
+        let dir = TempDir::new().ok().unwrap();
+        let path = dir.path().join("swarm.cap");
+        let mut network1 = construct_network(0isize);
+        let mut network2 = construct_network(10isize);
+        let mut joining = construct_network(9isize);
+        network1.neighbors.push(network2.local_agent.clone());
+        network1.set_capture(Some(CaptureWriter::create(&path).ok().unwrap()));
+
+        joining.join(network1.address());
+        assert!(network1.dispatch_rpc().is_ok());
+        network1.set_capture(None);
+        let capture = read_capture(&path).ok().unwrap();
+        assert_eq!(capture.len(), 2);
+
+        // An agent in the same situation makes the same routing decision.
+        let mut replayed = construct_network(0isize);
+        replayed.neighbors.push(network2.local_agent.clone());
+        let sent = replayed.replay(capture.as_slice());
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].peer(), network2.address());
+        assert_eq!(sent[0].peer(), capture[1].peer());
+        assert_eq!(replayed.neighbors.len(), 1);
+
+        let forwarded: SignedRPC<isize, ()> =
+            network2.socket.decode_msg(sent[0].peer(), sent[0].data()).ok().unwrap();
+        assert_eq!(forwarded.rpc, IronSwarmRPC::JOIN(joining.local_agent.clone()));
+    }
+
+    #[test]
+    fn replay_clock_test() {
+        let dir = TempDir::new().ok().unwrap();
+        let path = dir.path().join("swarm.cap");
+        let mut network1 = construct_network(0isize);
+        let mut joining1 = construct_network(9isize);
+        let mut joining2 = construct_network(1isize);
+        network1.set_capture(Some(CaptureWriter::create(&path).ok().unwrap()));
+
+        joining1.join(network1.address());
+        assert!(network1.dispatch_rpc().is_ok());
+        joining2.join(network1.address());
+        assert!(network1.dispatch_rpc().is_ok());
+        network1.set_capture(None);
+        let capture = read_capture(&path).ok().unwrap();
+        let t0 = capture[0].timestamp();
+        let at = |timestamps: [u64; 2]| -> Vec<CapturedPacket> {
+            capture.iter().zip(timestamps.iter()).
+                map(|(p, &t)| CapturedPacket::at(t, p.direction(), p.peer(), p.data().to_vec())).
+                collect()
+        };
+
+        // Both JOINs come from the same address, which may only send one per
+        // second.
+        let mut replayed = construct_network(0isize);
+        replayed.set_rate_limit(1, 1000);
+        replayed.replay(at([t0, t0 + 500]).as_slice());
+        assert_eq!(replayed.neighbors.len(), 1);
+
+        let mut replayed = construct_network(0isize);
+        replayed.set_rate_limit(1, 1000);
+        replayed.replay(at([t0, t0 + 1000]).as_slice());
+        assert_eq!(replayed.neighbors.len(), 2);
+        assert_eq!(replayed.last_seen.values().cloned().max(), Some(t0 + 1000));
+
+        // RPCs are as old as the capture says they are.
+        let mut replayed = construct_network(0isize);
+        replayed.replay(at([t0 + MAX_RPC_AGE + 1000, t0 + MAX_RPC_AGE + 1000]).as_slice());
+        assert_eq!(replayed.neighbors.len(), 0);
+    }
+
+    #[test]
+    fn admin_test() {
+        let mut network1 = construct_network(0isize);
+        let mut network2 = construct_network(10isize);
+        let admin_addr = network1.enable_admin(local_socket()).ok().unwrap();
+
+        network2.join(network1.address());
+        assert!(network1.dispatch_rpc().is_ok());
+        let table = network1.neighbor_table();
+        assert_eq!(table.len(), 1);
+        assert!(table[0].last_seen_ms_ago.is_some());
+        assert_eq!(network1.counters().neighbors, 1);
+
+        let client = thread::spawn(move || {
+            admin::request(admin_addr, &AdminRequest::Neighbors)
+        });
+        let mut answered = false;
+        for _ in 0usize..20 {
+            if network1.poll_admin().is_ok() {
+                answered = true;
+                break
+            }
+        }
+        assert!(answered);
+        let text = client.join().ok().unwrap().ok().unwrap();
+        assert!(text.contains("last_seen_ms_ago"));
+
+        match network1.admin_response(AdminRequest::Join("not an address".to_string())) {
+            AdminResponse::Error(_) => {}
+            _ => panic!("expected an error")
+        }
+        match network1.admin_response(AdminRequest::Leave) {
+            AdminResponse::Done => {}
+            _ => panic!("expected leave to succeed")
+        }
+        assert_eq!(network1.neighbors.len(), 0);
+    }
+
+    #[test]
+    fn broadcast_custom_test() {
+        let mut network1 = construct_network(0isize);
+        let mut network2 = construct_network(10isize);
+        network1.neighbors.push(network2.local_agent.clone());
+
+        assert!(network1.broadcast_custom(vec![1u8, 2, 3]).is_ok());
+        match network2.next_msg() {
+            Ok(IronSwarmRPC::BROADCAST(_, msg)) => {
+                assert_eq!(*msg.event(), SwarmEvent::Custom(vec![1u8, 2, 3]));
+                assert!(msg.verify());
+            }
+            _ => panic!("expected a broadcast")
+        }
+    }
+
+    #[test]
+    fn deposit_pheromone_test() {
+        let mut network1 = construct_network(0isize);
+        let mut network2 = construct_network(1isize);
+        network1.neighbors.push(network2.local_agent.clone());
+        network2.neighbors.push(network1.local_agent.clone());
+        let mut field = PheromoneField::new(0.1, 0.1).unwrap();
+
+        assert!(network1.deposit_pheromone(1isize, 2.5).is_ok());
+        match network2.next_msg() {
+            Ok(IronSwarmRPC::BROADCAST(_, msg)) => field.update(&msg),
+            _ => panic!("expected a broadcast")
+        }
+        assert_eq!(field.intensity(&1isize), 2.5);
+
+        // Deposits are not offered in digests.
+        assert!(network1.broadcasts.digest(10).is_empty());
+    }
+
+    #[test]
+    fn metrics_test() {
+        let mut network1 = construct_network(0isize);
+        let mut joining = construct_network(10isize);
+
+        joining.join(network1.address());
+        assert!(network1.dispatch_rpc().is_ok());
+        assert_eq!(joining.metrics().sent("JOIN"), 1);
+        assert_eq!(network1.metrics().received("JOIN"), 1);
+        assert_eq!(network1.metrics().neighbor_churn(), (1, 0));
+
+        // The join completes once the joining agent learns of a neighbor.
+        assert!(network1.heartbeat().is_ok());
+        assert!(joining.dispatch_rpc().is_ok());
+        assert_eq!(joining.metrics().join_latency().count(), 1);
+
+        joining.leave();
+        assert_eq!(joining.metrics().neighbor_churn(), (1, 1));
+
+        let text = network1.metrics().to_prometheus(network1.rejected_packets());
+        assert!(text.contains("rpc=\"JOIN\""));
+    }
+
+    #[test]
+    fn info_hops_test() {
+        let mut network1 = construct_network(1isize);
+        let mut network2 = construct_network(2isize);
+        let mut network3 = construct_network(3isize);
+        network3.neighbors.push(network2.local_agent.clone());
+        network2.neighbors.push(network1.local_agent.clone());
+
+        let loc = *network1.local_agent.location();
+        assert!(network3.route_info_msg(loc, 0, construct_swarm_msg()).is_ok());
+        assert!(network2.dispatch_rpc().is_ok());
+        assert!(network1.dispatch_rpc().is_ok());
+
+        assert_eq!(network1.metrics().info_hops().count(), 1);
+        assert_eq!(network1.metrics().info_hops().sum(), 2.0);
+        assert_eq!(network2.metrics().info_hops().count(), 0);
+    }
+
+    #[test]
+    fn broadcast_duplicate_test() {
+        let mut network1 = construct_network(0isize);
+        let mut network2 = construct_network(10isize);
+        let msg = construct_swarm_msg();
+
+        let dest = network2.address();
+        assert!(network1.send_broadcast(0, msg.clone(), dest).is_ok());
+        assert!(network1.send_broadcast(0, msg, dest).is_ok());
+        assert!(network2.dispatch_rpc().is_ok());
+        assert!(network2.dispatch_rpc().is_ok());
+
+        assert_eq!(network2.metrics().received("BROADCAST"), 2);
+        assert_eq!(network2.metrics().broadcast_duplicates(), 1);
+    }
+
+    #[test]
+    fn trace_test() {
+        let mut network1 = construct_network(1isize);
+        let mut network2 = construct_network(2isize);
+        let mut network3 = construct_network(3isize);
+        network3.neighbors.push(network2.local_agent.clone());
+        network2.neighbors.push(network1.local_agent.clone());
+
+        let log = TraceLog::new();
+        network1.set_tracer(Box::new(log.clone()));
+        network2.set_tracer(Box::new(log.clone()));
+        network3.set_tracer(Box::new(log.clone()));
+
+        network3.current_trace = Some(42);
+        let loc = *network1.local_agent.location();
+        assert!(network3.route_info_msg(loc, 0, construct_swarm_msg()).is_ok());
+        assert!(network2.dispatch_rpc().is_ok());
+        assert!(network1.dispatch_rpc().is_ok());
+
+        let path = log.path(42);
+        let agents: Vec<ByteId> = path.iter().map(|e| e.agent.clone()).collect();
+        assert_eq!(agents, vec![network3.local_agent.id().clone(),
+                                network2.local_agent.id().clone(),
+                                network2.local_agent.id().clone(),
+                                network1.local_agent.id().clone(),
+                                network1.local_agent.id().clone()]);
+        assert!(path.iter().all(|e| e.rpc == "INFO"));
+
+        match path[2].decision {
+            TraceDecision::Forwarded { ref next_hop, self_distance, next_hop_distance } => {
+                assert_eq!(*next_hop, *network1.local_agent.address());
+                assert_eq!((self_distance, next_hop_distance), (1, 0));
+            }
+            _ => panic!("expected the INFO to be forwarded")
+        }
+        match path[4].decision {
+            TraceDecision::Delivered { self_distance, .. } => assert_eq!(self_distance, 0),
+            _ => panic!("expected the INFO to be delivered")
+        }
+        assert_eq!(path[3].decision, TraceDecision::Received);
+        assert_eq!(path[3].source, Some(network2.local_agent.address().clone()));
+
+        // RPCs sent on this agent's own account have no source.
+        assert!(network1.put_artifact(SwarmArtifact::new(1isize, ())).is_ok());
+        let events = log.events();
+        let put = events.last().unwrap();
+        assert_eq!(put.rpc, "PUT");
+        assert_eq!(put.source, None);
+    }
+
+    impl Coordinates for isize {
+        fn coordinates(&self) -> (f64, f64) {
+            (*self as f64, 0.0)
+        }
+    }
+
+    #[test]
+    fn topology_test() {
+        let mut network1 = construct_network(1isize);
+        let mut network2 = construct_network(2isize);
+        let network3 = construct_network(3isize);
+        network1.neighbors.push(network2.local_agent.clone());
+        network2.neighbors.push(network1.local_agent.clone());
+        network2.neighbors.push(network3.local_agent.clone());
+
+        let topology = Topology::from_networks(&[&network1, &network2]);
+        assert_eq!(topology.node_count(), 3);
+        assert_eq!(topology.edge_count(), 3);
+        assert!(topology.to_dot().contains("pos=\"3,0!\""));
+    }
+
+    #[test]
+    fn get_neighbors_test() {
+        let mut network1 = construct_network(0isize);
+        let mut network2 = construct_network(10isize);
+        let network3 = construct_network(5isize);
+        network2.neighbors.push(network3.local_agent.clone());
+
+        let dest = network2.address();
+        assert!(network1.send_get_neighbors(dest).is_ok());
+        assert!(network2.dispatch_rpc().is_ok());
+        match network1.next_msg() {
+            Ok(IronSwarmRPC::NEIGHBORS(agn, neighbors)) => {
+                assert_eq!(agn, network2.local_agent);
+                assert_eq!(neighbors, vec![network3.local_agent.clone()]);
+            }
+            _ => panic!("expected a neighbors rpc")
+        }
+        // Asking does not make the requester a neighbor.
+        assert_eq!(network2.neighbors.len(), 1);
+
+        // NEIGHBORS are dropped when no crawl is in progress.
+        assert!(network2.send_neighbors(network1.address()).is_ok());
+        assert!(network1.dispatch_rpc().is_ok());
+        assert!(network1.crawl.is_none());
+    }
+
+    #[test]
+    fn json_neighbors_test() {
+        let mut network1 = construct_network(0isize);
+        let mut network2 = construct_network(10isize);
+        let others = [construct_agent(), construct_agent(), construct_agent()];
+        network2.set_wire_format(WireFormat::Json);
+        network2.neighbors.extend(others.iter().cloned());
+
+        // A table too large for one packet is split across several.
+        assert!(network1.send_get_neighbors(network2.address()).is_ok());
+        assert!(network2.dispatch_rpc().is_ok());
+        let packets = network2.metrics.sent("NEIGHBORS");
+        assert!(packets > 1);
+        let mut received = Vec::new();
+        for _ in 0..packets {
+            match network1.next_msg() {
+                Ok(IronSwarmRPC::NEIGHBORS(agn, neighbors)) => {
+                    assert_eq!(agn, network2.local_agent);
+                    received.extend(neighbors);
+                }
+                _ => panic!("expected a neighbors rpc")
+            }
+        }
+        assert_eq!(received, others.to_vec());
+    }
+
+    #[test]
+    fn crawl_test() {
+        let mut crawler = construct_network(100isize);
+        let mut network1 = construct_network(1isize);
+        let mut network2 = construct_network(2isize);
+        let mut network3 = construct_network(3isize);
+        network1.neighbors.push(network2.local_agent.clone());
+        network2.neighbors.push(network1.local_agent.clone());
+        network2.neighbors.push(network3.local_agent.clone());
+        network3.neighbors.push(network2.local_agent.clone());
+
+        let start = network1.address();
+        let mut stalled = CrawlConfig::new();
+        stalled.max_rate = 0;
+        assert!(crawler.start_crawl(start, stalled).is_err());
+        assert!(crawler.start_crawl(start, CrawlConfig::new()).is_ok());
+
+        // network1, then network2, then network3 are asked in turn.
+        let mut result = None;
+        for _ in 0usize..3 {
+            let _ = network1.dispatch_rpc();
+            let _ = network2.dispatch_rpc();
+            let _ = network3.dispatch_rpc();
+            assert!(crawler.dispatch_rpc().is_ok());
+            result = crawler.poll_crawl().ok().unwrap();
+        }
+
+        let result = result.expect("the crawl should be done");
+        let ids: Vec<ByteId> = result.agents().iter().map(|a| a.id().clone()).collect();
+        assert_eq!(ids, vec![network1.local_agent.id().clone(),
+                             network2.local_agent.id().clone(),
+                             network3.local_agent.id().clone()]);
+        assert_eq!(result.unreachable().len(), 0);
+        assert_eq!(result.topology().edge_count(), 4);
+    }
+
+    #[test]
+    fn aggregate_test() {
+        let mut network1 = construct_network(1isize);
+        let mut network2 = construct_network(2isize);
+        network1.neighbors.push(network2.local_agent.clone());
+        network2.neighbors.push(network1.local_agent.clone());
+
+        network1.start_aggregate("battery");
+        network1.contribute("battery", 0.25);
+        network2.contribute("battery", 0.75);
+        assert!(network2.aggregate("battery").is_none());
+
+        for _ in 0usize..30 {
+            assert!(network1.gossip_aggregates().is_ok());
+            assert!(network2.dispatch_rpc().is_ok());
+            assert!(network2.gossip_aggregates().is_ok());
+            assert!(network1.dispatch_rpc().is_ok());
+        }
+
+        for network in [&network1, &network2].iter() {
+            let estimate = network.aggregate("battery").unwrap();
+            assert!((estimate.average.unwrap() - 0.5).abs() < 1e-6);
+            assert!((estimate.count.unwrap() - 2.0).abs() < 1e-6);
+            assert!((estimate.sum.unwrap() - 1.0).abs() < 1e-6);
+        }
+
+        // Only neighbors' shares are accepted.
+        let mut network3 = construct_network(3isize);
+        network3.start_aggregate("intruder");
+        assert!(network3.send_aggregate("intruder".to_string(),
+                                        Mass { value: 1.0, count: 1.0, weight: 0.5 },
+                                        network2.address()).is_ok());
+        assert!(network2.dispatch_rpc().is_err());
+        assert!(network2.aggregate("intruder").is_none());
+    }
+
+    #[test]
+    fn aggregate_send_failure_test() {
+        let mut network = construct_network(1isize);
+        network.neighbors.push(SwarmAgent::new(2isize, SocketAddr::from(([127, 0, 0, 1], 0))));
+        network.start_aggregate("battery");
+        network.contribute("battery", 4.0);
+
+        // The halves that could not be sent are taken back.
+        assert!(network.gossip_aggregates().is_err());
+        let estimate = network.aggregate("battery").unwrap();
+        assert!((estimate.sum.unwrap() - 4.0).abs() < 1e-6);
+        assert!((estimate.count.unwrap() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn election_test() {
+        let mut network1 = construct_network(1isize);
+        let mut network2 = construct_network(2isize);
+        let mut network3 = construct_network(3isize);
+        network1.neighbors.push(network2.local_agent.clone());
+        network2.neighbors.push(network1.local_agent.clone());
+        network2.neighbors.push(network3.local_agent.clone());
+        network3.neighbors.push(network2.local_agent.clone());
+        for network in [&mut network1, &mut network2, &mut network3].iter_mut() {
+            network.enable_election(10, 5000);
+        }
+
+        let lowest = [&network1, &network2, &network3].iter().
+            map(|n| n.local_agent.id().clone()).
+            min().
+            unwrap();
+
+        // network1 and network3 learn of each other through network2. An
+        // agent that follows another sends its leader's announcement too.
+        for _ in 0usize..2 {
+            let sent: Vec<u64> = [&network1, &network2, &network3].iter().
+                map(|n| n.metrics.sent("LEADER")).collect();
+            assert!(network1.announce_leader().is_ok());
+            assert!(network2.announce_leader().is_ok());
+            assert!(network3.announce_leader().is_ok());
+            let to_network2 = network1.metrics.sent("LEADER") - sent[0] +
+                network3.metrics.sent("LEADER") - sent[2];
+            let from_network2 = (network2.metrics.sent("LEADER") - sent[1]) / 2;
+            for _ in 0..from_network2 {
+                assert!(network1.dispatch_rpc().is_ok());
+                assert!(network3.dispatch_rpc().is_ok());
+            }
+            for _ in 0..to_network2 {
+                assert!(network2.dispatch_rpc().is_ok());
+            }
+        }
+
+        for network in [&mut network1, &mut network2, &mut network3].iter_mut() {
+            assert_eq!(*network.leader().unwrap().id(), lowest);
+            assert_eq!(*network.take_leader_change().unwrap().id(), lowest);
+            assert!(network.take_leader_change().is_none());
+        }
+        assert_eq!(network1.is_leader(), *network1.local_agent.id() == lowest);
     }
 
-    fn construct_swarm_msg() -> SwarmMsg<isize> {
-        SwarmMsg::new_artifact_msg(construct_agent(), construct_artifact())
+    struct LeaderTester {
+        leaders: Vec<ByteId>
     }
 
-    fn local_socket() -> SocketAddr {
-        SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: next_test_port() }
+    impl ReactToSwarm<isize, ()> for LeaderTester {
+        fn react(&mut self, _msg: &SwarmMsg<isize, ()>) {}
+
+        fn leader_changed(&mut self, leader: &SwarmAgent<isize>) {
+            self.leaders.push(leader.id().clone());
+        }
     }
 
-    fn bincode_rpc_tester(rpc: IronSwarmRPC<isize>) {
-        let orig_rpc = rpc.clone();
-        let limit = bincode::SizeLimit::Infinite;
-        let encoded = bincode::encode(&rpc, limit).ok().unwrap();
-        let dec_rpc: IronSwarmRPC<isize> =
-            bincode::decode(encoded.as_slice()).ok().unwrap();
-        assert_eq!(orig_rpc, dec_rpc);
+    #[test]
+    fn follow_leader_test() {
+        let mut network = construct_network(1isize);
+        let mut controller = SwarmController::new(LeaderTester { leaders: Vec::new() });
+        controller.follow_leader(&mut network);
+
+        network.enable_election(10, 5000);
+        assert!(network.announce_leader().is_ok());
+        controller.follow_leader(&mut network);
+        controller.follow_leader(&mut network);
+        assert_eq!(controller.actor.leaders, vec![network.local_agent.id().clone()]);
     }
 
-    fn send_broadcast_tester(from_nework: &mut SwarmNetwork<isize>,
-                           to_network: &mut SwarmNetwork<isize>) -> SwarmResult<()> {
-        let msg = construct_swarm_msg();
-        let exp_rpc = IronSwarmRPC::BROADCAST(msg.clone());
+    #[test]
+    fn relayed_leader_test() {
+        let network1 = construct_network(1isize);
+        let mut network2 = construct_network(2isize);
+        let mut network3 = construct_network(3isize);
+        network2.enable_election(10, 5000);
+        let now = wall_clock_ms();
+        network2.replay_time = Some(now);
+
+        // A leader relayed by network3 is as old as its own announcement.
+        let own = Announcement::new(network3.local_agent.clone(), &network3.keypair, now);
+        let leader = Announcement::new(network1.local_agent.clone(), &network1.keypair,
+                                       now - 4000);
+        for announcement in vec![own, leader].into_iter() {
+            assert!(network3.send_rpc(IronSwarmRPC::LEADER(announcement), network2.address()).is_ok());
+            assert!(network2.dispatch_rpc().is_ok());
+        }
+        {
+            let election = network2.election.as_ref().unwrap();
+            assert_eq!(election.heard(network3.local_agent.id()), Some(now));
+            assert_eq!(election.heard(network1.local_agent.id()), Some(now - 4000));
+        }
 
-        try!(from_nework.send_broadcast(msg, to_network.address()));
-        let recv_rpc = try!(to_network.next_msg());
+        // network3 can not vouch for the leader itself, nor can the leader
+        // date its announcement ahead.
+        let forged = Announcement::new(network1.local_agent.clone(), &network3.keypair, now);
+        let ahead = Announcement::new(network1.local_agent.clone(), &network1.keypair,
+                                      now + MAX_RPC_AGE + 1);
+        for leader in vec![forged, ahead].into_iter() {
+            assert!(network3.send_rpc(IronSwarmRPC::LEADER(leader), network2.address()).is_ok());
+            let _ = network2.dispatch_rpc();
+        }
+        {
+            let election = network2.election.as_ref().unwrap();
+            assert_eq!(election.heard(network1.local_agent.id()), Some(now - 4000));
+        }
 
-        assert_eq!(exp_rpc, recv_rpc);
-        Ok(())
+        // Candidates are held to the work required of joining agents.
+        let network4 = construct_network(4isize);
+        network2.set_required_work(32);
+        let leader = Announcement::new(network4.local_agent.clone(), &network4.keypair, now);
+        assert!(network3.send_rpc(IronSwarmRPC::LEADER(leader), network2.address()).is_ok());
+        assert!(network2.dispatch_rpc().is_ok());
+        let election = network2.election.as_ref().unwrap();
+        assert_eq!(election.heard(network4.local_agent.id()), None);
     }
 
-    fn next_msg_rpc_tester(from_network: &mut SwarmNetwork<isize>,
-                           to_network: &mut SwarmNetwork<isize>,
-                           rpc: IronSwarmRPC<isize>) -> SwarmResult<()> {
-        let orig_rpc = rpc.clone();
-        let net_sock = to_network.address();
-        try!(from_network.socket.send_packet(rpc, net_sock));
+    #[test]
+    fn sync_broadcasts_test() {
+        let mut network1 = construct_network(1isize);
+        let mut network2 = construct_network(2isize);
+        let mut network3 = construct_network(3isize);
+        network1.neighbors.push(network2.local_agent.clone());
+        network2.neighbors.push(network3.local_agent.clone());
+        network3.neighbors.push(network2.local_agent.clone());
 
-        let dec_rpc = try!(to_network.next_msg());
-        assert_eq!(orig_rpc, dec_rpc);
-        Ok(())
+        // network3 is not a neighbor of network1 and misses its broadcast.
+        assert!(network1.broadcast_custom(vec![1u8, 2, 3]).is_ok());
+        assert!(network2.dispatch_rpc().is_ok());
+        assert_eq!(network3.broadcasts.len(), 0);
+
+        // DIGEST, PULL and the broadcast sent again.
+        assert!(network2.sync_broadcasts().is_ok());
+        assert!(network3.dispatch_rpc().is_ok());
+        assert!(network2.dispatch_rpc().is_ok());
+        assert!(network3.dispatch_rpc().is_ok());
+        assert_eq!(network3.metrics().received("BROADCAST"), 1);
+        assert_eq!(network3.broadcasts.digest(10), network2.broadcasts.digest(10));
+
+        // Nothing is missing any more, so nothing is pulled.
+        assert!(network2.sync_broadcasts().is_ok());
+        assert!(network3.dispatch_rpc().is_ok());
+        assert!(network2.dispatch_rpc().is_err());
+
+        // Broadcasts larger than the request are not sent again.
+        let ids = network2.broadcasts.digest(10);
+        assert!(network3.send_rpc(IronSwarmRPC::PULL(ids.clone(), Vec::new()),
+                                  network2.address()).is_ok());
+        assert!(network2.dispatch_rpc().is_ok());
+        assert!(network3.next_msg().is_err());
+
+        // Only neighbors are answered.
+        assert!(network1.send_pull(ids, network2.address()).is_ok());
+        assert!(network2.dispatch_rpc().is_err());
     }
 
     #[test]
-    fn next_msg_test() {
-        let mut ack_vec = Vec::new();
-        ack_vec.push(construct_agent());
-        let mut from_network = SwarmNetwork::new(0is, local_socket());
-        let mut to_network = SwarmNetwork::new(1is, local_socket());
+    fn shared_state_test() {
+        let mut network1 = construct_network(1isize);
+        let mut network2 = construct_network(2isize);
+        network1.neighbors.push(network2.local_agent.clone());
+        network2.neighbors.push(network1.local_agent.clone());
 
-        let res = next_msg_rpc_tester(&mut from_network, &mut to_network,
-                                      IronSwarmRPC::HRTBT(construct_agent()));
-        assert!(res.is_ok());
-        let res = next_msg_rpc_tester(&mut from_network, &mut to_network,
-                                      IronSwarmRPC::HRTBTACK(ack_vec));
-        assert!(res.is_ok());
-        let res = next_msg_rpc_tester(&mut from_network, &mut to_network,
-                                      IronSwarmRPC::JOIN(construct_agent()));
-        assert!(res.is_ok());
-        let res = next_msg_rpc_tester(&mut from_network, &mut to_network,
-                                      IronSwarmRPC::INFO(10, construct_swarm_msg()));
-        assert!(res.is_ok());
-        let res = next_msg_rpc_tester(&mut from_network, &mut to_network,
-                                      IronSwarmRPC::BROADCAST(construct_swarm_msg()));
-        assert!(res.is_ok());
+        network1.shared_state_mut().increment("visits", 2);
+        network1.shared_state_mut().insert("hazards", b"7,3".to_vec());
+        network2.shared_state_mut().increment("visits", 1);
+        network2.shared_state_mut().write("mode", b"explore".to_vec());
+
+        // network2 knows more of visits than network1 sent, and answers with
+        // the merged counter.
+        assert!(network1.sync_state().is_ok());
+        assert!(network2.dispatch_rpc().is_ok());
+        assert!(network2.dispatch_rpc().is_ok());
+        assert!(network1.dispatch_rpc().is_ok());
+        assert!(network2.sync_state().is_ok());
+        for _ in 0usize..3 {
+            assert!(network1.dispatch_rpc().is_ok());
+        }
+
+        for network in [&network1, &network2].iter() {
+            let state = network.shared_state();
+            assert_eq!(state.counter("visits"), Some(3));
+            assert!(state.set("hazards").unwrap().contains(b"7,3"));
+            assert_eq!(state.register("mode"), Some(b"explore".as_slice()));
+        }
+
+        // A replica of another type is not answered.
+        let entry = Crdt::Counter(GCounter::new());
+        assert!(network1.send_state("hazards".to_string(), entry, network2.address()).is_ok());
+        assert!(network2.dispatch_rpc().is_ok());
+        assert!(network1.next_msg().is_err());
+
+        // Registers must be signed by their writer.
+        let forged = LwwRegister::new(Vec::new(), u64::MAX, network1.local_agent.id().clone());
+        assert!(network1.send_state("mode".to_string(), Crdt::Register(forged),
+                                    network2.address()).is_ok());
+        assert!(network2.dispatch_rpc().is_err());
+        assert_eq!(network2.shared_state().register("mode"), Some(b"explore".as_slice()));
     }
 
     #[test]
-    fn bincode_test() {
-        let mut ack_vec = Vec::new();
-        ack_vec.push(construct_agent());
+    fn task_test() {
+        let mut network1 = construct_network(1isize);
+        let mut network2 = construct_network(5isize);
+        let mut network3 = construct_network(6isize);
+        network1.neighbors.push(network2.local_agent.clone());
+        network2.neighbors.push(network1.local_agent.clone());
+        network2.neighbors.push(network3.local_agent.clone());
+        network3.neighbors.push(network2.local_agent.clone());
 
-        bincode_rpc_tester(IronSwarmRPC::HRTBT(construct_agent()));
-        bincode_rpc_tester(IronSwarmRPC::HRTBTACK(ack_vec));
-        bincode_rpc_tester(IronSwarmRPC::JOIN(construct_agent()));
-        bincode_rpc_tester(IronSwarmRPC::INFO(10, construct_swarm_msg()));
-        bincode_rpc_tester(IronSwarmRPC::BROADCAST(construct_swarm_msg()));
+        // The announcement is routed to network3, which passes it on to
+        // network2. network2 is within the radius and passes it on in turn,
+        // which network1 and network3 have seen already. Both bid; network1
+        // is not a neighbor of network3, so its bid is routed through
+        // network2.
+        let id = network1.announce_task(6, 3, b"survey".to_vec()).unwrap();
+        assert!(network2.dispatch_rpc().is_ok());
+        assert!(network3.dispatch_rpc().is_ok());
+        assert!(network2.dispatch_rpc().is_ok());
+        assert!(network2.dispatch_rpc().is_ok());
+        assert_eq!(network2.metrics.sent("INFO"), 2);
+        for _ in 0usize..3 {
+            assert!(network1.dispatch_rpc().is_ok());
+        }
+        assert!(network3.dispatch_rpc().is_ok());
+        assert_eq!(*network1.task_status(id).unwrap(), TaskStatus::Bidding(2));
+
+        let winner = network1.award_task(id).unwrap().unwrap();
+        assert_eq!(winner.id(), network3.local_agent.id());
+        assert!(network3.dispatch_rpc().is_ok());
+        assert!(network2.dispatch_rpc().is_ok());
+        assert!(network2.take_assigned_tasks().is_empty());
+        let assigned = network3.take_assigned_tasks();
+        assert_eq!(assigned.len(), 1);
+        assert_eq!(assigned[0].description(), b"survey".as_slice());
+
+        assert!(network3.report_task(id, TaskOutcome::Completed).is_ok());
+        assert!(network1.dispatch_rpc().is_ok());
+        assert_eq!(*network1.task_status(id).unwrap(),
+                   TaskStatus::Done(winner, TaskOutcome::Completed));
     }
 
     #[test]
-    fn send_rpc_test() {
-        let mut network_to = SwarmNetwork::new(0is, local_socket());
-        let mut network_from = SwarmNetwork::new(10is, local_socket());
+    fn send_failure_test() {
+        let mut network1 = construct_network(1isize);
+        let mut network2 = construct_network(2isize);
+        // Nothing can be sent to port 0.
+        network1.neighbors.push(construct_agent());
+        network1.neighbors.push(network2.local_agent.clone());
 
-        let res = send_broadcast_tester(&mut network_from, &mut network_to);
-        assert!(res.is_ok());
+        // The neighbors after the one that failed are still sent to.
+        assert!(network1.heartbeat().is_err());
+        assert_eq!(network1.metrics.send_failures(), 1);
+        assert!(network2.dispatch_rpc().is_ok());
+        assert!(network1.broadcast_custom(vec![1u8]).is_err());
+        assert!(network2.dispatch_rpc().is_ok());
+        assert_eq!(network2.metrics.received("BROADCAST"), 1);
     }
 
     #[test]
-    fn join_self_closest_test() {
-        let mut network1 = SwarmNetwork::new(0is, local_socket());
-        let mut network2 = SwarmNetwork::new(10is, local_socket());
-        let mut joining = SwarmNetwork::new(2is, local_socket());
-        network1.neighbors.push(network2.local_agent.clone());
+    fn task_flood_test() {
+        // A chain of agents within the radius of the task, and one beyond it.
+        let mut networks: Vec<SwarmNetwork<isize, ()>> =
+            [0isize, 1, 2, 3, 20].iter().map(|&loc| construct_network(loc)).collect();
+        for i in 0..networks.len() - 1 {
+            let next = networks[i + 1].local_agent.clone();
+            let prev = networks[i].local_agent.clone();
+            networks[i].neighbors.push(next);
+            networks[i + 1].neighbors.push(prev);
+        }
 
-        joining.join(network1.address());
-        let res = network1.dispatch_rpc();
-        assert!(res.is_ok());
+        let task = Task::new(0isize, 5, networks[0].local_agent.clone(), Vec::new());
+        let mut msg = SwarmMsg::new_task_msg(networks[0].local_agent.clone(),
+                                             TaskMsg::Announce(task));
+        msg.sign(&networks[0].keypair);
+        let dest = networks[1].address();
+        assert!(networks[0].send_broadcast(0, msg, dest).is_ok());
+
+        // Passed on hop by hop within the radius.
+        assert!(networks[1].dispatch_rpc().is_ok());
+        assert!(networks[2].dispatch_rpc().is_ok());
+        assert!(networks[3].dispatch_rpc().is_ok());
+        match networks[4].next_msg() {
+            Ok(IronSwarmRPC::BROADCAST(hops, _)) => assert_eq!(hops, 3),
+            _ => panic!("expected a broadcast")
+        }
 
-        assert_eq!(network1.neighbors.len(), 2);
+        // Not past the hop limit.
+        let dest = networks[2].address();
+        let mut msg = SwarmMsg::new_task_msg(networks[0].local_agent.clone(),
+            TaskMsg::Announce(Task::new(0isize, 5, networks[0].local_agent.clone(), Vec::new())));
+        msg.sign(&networks[0].keypair);
+        assert!(networks[1].send_broadcast(MAX_TASK_HOPS, msg, dest).is_ok());
+        assert!(networks[2].dispatch_rpc().is_ok());
+        assert!(networks[3].next_msg().is_err());
     }
 
     #[test]
     fn join_other_closest_test() {
-        let mut network1 = SwarmNetwork::new(0is, local_socket());
-        let mut network2 = SwarmNetwork::new(10is, local_socket());
-        let mut joining = SwarmNetwork::new(9is, local_socket());
+        let mut network1 = construct_network(0isize);
+        let mut network2 = construct_network(10isize);
+        let mut joining = construct_network(9isize);
         network1.neighbors.push(network2.local_agent.clone());
+        network2.neighbors.push(network1.local_agent.clone());
 
         joining.join(network1.address());
-        network1.dispatch_rpc();
-        network2.dispatch_rpc();
+        let _ = network1.dispatch_rpc();
+        let _ = network2.dispatch_rpc();
 
         assert_eq!(network1.neighbors.len(), 1);
-        assert_eq!(network2.neighbors.len(), 1);
+        assert_eq!(network2.neighbors.len(), 2);
     }
 
     #[test]
     fn join_self_closest_over_max_test() {
-        let mut network1 = SwarmNetwork::new(10is, local_socket());
-        let mut network2 = SwarmNetwork::new(1is, local_socket());
-        let mut network3 = SwarmNetwork::new(2is, local_socket());
-        let mut network4 = SwarmNetwork::new(3is, local_socket());
-        let mut joining = SwarmNetwork::new(20is, local_socket());
+        let mut network1 = construct_network(10isize);
+        let network2 = construct_network(1isize);
+        let network3 = construct_network(2isize);
+        let mut network4 = construct_network(3isize);
+        let mut joining = construct_network(20isize);
         network1.neighbors.push(network2.local_agent.clone());
         network1.neighbors.push(network3.local_agent.clone());
         network1.neighbors.push(network4.local_agent.clone());
+        network4.neighbors.push(network1.local_agent.clone());
 
         joining.join(network1.address());
-        network1.dispatch_rpc();
-        network4.dispatch_rpc();
+        let _ = network1.dispatch_rpc();
+        // The forwarded JOIN arrives, and network4 passes it back on as it is
+        // further away still.
+        assert!(network4.dispatch_rpc().is_ok());
 
         assert_eq!(network1.neighbors.len(), 3);
         assert_eq!(network4.neighbors.len(), 1);
@@ -378,26 +2833,26 @@ mod test {
 
     #[test]
     fn htbt_and_ack_add_neighbor_test() {
-        let mut network1 = SwarmNetwork::new(0is, local_socket());
-        let mut network2 = SwarmNetwork::new(10is, local_socket());
+        let mut network1 = construct_network(0isize);
+        let mut network2 = construct_network(10isize);
         network1.neighbors.push(network2.local_agent.clone());
 
         // Neighbor of ACK'd swarm agent
-        let mut network3 = SwarmNetwork::new(9is, local_socket());
+        let network3 = construct_network(9isize);
         network2.neighbors.push(network3.local_agent.clone());
 
         // New agent that is not in neighbor list.
-        let mut network4 = SwarmNetwork::new(11is, local_socket());
+        let mut network4 = construct_network(11isize);
         network4.neighbors.push(network1.local_agent.clone());
         network4.neighbors.push(network2.local_agent.clone());
 
-        network1.heartbeat();
+        let _ = network1.heartbeat();
         let mut res = network2.dispatch_rpc();
         assert!(res.is_ok());
         res = network1.dispatch_rpc();
         assert!(res.is_ok());
 
-        network4.heartbeat();
+        let _ = network4.heartbeat();
         res = network1.dispatch_rpc();
         assert!(res.is_ok());
         res = network2.dispatch_rpc();
@@ -411,15 +2866,15 @@ mod test {
 
     #[test]
     fn hrtbt_ack_filter_neighbors_test() {
-        let mut network1 = SwarmNetwork::new(0is, local_socket());
-        let mut network2 = SwarmNetwork::new(10is, local_socket());
-        let mut network3 = SwarmNetwork::new(9is, local_socket());
+        let mut network1 = construct_network(0isize);
+        let mut network2 = construct_network(10isize);
+        let network3 = construct_network(9isize);
 
         network1.neighbors.push(network2.local_agent.clone());
         network1.neighbors.push(network3.local_agent.clone());
         network2.neighbors.push(network3.local_agent.clone());
 
-        network1.heartbeat();
+        let _ = network1.heartbeat();
         let mut res = network2.dispatch_rpc();
         assert!(res.is_ok());
         res = network1.dispatch_rpc();
@@ -430,11 +2885,11 @@ mod test {
 
     #[test]
     fn hrtbt_over_max_no_ack_test() {
-        let mut network1 = SwarmNetwork::new(10is, local_socket());
-        let mut network2 = SwarmNetwork::new(1is, local_socket());
-        let mut network3 = SwarmNetwork::new(2is, local_socket());
-        let mut network4 = SwarmNetwork::new(3is, local_socket());
-        let mut network5 = SwarmNetwork::new(20is, local_socket());
+        let mut network1 = construct_network(10isize);
+        let network2 = construct_network(1isize);
+        let network3 = construct_network(2isize);
+        let network4 = construct_network(3isize);
+        let mut network5 = construct_network(20isize);
 
         network1.neighbors.push(network2.local_agent.clone());
         network1.neighbors.push(network3.local_agent.clone());
@@ -442,8 +2897,8 @@ mod test {
 
         network5.neighbors.push(network1.local_agent.clone());
 
-        network5.heartbeat();
-        network1.dispatch_rpc();
+        let _ = network5.heartbeat();
+        let _ = network1.dispatch_rpc();
         let res = network5.dispatch_rpc();
         assert!(res.is_err());
 
@@ -456,22 +2911,20 @@ mod test {
 
     #[test]
     fn info_msg_test() {
-        let mut network1 = SwarmNetwork::new(1is, local_socket());
-        let mut network2 = SwarmNetwork::new(2is, local_socket());
-        let mut network3 = SwarmNetwork::new(3is, local_socket());
-        let mut network4 = SwarmNetwork::new(4is, local_socket());
+        let mut network1 = construct_network(1isize);
+        let mut network2 = construct_network(2isize);
+        let mut network3 = construct_network(3isize);
+        let mut network4 = construct_network(4isize);
 
         network3.neighbors.push(network2.local_agent.clone());
         network3.neighbors.push(network4.local_agent.clone());
         network2.neighbors.push(network1.local_agent.clone());
 
-        let agent = SwarmAgent::new(0is, local_socket());
-        let artifact = SwarmArtifact::new(3is);
-        let msg = SwarmMsg::new_artifact_gone_msg(agent, artifact);
+        let msg = construct_swarm_msg();
 
         {
-            let loc = network1.local_agent.location().clone();
-            network3.route_info_msg(loc, msg);
+            let loc = *network1.local_agent.location();
+            let _ = network3.route_info_msg(loc, 0, msg);
         }
 
         let mut res = network2.dispatch_rpc();
@@ -484,21 +2937,19 @@ mod test {
 
     #[test]
     fn info_msg_no_send_test() {
-        let mut network1 = SwarmNetwork::new(1is, local_socket());
-        let mut network2 = SwarmNetwork::new(2is, local_socket());
-        let mut network3 = SwarmNetwork::new(10is, local_socket());
-        let mut network4 = SwarmNetwork::new(4is, local_socket());
+        let mut network1 = construct_network(1isize);
+        let mut network2 = construct_network(2isize);
+        let mut network3 = construct_network(10isize);
+        let mut network4 = construct_network(4isize);
 
         network3.neighbors.push(network2.local_agent.clone());
         network3.neighbors.push(network4.local_agent.clone());
 
-        let agent = SwarmAgent::new(0is, local_socket());
-        let artifact = SwarmArtifact::new(3is);
-        let msg = SwarmMsg::new_artifact_gone_msg(agent, artifact);
+        let msg = construct_swarm_msg();
 
         {
-            let loc = 9is;
-            network3.route_info_msg(loc, msg);
+            let loc = 9isize;
+            let _ = network3.route_info_msg(loc, 0, msg);
         }
 
         // Msg is not sent anywhere
@@ -509,4 +2960,559 @@ mod test {
         res = network4.dispatch_rpc();
         assert!(res.is_err());
     }
+
+    #[test]
+    fn put_stores_at_closest_test() {
+        let mut network1 = construct_network(1isize);
+        let mut network2 = construct_network(5isize);
+        let mut network3 = construct_network(9isize);
+
+        network1.neighbors.push(network2.local_agent.clone());
+        network2.neighbors.push(network1.local_agent.clone());
+        network2.neighbors.push(network3.local_agent.clone());
+        network3.neighbors.push(network2.local_agent.clone());
+
+        let mut res = network1.put_artifact(SwarmArtifact::new(8isize, ()));
+        assert!(res.is_ok());
+        res = network2.dispatch_rpc();
+        assert!(res.is_ok());
+        res = network3.dispatch_rpc();
+        assert!(res.is_ok());
+
+        assert_eq!(network1.stored_artifacts().len(), 0);
+        assert_eq!(network2.stored_artifacts().len(), 0);
+        assert_eq!(network3.stored_artifacts().len(), 1);
+    }
+
+    #[test]
+    fn put_replicates_to_nearest_test() {
+        let mut network1 = construct_network(0isize);
+        let mut network2 = construct_network(1isize);
+        let mut network3 = construct_network(10isize);
+        let mut network4 = construct_network(20isize);
+
+        network1.neighbors.push(network4.local_agent.clone());
+        network1.neighbors.push(network3.local_agent.clone());
+        network1.neighbors.push(network2.local_agent.clone());
+        network2.neighbors.push(network1.local_agent.clone());
+        network3.neighbors.push(network1.local_agent.clone());
+
+        let mut res = network1.put_artifact(SwarmArtifact::new(0isize, ()));
+        assert!(res.is_ok());
+        res = network2.dispatch_rpc();
+        assert!(res.is_ok());
+        res = network3.dispatch_rpc();
+        assert!(res.is_ok());
+        res = network4.dispatch_rpc();
+        assert!(res.is_err());
+
+        assert_eq!(network1.stored_artifacts().len(), 1);
+        assert_eq!(network2.stored_artifacts().len(), 1);
+        assert_eq!(network3.stored_artifacts().len(), 1);
+        assert_eq!(network4.stored_artifacts().len(), 0);
+
+        // Only to the nearest neighbor, once set so.
+        network1.set_replication(1);
+        assert!(network1.put_artifact(SwarmArtifact::new(0isize, ())).is_ok());
+        assert!(network2.dispatch_rpc().is_ok());
+        assert!(network3.dispatch_rpc().is_err());
+        assert_eq!(network1.stored_artifacts().len(), 2);
+        assert_eq!(network2.stored_artifacts().len(), 2);
+        assert_eq!(network3.stored_artifacts().len(), 1);
+    }
+
+    #[test]
+    fn get_artifacts_test() {
+        let mut network1 = construct_network(0isize);
+        let mut network2 = construct_network(10isize);
+        network1.neighbors.push(network2.local_agent.clone());
+
+        network2.store.insert(SwarmArtifact::new(9isize, ()));
+        network2.store.insert(SwarmArtifact::new(15isize, ()));
+
+        let mut res = network1.find_artifacts(9isize, 2);
+        assert!(res.is_ok());
+        res = network2.dispatch_rpc();
+        assert!(res.is_ok());
+        res = network1.dispatch_rpc();
+        assert!(res.is_ok());
+
+        let found = network1.take_found_artifacts();
+        assert_eq!(found.len(), 1);
+        assert_eq!(*found[0].location(), 9isize);
+        assert_eq!(network1.take_found_artifacts().len(), 0);
+    }
+
+    #[test]
+    fn get_artifacts_split_test() {
+        let mut network1 = construct_network(0isize);
+        let mut network2 = construct_network(10isize);
+        network1.neighbors.push(network2.local_agent.clone());
+        for _ in 0usize..60 {
+            network2.store.insert(SwarmArtifact::new(9isize, ()));
+        }
+
+        // Too many artifacts for a single packet.
+        assert!(network1.find_artifacts(9isize, 2).is_ok());
+        assert!(network2.dispatch_rpc().is_ok());
+        let packets = network2.metrics().sent("ARTIFACTS");
+        assert!(packets > 1);
+        for _ in 0..packets {
+            assert!(network1.dispatch_rpc().is_ok());
+        }
+        assert_eq!(network1.take_found_artifacts().len(), 60);
+    }
+
+    #[test]
+    fn get_artifacts_relay_test() {
+        let mut network1 = construct_network(0isize);
+        let mut network2 = construct_network(5isize);
+        let mut network3 = construct_network(10isize);
+        network1.neighbors.push(network2.local_agent.clone());
+        network2.neighbors.push(network3.local_agent.clone());
+        network3.neighbors.push(network2.local_agent.clone());
+        network3.store.insert(SwarmArtifact::new(10isize, ()));
+
+        // The answer goes back the way the GET came.
+        assert!(network1.find_artifacts(10isize, 0).is_ok());
+        assert!(network2.dispatch_rpc().is_ok());
+        assert!(network3.dispatch_rpc().is_ok());
+        assert!(network2.dispatch_rpc().is_ok());
+        assert!(network1.dispatch_rpc().is_ok());
+        assert_eq!(network1.take_found_artifacts().len(), 1);
+
+        // Answers nobody waits for are dropped, and only neighbors answer.
+        let arts = vec![SwarmArtifact::new(1isize, ())];
+        let id = network1.local_agent.id().clone();
+        assert!(network2.send_artifacts(id.clone(), arts.clone(), network1.address()).is_ok());
+        network1.finds.clear();
+        assert!(network1.dispatch_rpc().is_ok());
+        assert!(network3.send_artifacts(id, arts, network1.address()).is_ok());
+        assert!(network1.dispatch_rpc().is_err());
+        assert_eq!(network1.take_found_artifacts().len(), 0);
+    }
+
+    #[test]
+    fn get_artifacts_local_test() {
+        let mut network1 = construct_network(0isize);
+        network1.store.insert(SwarmArtifact::new(1isize, ()));
+
+        let res = network1.find_artifacts(0isize, 2);
+        assert!(res.is_ok());
+        assert_eq!(network1.take_found_artifacts().len(), 1);
+    }
+
+    #[test]
+    fn info_custom_msg_test() {
+        let mut network1 = construct_network(1isize);
+        let mut network2 = construct_network(5isize);
+        network1.neighbors.push(network2.local_agent.clone());
+
+        let res = network1.route_info_msg(6isize, 0, construct_custom_msg());
+        assert!(res.is_ok());
+
+        match network2.next_msg() {
+            Ok(IronSwarmRPC::INFO(loc, _, msg)) => {
+                assert_eq!(loc, 6isize);
+                let event: (u8, u32) = msg.event().decode_custom().unwrap();
+                assert_eq!(event, (7u8, 1000u32));
+            }
+            _ => panic!("expected an INFO rpc")
+        }
+    }
+
+    #[test]
+    fn forged_signature_test() {
+        let mut network1 = construct_network(0isize);
+        let mut network2 = construct_network(10isize);
+        let other = Keypair::generate();
+
+        let rpc = IronSwarmRPC::<isize, ()>::JOIN(network1.local_agent.clone());
+        let forged = SignedRPC {
+            public_key: network1.keypair.public_key().to_vec(),
+            signature: other.sign(&[0u8]),
+            timestamp: wall_clock_ms(),
+            trace_id: 0,
+            rpc
+        };
+        let dest = network2.address();
+        assert!(network1.socket.send_packet(forged, dest).is_ok());
+
+        match network2.dispatch_rpc() {
+            Err(SwarmError::InvalidSignature) => {}
+            _ => panic!("expected an invalid signature")
+        }
+        assert_eq!(network2.neighbors.len(), 0);
+        assert_eq!(network2.metrics().decode_failures(), 1);
+    }
+
+    #[test]
+    fn stale_rpc_test() {
+        let mut network1 = construct_network(0isize);
+        let mut network2 = construct_network(10isize);
+        let dest = network2.address();
+        let now = wall_clock_ms();
+
+        // RPCs signed too long ago, or too far ahead, are not accepted.
+        for &timestamp in [now - MAX_RPC_AGE - 1000, now + MAX_RPC_AGE + 1000].iter() {
+            let rpc = IronSwarmRPC::<isize, ()>::JOIN(network1.local_agent.clone());
+            let signed = network1.sign_rpc_at(rpc, timestamp).ok().unwrap();
+            assert!(network1.socket.send_packet(signed, dest).is_ok());
+            match network2.dispatch_rpc() {
+                Err(SwarmError::InvalidSignature) => {}
+                _ => panic!("expected a stale rpc to be rejected")
+            }
+        }
+        assert_eq!(network2.neighbors.len(), 0);
+
+        // Nor is the signing time changed after the fact.
+        let rpc = IronSwarmRPC::<isize, ()>::JOIN(network1.local_agent.clone());
+        let mut signed = network1.sign_rpc_at(rpc, now - MAX_RPC_AGE - 1000).ok().unwrap();
+        signed.timestamp = now;
+        assert!(network1.socket.send_packet(signed, dest).is_ok());
+        assert!(network2.dispatch_rpc().is_err());
+
+        network1.join(dest);
+        assert!(network2.dispatch_rpc().is_ok());
+        assert_eq!(network2.neighbors.len(), 1);
+    }
+
+    #[test]
+    fn forged_heartbeat_test() {
+        let mut network1 = construct_network(0isize);
+        let mut network2 = construct_network(10isize);
+        let mut network3 = construct_network(5isize);
+
+        // network1 claims to be network3.
+        let agn = network3.local_agent.clone();
+        let dest = network2.address();
+        assert!(network1.send_heartbeat(agn, dest).is_ok());
+
+        match network2.dispatch_rpc() {
+            Err(SwarmError::InvalidSignature) => {}
+            _ => panic!("expected an invalid signature")
+        }
+        assert_eq!(network2.neighbors.len(), 0);
+        assert!(network3.dispatch_rpc().is_err());
+    }
+
+    #[test]
+    fn forged_msg_test() {
+        let mut network1 = construct_network(0isize);
+        let mut network2 = construct_network(10isize);
+
+        // The message claims to be from network2's agent, but is unsigned.
+        let agn = network2.local_agent.clone();
+        let msg = SwarmMsg::new_converge_msg(agn, 3isize);
+        let dest = network2.address();
+        assert!(network1.send_info(10isize, 0, msg, dest).is_ok());
+
+        match network2.dispatch_rpc() {
+            Err(SwarmError::InvalidSignature) => {}
+            _ => panic!("expected an invalid signature")
+        }
+    }
+
+    #[test]
+    fn session_encryption_test() {
+        let mut network1 = construct_network(0isize);
+        let mut network2 = construct_network(10isize);
+        network1.set_encryption(Encryption::Session(b"swarm key".to_vec()));
+        network2.set_encryption(Encryption::Session(b"swarm key".to_vec()));
+        network1.neighbors.push(network2.local_agent.clone());
+
+        // The first heartbeat is encrypted with the swarm key, the ACK with
+        // the session key.
+        assert!(network1.heartbeat().is_ok());
+        let mut res = network2.dispatch_rpc();
+        assert!(res.is_ok());
+        res = network1.dispatch_rpc();
+        assert!(res.is_ok());
+
+        // Both agents know each other now.
+        assert!(network1.heartbeat().is_ok());
+        res = network2.dispatch_rpc();
+        assert!(res.is_ok());
+        res = network1.dispatch_rpc();
+        assert!(res.is_ok());
+        assert_eq!(network2.neighbors.len(), 1);
+    }
+
+    #[test]
+    fn learn_peers_test() {
+        let mut network1 = construct_network(0isize);
+        let mut network2 = construct_network(10isize);
+        let other = construct_agent();
+        network1.neighbors.push(network2.local_agent.clone());
+
+        // Agents listed by others are not bound to their addresses.
+        let ack = vec![other.clone()];
+        assert!(network2.send_heartbeat_ack(ack, network1.address()).is_ok());
+        assert!(network1.dispatch_rpc().is_ok());
+        let addr2 = network2.address();
+        let session = network1.socket.session_layer();
+        assert_eq!(session.peer_key(&addr2), Some(network2.local_agent.exchange_key()));
+        assert_eq!(session.peer_key(&other.address().socket_addr()), None);
+    }
+
+    #[test]
+    fn pre_shared_encryption_test() {
+        let mut network1 = construct_network(0isize);
+        let mut network2 = construct_network(10isize);
+        let mut network3 = construct_network(20isize);
+        network1.set_encryption(Encryption::PreShared(b"swarm key".to_vec()));
+        network2.set_encryption(Encryption::PreShared(b"swarm key".to_vec()));
+        network3.set_encryption(Encryption::PreShared(b"other key".to_vec()));
+
+        network1.join(network2.address());
+        assert!(network2.dispatch_rpc().is_ok());
+        assert_eq!(network2.neighbors.len(), 1);
+
+        network1.join(network3.address());
+        match network3.dispatch_rpc() {
+            Err(SwarmError::DecryptionFailed) => {}
+            _ => panic!("expected decryption to fail")
+        }
+        assert_eq!(network3.neighbors.len(), 0);
+    }
+
+    fn report_malicious(reporter: &mut SwarmNetwork<isize, ()>,
+                        mal: SwarmAgent<isize>,
+                        dest: SocketAddr) {
+        let mut msg = SwarmMsg::new_malicious_agent_msg(reporter.local_agent.clone(), mal);
+        msg.sign(&reporter.keypair);
+        assert!(reporter.send_broadcast(0, msg, dest).is_ok());
+    }
+
+    #[test]
+    fn malicious_reports_test() {
+        let mut network1 = construct_network(0isize);
+        let mut network2 = construct_network(10isize);
+        let mut reporter1 = construct_network(1isize);
+        let mut reporter2 = construct_network(2isize);
+        let mut reporter3 = construct_network(3isize);
+        network1.neighbors.push(network2.local_agent.clone());
+
+        // Reports from agents that are not neighbors do not count.
+        let mal = network2.local_agent.clone();
+        let dest = network1.address();
+        for loc in [4isize, 5].iter() {
+            report_malicious(&mut construct_network(*loc), mal.clone(), dest);
+            assert!(network1.dispatch_rpc().is_ok());
+        }
+        assert_eq!(network1.reputation().score(mal.id()), 0);
+
+        network1.neighbors.push(reporter1.local_agent.clone());
+        network1.neighbors.push(reporter2.local_agent.clone());
+        network1.neighbors.push(reporter3.local_agent.clone());
+        report_malicious(&mut reporter1, mal.clone(), dest);
+        assert!(network1.dispatch_rpc().is_ok());
+        report_malicious(&mut reporter2, mal.clone(), dest);
+        assert!(network1.dispatch_rpc().is_ok());
+        assert_eq!(network1.neighbors.len(), 4);
+        report_malicious(&mut reporter3, mal.clone(), dest);
+        assert!(network1.dispatch_rpc().is_ok());
+
+        assert!(!network1.reputation().is_trusted(mal.id()));
+        assert_eq!(network1.neighbors.len(), 3);
+
+        // Heartbeats and JOINs of the untrusted agent are ignored.
+        network2.neighbors.push(network1.local_agent.clone());
+        assert!(network2.heartbeat().is_ok());
+        assert!(network1.dispatch_rpc().is_ok());
+        assert!(network2.dispatch_rpc().is_err());
+
+        network2.join(dest);
+        assert!(network1.dispatch_rpc().is_ok());
+        assert_eq!(network1.neighbors.len(), 3);
+        assert!(!network1.neighbors.iter().any(|n| n.id() == mal.id()));
+    }
+
+    #[test]
+    fn location_jump_test() {
+        let mut network1 = construct_network(0isize);
+        let mut network2 = construct_network(10isize);
+        network1.set_max_location_jump(5);
+        network1.neighbors.push(network2.local_agent.clone());
+        network2.neighbors.push(network1.local_agent.clone());
+
+        network2.update_location(14isize);
+        assert!(network2.heartbeat().is_ok());
+        assert!(network1.dispatch_rpc().is_ok());
+        assert_eq!(network1.reputation().score(network2.local_agent.id()), 0);
+        assert_eq!(*network1.neighbors[0].location(), 14isize);
+
+        network2.update_location(100isize);
+        assert!(network2.heartbeat().is_ok());
+        assert!(network1.dispatch_rpc().is_ok());
+        assert!(network1.reputation().score(network2.local_agent.id()) < 0);
+        assert_eq!(network1.neighbors.len(), 1);
+    }
+
+    #[test]
+    fn location_jump_at_capacity_test() {
+        let mut network1 = construct_network(0isize);
+        let mut network2 = construct_network(10isize);
+        network1.set_max_location_jump(5);
+        network1.neighbors.push(network2.local_agent.clone());
+        network1.neighbors.push(construct_agent());
+        network1.neighbors.push(construct_agent());
+        network2.neighbors.push(network1.local_agent.clone());
+
+        // Small steps are tracked with a full neighbor list as well, and do
+        // not add up to a jump.
+        for loc in [14isize, 18, 22].iter() {
+            network2.update_location(*loc);
+            assert!(network2.heartbeat().is_ok());
+            assert!(network1.dispatch_rpc().is_ok());
+            assert_eq!(network1.reputation().score(network2.local_agent.id()), 0);
+        }
+        assert_eq!(*network1.neighbors[0].location(), 22isize);
+        assert_eq!(network1.neighbors.len(), 3);
+    }
+
+    #[test]
+    fn bad_packet_test() {
+        let mut network1 = construct_network(0isize);
+        let mut network2 = construct_network(10isize);
+        network1.neighbors.push(network2.local_agent.clone());
+        let dest = network1.address();
+
+        // Packets anyone could have sent do not count against the neighbor.
+        let forged = SignedRPC {
+            public_key: network2.keypair.public_key().to_vec(),
+            signature: Vec::new(),
+            timestamp: wall_clock_ms(),
+            trace_id: 0,
+            rpc: IronSwarmRPC::<isize, ()>::HRTBT(network2.local_agent.clone())
+        };
+        assert!(network2.socket.send_packet(forged, dest).is_ok());
+        match network1.dispatch_rpc() {
+            Err(SwarmError::InvalidSignature) => {}
+            _ => panic!("expected an invalid signature")
+        }
+        assert_eq!(network1.reputation().score(network2.local_agent.id()), 0);
+
+        // A heartbeat signed for another agent does.
+        let rpc = IronSwarmRPC::HRTBT(construct_agent());
+        assert!(network2.send_rpc(rpc, dest).is_ok());
+        match network1.dispatch_rpc() {
+            Err(SwarmError::InvalidSignature) => {}
+            _ => panic!("expected an invalid signature")
+        }
+        assert!(network1.reputation().score(network2.local_agent.id()) < 0);
+    }
+
+    #[test]
+    fn swarm_id_test() {
+        let mut network1 = construct_network(0isize);
+        let mut network2 = construct_network(10isize);
+        let mut joining = construct_network(2isize);
+        network1.neighbors.push(network2.local_agent.clone());
+        network1.set_swarm_id(7);
+        joining.set_swarm_id(7);
+
+        network2.join(network1.address());
+        match network1.dispatch_rpc() {
+            Err(SwarmError::IncompatiblePacket) => {}
+            _ => panic!("expected an incompatible packet")
+        }
+        assert_eq!(network1.rejected_packets(), 1);
+        // Packets of another swarm do not count against the sender.
+        assert_eq!(network1.reputation().score(network2.local_agent.id()), 0);
+
+        joining.join(network1.address());
+        assert!(network1.dispatch_rpc().is_ok());
+        assert_eq!(network1.neighbors.len(), 2);
+        assert_eq!(network1.rejected_packets(), 1);
+    }
+
+    struct NoHeartbeats;
+
+    impl Admission<isize> for NoHeartbeats {
+        fn admit_heartbeat(&mut self, _agn: &SwarmAgent<isize>) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn admission_hook_test() {
+        let mut network1 = construct_network(0isize);
+        let mut network2 = construct_network(10isize);
+        let mut joining = construct_network(2isize);
+        network1.set_admission(Box::new(NoHeartbeats));
+        network2.neighbors.push(network1.local_agent.clone());
+
+        assert!(network2.heartbeat().is_ok());
+        assert!(network1.dispatch_rpc().is_ok());
+        assert!(network2.dispatch_rpc().is_err());
+        assert_eq!(network1.neighbors.len(), 0);
+
+        // Nor are agents learnt of from a HRTBTACK.
+        assert!(network2.send_heartbeat_ack(Vec::new(), network1.address()).is_ok());
+        assert!(network1.dispatch_rpc().is_ok());
+        assert_eq!(network1.neighbors.len(), 0);
+
+        // JOINs are still admitted.
+        joining.join(network1.address());
+        assert!(network1.dispatch_rpc().is_ok());
+        assert_eq!(network1.neighbors.len(), 1);
+    }
+
+    #[test]
+    fn blocklist_test() {
+        let mut network1 = construct_network(0isize);
+        let mut joining1 = construct_network(2isize);
+        let mut joining2 = construct_network(3isize);
+        let blocked_id = joining1.local_agent.id().clone();
+        network1.blocklist().block_id(blocked_id);
+
+        joining1.join(network1.address());
+        assert!(network1.dispatch_rpc().is_ok());
+        assert_eq!(network1.neighbors.len(), 0);
+
+        let ip = joining2.address().ip();
+        network1.blocklist().block_ip(ip);
+        joining2.join(network1.address());
+        assert!(network1.dispatch_rpc().is_ok());
+        assert_eq!(network1.neighbors.len(), 0);
+    }
+
+    #[test]
+    fn rate_limit_test() {
+        let mut network1 = construct_network(0isize);
+        let mut joining1 = construct_network(2isize);
+        let mut joining2 = construct_network(3isize);
+        network1.set_rate_limit(1, 60_000);
+
+        joining1.join(network1.address());
+        assert!(network1.dispatch_rpc().is_ok());
+        assert_eq!(network1.neighbors.len(), 1);
+
+        // joining2 uses the same source IP as joining1.
+        joining2.join(network1.address());
+        assert!(network1.dispatch_rpc().is_ok());
+        assert_eq!(network1.neighbors.len(), 1);
+    }
+
+    #[test]
+    fn required_work_test() {
+        let mut network1 = construct_network(0isize);
+        let mut cheap = construct_network(2isize);
+        let mut worked: SwarmNetwork<isize, ()> =
+            SwarmNetwork::with_keypair(3isize, local_socket(),
+                                       Keypair::generate_with_work(12));
+        network1.set_required_work(12);
+
+        // A random id has 12 leading zero bits once in 4096 times.
+        if cheap.local_agent.id().leading_zeros() < 12 {
+            cheap.join(network1.address());
+            assert!(network1.dispatch_rpc().is_ok());
+            assert_eq!(network1.neighbors.len(), 0);
+        }
+
+        worked.join(network1.address());
+        assert!(network1.dispatch_rpc().is_ok());
+        assert_eq!(network1.neighbors.len(), 1);
+    }
 }
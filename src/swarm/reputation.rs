@@ -0,0 +1,300 @@
+// Agent Reputation
+//
+// Every agent keeps a reputation score for the other agents it has dealt with.
+// Scores are lowered by MaliciousAgent reports, once enough different agents
+// have reported the same agent, and by protocol violations that the agent
+// observes itself, such as signed RPCs that fail to verify, and location
+// updates that are impossible. Agents whose score falls below the threshold are
+// no longer routed through, and their heartbeats and JOINs are ignored. Scores
+// recover over time, so that old violations are eventually forgiven, and
+// reports expire.
+use byteid::ByteId;
+use std::cmp;
+use std::collections::HashMap;
+use clock::now_ms;
+
+// Number of distinct agents that must report an agent before the reports are
+// taken into account.
+const CORROBORATING_REPORTS: usize = 2;
+const MALICIOUS_REPORT_PENALTY: i32 = 50;
+const BAD_PACKET_PENALTY: i32 = 10;
+const LOCATION_JUMP_PENALTY: i32 = 25;
+const DEFAULT_THRESHOLD: i32 = -100;
+// Scores recover by one point every RECOVERY_MS.
+const RECOVERY_MS: u64 = 6_000;
+// Milliseconds a report is remembered for.
+const REPORT_EXPIRY_MS: u64 = 600_000;
+// Reports remembered from a single reporter, and agents reported at once.
+const MAX_REPORTS_PER_REPORTER: usize = 16;
+const MAX_REPORTED: usize = 1024;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Violation {
+    // A packet that could not be decrypted, decoded or verified.
+    BadPacket,
+    // A location update further away than an agent can possibly move.
+    LocationJump
+}
+
+pub struct Reputation {
+    scores: HashMap<ByteId, i32>,
+    // Agents that reported an agent as malicious and when, by reported agent.
+    reports: HashMap<ByteId, HashMap<ByteId, u64>>,
+    threshold: i32,
+    // When scores last recovered, in milliseconds.
+    last_decay: Option<u64>
+}
+
+impl Default for Reputation {
+    fn default() -> Reputation {
+        Reputation::new()
+    }
+}
+
+impl Reputation {
+    pub fn new() -> Reputation {
+        Reputation::with_threshold(DEFAULT_THRESHOLD)
+    }
+
+    pub fn with_threshold(threshold: i32) -> Reputation {
+        Reputation {
+            scores: HashMap::new(),
+            reports: HashMap::new(),
+            threshold,
+            last_decay: None
+        }
+    }
+
+    pub fn score(&self, id: &ByteId) -> i32 {
+        match self.scores.get(id) {
+            Some(s) => *s,
+            None => 0
+        }
+    }
+
+    pub fn is_trusted(&self, id: &ByteId) -> bool {
+        self.score(id) > self.threshold
+    }
+
+    // Record that reporter reported accused as malicious. Reports beyond the
+    // limits are ignored until older ones expire.
+    pub fn report(&mut self, reporter: &ByteId, accused: &ByteId) {
+        self.report_at(reporter, accused, now_ms());
+    }
+
+    pub fn report_at(&mut self, reporter: &ByteId, accused: &ByteId, now: u64) {
+        if reporter == accused || !self.is_trusted(reporter) {
+            return
+        }
+        self.expire_reports(now);
+
+        let made = self.reports.
+            values().
+            filter(|reporters| reporters.contains_key(reporter)).
+            count();
+        if made >= MAX_REPORTS_PER_REPORTER {
+            return
+        }
+        if !self.reports.contains_key(accused) {
+            if self.reports.len() >= MAX_REPORTED {
+                return
+            }
+            self.reports.insert(accused.clone(), HashMap::new());
+        }
+
+        let count = {
+            let reporters = self.reports.get_mut(accused).unwrap();
+            if reporters.contains_key(reporter) {
+                return
+            }
+            reporters.insert(reporter.clone(), now);
+            reporters.len()
+        };
+
+        if count >= CORROBORATING_REPORTS {
+            self.penalize(accused, MALICIOUS_REPORT_PENALTY);
+        }
+    }
+
+    fn expire_reports(&mut self, now: u64) {
+        for reporters in self.reports.values_mut() {
+            reporters.retain(|_, &mut at| at.saturating_add(REPORT_EXPIRY_MS) > now);
+        }
+        self.reports.retain(|_, reporters| !reporters.is_empty());
+    }
+
+    pub fn violation(&mut self, id: &ByteId, violation: Violation) {
+        let penalty = match violation {
+            Violation::BadPacket => BAD_PACKET_PENALTY,
+            Violation::LocationJump => LOCATION_JUMP_PENALTY
+        };
+        self.penalize(id, penalty);
+    }
+
+    fn penalize(&mut self, id: &ByteId, penalty: i32) {
+        let score = self.score(id).saturating_sub(penalty);
+        self.scores.insert(id.clone(), score);
+    }
+
+    // Let scores recover for the time passed since the last call. Should be
+    // called periodically.
+    pub fn decay(&mut self) {
+        self.decay_at(now_ms());
+    }
+
+    pub fn decay_at(&mut self, now: u64) {
+        self.expire_reports(now);
+        let last = *self.last_decay.get_or_insert(now);
+        let points = now.saturating_sub(last) / RECOVERY_MS;
+        if points == 0 {
+            return
+        }
+        self.last_decay = Some(last + points * RECOVERY_MS);
+
+        let points = cmp::min(points, i32::MAX as u64) as i32;
+        for score in self.scores.values_mut() {
+            *score = cmp::min(score.saturating_add(points), 0);
+        }
+        self.scores.retain(|_, score| *score < 0);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use byteid::ByteId;
+    use super::{Reputation, Violation};
+
+    #[test]
+    fn unknown_agent_trusted_test() {
+        let rep = Reputation::new();
+        let id = ByteId::random_id();
+
+        assert_eq!(rep.score(&id), 0);
+        assert!(rep.is_trusted(&id));
+    }
+
+    #[test]
+    fn corroborated_report_test() {
+        let mut rep = Reputation::new();
+        let accused = ByteId::random_id();
+        let reporter1 = ByteId::random_id();
+        let reporter2 = ByteId::random_id();
+        let reporter3 = ByteId::random_id();
+
+        rep.report(&reporter1, &accused);
+        assert_eq!(rep.score(&accused), 0);
+
+        // The same reporter does not corroborate its own report.
+        rep.report(&reporter1, &accused);
+        assert_eq!(rep.score(&accused), 0);
+
+        rep.report(&reporter2, &accused);
+        assert!(rep.score(&accused) < 0);
+        assert!(rep.is_trusted(&accused));
+
+        rep.report(&reporter3, &accused);
+        assert!(!rep.is_trusted(&accused));
+    }
+
+    #[test]
+    fn self_report_test() {
+        let mut rep = Reputation::new();
+        let id = ByteId::random_id();
+
+        for _ in 0usize..5 {
+            rep.report(&id, &id);
+        }
+        assert_eq!(rep.score(&id), 0);
+    }
+
+    #[test]
+    fn untrusted_reporter_test() {
+        let mut rep = Reputation::with_threshold(-1);
+        let accused = ByteId::random_id();
+        let reporter1 = ByteId::random_id();
+        let reporter2 = ByteId::random_id();
+
+        rep.violation(&reporter2, Violation::BadPacket);
+        assert!(!rep.is_trusted(&reporter2));
+
+        rep.report(&reporter1, &accused);
+        rep.report(&reporter2, &accused);
+        assert_eq!(rep.score(&accused), 0);
+    }
+
+    #[test]
+    fn report_expiry_test() {
+        let mut rep = Reputation::new();
+        let accused = ByteId::random_id();
+        let reporter1 = ByteId::random_id();
+        let reporter2 = ByteId::random_id();
+
+        // Reports too far apart do not corroborate each other.
+        rep.report_at(&reporter1, &accused, 1000);
+        rep.decay_at(1000 + super::REPORT_EXPIRY_MS);
+        assert!(rep.reports.is_empty());
+        rep.report_at(&reporter2, &accused, 2000 + super::REPORT_EXPIRY_MS);
+        assert_eq!(rep.score(&accused), 0);
+
+        rep.report_at(&reporter1, &accused, 3000 + super::REPORT_EXPIRY_MS);
+        assert!(rep.score(&accused) < 0);
+    }
+
+    #[test]
+    fn report_limit_test() {
+        let mut rep = Reputation::new();
+        let reporter = ByteId::random_id();
+
+        for _ in 0..2 * super::MAX_REPORTS_PER_REPORTER {
+            rep.report_at(&reporter, &ByteId::random_id(), 1000);
+        }
+        assert_eq!(rep.reports.len(), super::MAX_REPORTS_PER_REPORTER);
+
+        for _ in 0..2 * super::MAX_REPORTED {
+            rep.report_at(&ByteId::random_id(), &ByteId::random_id(), 1000);
+        }
+        assert_eq!(rep.reports.len(), super::MAX_REPORTED);
+
+        // Room is made as reports expire.
+        let accused = ByteId::random_id();
+        rep.report_at(&reporter, &accused, 1000 + super::REPORT_EXPIRY_MS);
+        assert!(rep.reports.contains_key(&accused));
+    }
+
+    #[test]
+    fn violation_test() {
+        let mut rep = Reputation::new();
+        let id = ByteId::random_id();
+
+        rep.violation(&id, Violation::LocationJump);
+        rep.violation(&id, Violation::BadPacket);
+        assert!(rep.score(&id) < 0);
+
+        for _ in 0usize..10 {
+            rep.violation(&id, Violation::BadPacket);
+        }
+        assert!(!rep.is_trusted(&id));
+    }
+
+    #[test]
+    fn decay_test() {
+        let mut rep = Reputation::new();
+        let id = ByteId::random_id();
+        for _ in 0usize..11 {
+            rep.violation(&id, Violation::BadPacket);
+        }
+        assert_eq!(rep.score(&id), -110);
+
+        rep.decay_at(1000);
+        assert_eq!(rep.score(&id), -110);
+        assert!(!rep.is_trusted(&id));
+        rep.decay_at(1000 + 15 * super::RECOVERY_MS + 1);
+        assert_eq!(rep.score(&id), -95);
+        assert!(rep.is_trusted(&id));
+
+        // Scores recover up to, and not beyond, zero.
+        rep.decay_at(1000 + 1000 * super::RECOVERY_MS);
+        assert_eq!(rep.score(&id), 0);
+        assert!(rep.scores.is_empty());
+    }
+}
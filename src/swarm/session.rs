@@ -0,0 +1,458 @@
+// Encrypted Transport
+//
+// Packets between agents can optionally be encrypted with XChaCha20-Poly1305.
+// In pre-shared mode, the whole swarm shares a single key. In session mode, the
+// key for a packet is agreed between the sending and the receiving agent from
+// their X25519 exchange keys. An agent learns the exchange keys of other agents
+// from the records they sign about themselves, and each session encrypted
+// packet carries the exchange key of its sender so that the receiver can
+// derive the same key. Packets to agents whose key is not yet known are
+// encrypted with the swarm key, which session mode requires for that reason.
+// Once encryption is on, cleartext packets are refused. The session layer only
+// wraps the body of a packet; the packet header stays in cleartext in front of
+// the frame, so that packets of another swarm or protocol version are told
+// apart before decrypting, and is authenticated along with the body.
+//
+// The nonce of a frame is made of a random id of the sending layer and a
+// counter, which the receiver keeps a window of per sender, so that a frame
+// can only be received once.
+use identity::Keypair;
+use chacha20poly1305::{XChaCha20Poly1305, KeyInit, XNonce};
+use chacha20poly1305::aead::{Aead, Payload};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use rand::{thread_rng, Rng};
+use std::vec::Vec;
+
+const PLAIN: u8 = 0;
+const PRE_SHARED: u8 = 1;
+const SESSION: u8 = 2;
+
+const PUBLIC_KEY_LEN: usize = 32;
+const SENDER_LEN: usize = 16;
+const COUNTER_LEN: usize = 8;
+const NONCE_LEN: usize = SENDER_LEN + COUNTER_LEN;
+const TAG_LEN: usize = 16;
+// Bytes a frame adds to the data it wraps, at most.
+pub const MAX_OVERHEAD: usize = 1 + PUBLIC_KEY_LEN + NONCE_LEN + TAG_LEN;
+// Number of counters below the highest one received that are still accepted
+// from a sender, for frames that arrive out of order.
+const REPLAY_WINDOW: u64 = 64;
+// Senders whose replay window is kept. The senders heard from least recently
+// are forgotten first.
+const MAX_SENDERS: usize = 1024;
+
+#[derive(Clone, Debug)]
+pub enum Encryption {
+    // Packets are sent in cleartext.
+    Disabled,
+    // Every packet is encrypted with a key shared by the whole swarm.
+    PreShared(Vec<u8>),
+    // Packets are encrypted with a key agreed between the two agents, or with
+    // the swarm key if the receiving agent is not known yet.
+    Session(Vec<u8>)
+}
+
+type SenderId = [u8; SENDER_LEN];
+
+// The counters received from a sender: the highest one, and a bit for each of
+// the REPLAY_WINDOW counters below it.
+struct ReplayWindow {
+    highest: u64,
+    seen: u64
+}
+
+impl ReplayWindow {
+    fn new(counter: u64) -> ReplayWindow {
+        ReplayWindow {
+            highest: counter,
+            seen: 0
+        }
+    }
+
+    // Record counter, returning whether it was not received before.
+    fn accept(&mut self, counter: u64) -> bool {
+        if counter > self.highest {
+            let shift = counter - self.highest;
+            self.seen = if shift > REPLAY_WINDOW {
+                0
+            } else {
+                // The old highest counter becomes bit shift - 1.
+                (self.seen << 1 | 1) << (shift - 1)
+            };
+            self.highest = counter;
+            return true
+        }
+
+        let age = self.highest - counter;
+        if age == 0 || age > REPLAY_WINDOW {
+            return false
+        }
+        let bit = 1u64 << (age - 1);
+        if self.seen & bit != 0 {
+            return false
+        }
+        self.seen |= bit;
+        true
+    }
+}
+
+pub struct SessionLayer {
+    mode: Encryption,
+    keypair: Option<Keypair>,
+    // Exchange keys of the agents known to this agent, by address.
+    peers: HashMap<SocketAddr, Vec<u8>>,
+    // Random id of this layer and the counter of the next frame it seals,
+    // which together make up the nonce.
+    sender: SenderId,
+    counter: u64,
+    // Replay windows of the senders received from, least recently used
+    // first.
+    windows: HashMap<SenderId, ReplayWindow>,
+    order: VecDeque<SenderId>
+}
+
+fn derive_key(secret: &[u8]) -> Vec<u8> {
+    Sha256::digest(secret).to_vec()
+}
+
+// Frame layout: header | sender | counter | ciphertext | tag. The frame header
+// is authenticated along with the ciphertext and the associated data aad.
+fn encrypt(key: &[u8], header: Vec<u8>, nonce: &[u8], aad: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut authenticated = aad.to_vec();
+    authenticated.extend_from_slice(header.as_slice());
+    let cipher = XChaCha20Poly1305::new_from_slice(key).unwrap();
+    let payload = Payload { msg: data, aad: authenticated.as_slice() };
+    let output = cipher.encrypt(XNonce::from_slice(nonce), payload).unwrap();
+
+    let mut frame = header;
+    frame.extend_from_slice(nonce);
+    frame.extend_from_slice(output.as_slice());
+    frame
+}
+
+// Returns the nonce and the data of an authentic frame.
+fn decrypt<'a>(key: &[u8], header_len: usize, aad: &[u8],
+               frame: &'a [u8]) -> Option<(&'a [u8], Vec<u8>)> {
+    if frame.len() < header_len + NONCE_LEN + TAG_LEN {
+        return None
+    }
+
+    let header = &frame[..header_len];
+    let nonce = &frame[header_len..header_len + NONCE_LEN];
+    let input = &frame[header_len + NONCE_LEN..];
+
+    let mut authenticated = aad.to_vec();
+    authenticated.extend_from_slice(header);
+    let cipher = match XChaCha20Poly1305::new_from_slice(key) {
+        Ok(cipher) => cipher,
+        Err(_) => return None
+    };
+    let payload = Payload { msg: input, aad: authenticated.as_slice() };
+    cipher.decrypt(XNonce::from_slice(nonce), payload).ok().map(|data| (nonce, data))
+}
+
+impl SessionLayer {
+    pub fn new(mode: Encryption, keypair: Option<Keypair>) -> SessionLayer {
+        let mut sender = [0u8; SENDER_LEN];
+        thread_rng().fill(&mut sender);
+
+        SessionLayer {
+            mode,
+            keypair,
+            peers: HashMap::new(),
+            sender,
+            counter: 0,
+            windows: HashMap::new(),
+            order: VecDeque::new()
+        }
+    }
+
+    pub fn disabled() -> SessionLayer {
+        SessionLayer::new(Encryption::Disabled, None)
+    }
+
+    pub fn add_peer(&mut self, addr: SocketAddr, exchange_key: &[u8]) {
+        if exchange_key.len() == PUBLIC_KEY_LEN {
+            self.peers.insert(addr, exchange_key.to_vec());
+        }
+    }
+
+    // The exchange key packets to addr are encrypted for, if known.
+    #[cfg(test)]
+    pub fn peer_key(&self, addr: &SocketAddr) -> Option<&[u8]> {
+        self.peers.get(addr).map(|k| k.as_slice())
+    }
+
+    fn swarm_key(&self) -> Option<Vec<u8>> {
+        match self.mode {
+            Encryption::PreShared(ref psk) | Encryption::Session(ref psk) => {
+                Some(derive_key(psk.as_slice()))
+            }
+            Encryption::Disabled => None
+        }
+    }
+
+    fn session_key(&self, exchange_key: &[u8]) -> Option<Vec<u8>> {
+        match (&self.mode, &self.keypair) {
+            (Encryption::Session(_), Some(keypair)) => {
+                keypair.exchange(exchange_key).map(|secret| derive_key(secret.as_slice()))
+            }
+            _ => None
+        }
+    }
+
+    fn next_nonce(&mut self) -> Vec<u8> {
+        let mut nonce = self.sender.to_vec();
+        nonce.extend_from_slice(&self.counter.to_be_bytes());
+        self.counter += 1;
+        nonce
+    }
+
+    // Record the nonce of an authentic frame, returning whether it was not
+    // received before.
+    fn accept_nonce(&mut self, nonce: &[u8]) -> bool {
+        let mut sender = [0u8; SENDER_LEN];
+        sender.copy_from_slice(&nonce[..SENDER_LEN]);
+        let mut counter = [0u8; COUNTER_LEN];
+        counter.copy_from_slice(&nonce[SENDER_LEN..]);
+        let counter = u64::from_be_bytes(counter);
+
+        if let Some(pos) = self.order.iter().position(|s| *s == sender) {
+            self.order.remove(pos);
+            self.order.push_back(sender);
+            return self.windows.get_mut(&sender).unwrap().accept(counter)
+        }
+
+        if self.order.len() >= MAX_SENDERS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.windows.remove(&oldest);
+            }
+        }
+        self.order.push_back(sender);
+        self.windows.insert(sender, ReplayWindow::new(counter));
+        true
+    }
+
+    // Wrap the encoded body of a packet for sending to dest. The packet
+    // header, sent in cleartext, is passed as aad to be authenticated.
+    pub fn seal(&mut self, dest: &SocketAddr, aad: &[u8], data: &[u8]) -> Vec<u8> {
+        let peer_key = self.peers.get(dest).and_then(|k| self.session_key(k.as_slice()));
+
+        match (peer_key, self.swarm_key()) {
+            (Some(key), _) => {
+                let mut header = vec![SESSION];
+                header.extend_from_slice(self.keypair.as_ref().unwrap().exchange_key());
+                let nonce = self.next_nonce();
+                encrypt(key.as_slice(), header, nonce.as_slice(), aad, data)
+            }
+            (None, Some(key)) => {
+                let nonce = self.next_nonce();
+                encrypt(key.as_slice(), vec![PRE_SHARED], nonce.as_slice(), aad, data)
+            }
+            (None, None) => {
+                let mut frame = vec![PLAIN];
+                frame.extend_from_slice(data);
+                frame
+            }
+        }
+    }
+
+    // Unwrap the body of a received packet, whose header is passed as aad.
+    // Returns None if the body can not be decrypted or authenticated, was
+    // received before, or is not encrypted although this agent requires it.
+    pub fn open(&mut self, aad: &[u8], frame: &[u8]) -> Option<Vec<u8>> {
+        if frame.is_empty() {
+            return None
+        }
+
+        let opened = match frame[0] {
+            PLAIN => {
+                return match self.mode {
+                    Encryption::Disabled => Some(frame[1..].to_vec()),
+                    _ => None
+                }
+            }
+            PRE_SHARED => {
+                self.swarm_key().and_then(|key| decrypt(key.as_slice(), 1, aad, frame))
+            }
+            SESSION => {
+                if frame.len() < 1 + PUBLIC_KEY_LEN {
+                    return None
+                }
+                let sender = &frame[1..1 + PUBLIC_KEY_LEN];
+                self.session_key(sender).and_then(|key| {
+                    decrypt(key.as_slice(), 1 + PUBLIC_KEY_LEN, aad, frame)
+                })
+            }
+            _ => None
+        };
+
+        match opened {
+            Some((nonce, data)) => {
+                if self.accept_nonce(nonce) {
+                    Some(data)
+                } else {
+                    None
+                }
+            }
+            None => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+    use identity::Keypair;
+    use super::{SessionLayer, Encryption, ReplayWindow, PLAIN, PRE_SHARED, SESSION,
+                MAX_SENDERS};
+
+    fn test_addr() -> SocketAddr {
+        SocketAddr::from(([127,0,0,1], 55555))
+    }
+
+    const AAD: &[u8] = b"header";
+
+    fn psk() -> Vec<u8> {
+        b"swarm key".to_vec()
+    }
+
+    #[test]
+    fn disabled_test() {
+        let mut layer = SessionLayer::disabled();
+        let data = [1u8, 2, 3];
+
+        let frame = layer.seal(&test_addr(), AAD, &data);
+        assert_eq!(frame[0], PLAIN);
+        assert_eq!(layer.open(AAD, frame.as_slice()), Some(data.to_vec()));
+    }
+
+    #[test]
+    fn pre_shared_test() {
+        let mut layer = SessionLayer::new(Encryption::PreShared(psk()), None);
+        let mut other = SessionLayer::new(Encryption::PreShared(b"other".to_vec()), None);
+        let mut plain = SessionLayer::disabled();
+        let data = [1u8, 2, 3];
+
+        let frame = layer.seal(&test_addr(), AAD, &data);
+        assert_eq!(frame[0], PRE_SHARED);
+        assert!(frame.as_slice() != data.as_slice());
+        assert_eq!(layer.open(AAD, frame.as_slice()), Some(data.to_vec()));
+        assert_eq!(other.open(AAD, frame.as_slice()), None);
+        assert_eq!(plain.open(AAD, frame.as_slice()), None);
+
+        // Cleartext packets are rejected when a swarm key is configured.
+        let clear = plain.seal(&test_addr(), AAD, &data);
+        assert_eq!(layer.open(AAD, clear.as_slice()), None);
+    }
+
+    #[test]
+    fn session_test() {
+        let key_a = Keypair::generate();
+        let key_b = Keypair::generate();
+        let mut layer_a = SessionLayer::new(Encryption::Session(psk()), Some(key_a.clone()));
+        let mut layer_b = SessionLayer::new(Encryption::Session(psk()), Some(key_b.clone()));
+        let mut layer_c = SessionLayer::new(Encryption::Session(psk()),
+                                            Some(Keypair::generate()));
+        let data = [1u8, 2, 3];
+
+        // B is not known yet, the packet is encrypted with the swarm key.
+        let frame = layer_a.seal(&test_addr(), AAD, &data);
+        assert_eq!(frame[0], PRE_SHARED);
+        assert_eq!(layer_b.open(AAD, frame.as_slice()), Some(data.to_vec()));
+
+        layer_a.add_peer(test_addr(), key_b.exchange_key());
+        let frame = layer_a.seal(&test_addr(), AAD, &data);
+        assert_eq!(frame[0], SESSION);
+        assert_eq!(layer_c.open(AAD, frame.as_slice()), None);
+        assert_eq!(layer_b.open(AAD, frame.as_slice()), Some(data.to_vec()));
+    }
+
+    #[test]
+    fn session_plain_test() {
+        let mut layer = SessionLayer::new(Encryption::Session(psk()), Some(Keypair::generate()));
+        let mut plain = SessionLayer::disabled();
+        let data = [1u8, 2, 3];
+
+        // Cleartext is neither sent nor accepted in session mode.
+        let clear = plain.seal(&test_addr(), AAD, &data);
+        assert_eq!(layer.open(AAD, clear.as_slice()), None);
+        assert!(layer.seal(&test_addr(), AAD, &data)[0] != PLAIN);
+    }
+
+    #[test]
+    fn replay_test() {
+        let mut layer_a = SessionLayer::new(Encryption::PreShared(psk()), None);
+        let mut layer_b = SessionLayer::new(Encryption::PreShared(psk()), None);
+        let data = [1u8, 2, 3];
+
+        let first = layer_a.seal(&test_addr(), AAD, &data);
+        let second = layer_a.seal(&test_addr(), AAD, &data);
+        assert!(first != second);
+
+        // Frames are accepted out of order, but only once.
+        assert!(layer_b.open(AAD, second.as_slice()).is_some());
+        assert!(layer_b.open(AAD, first.as_slice()).is_some());
+        assert!(layer_b.open(AAD, first.as_slice()).is_none());
+        assert!(layer_b.open(AAD, second.as_slice()).is_none());
+
+        // A replayed frame can not pass for another sender's either.
+        let other = SessionLayer::new(Encryption::PreShared(psk()), None).
+            seal(&test_addr(), AAD, &data);
+        assert!(layer_b.open(AAD, other.as_slice()).is_some());
+        assert!(layer_b.open(AAD, other.as_slice()).is_none());
+    }
+
+    #[test]
+    fn replay_window_test() {
+        let mut window = ReplayWindow::new(100);
+        assert!(!window.accept(100));
+        assert!(window.accept(99));
+        assert!(window.accept(36));
+        assert!(!window.accept(35));
+        assert!(!window.accept(99));
+
+        assert!(window.accept(102));
+        assert!(!window.accept(100));
+        assert!(window.accept(101));
+        assert!(!window.accept(36));
+
+        // Counters that fall out of the window are not accepted again.
+        assert!(window.accept(500));
+        assert!(!window.accept(102));
+        assert!(window.accept(499));
+    }
+
+    #[test]
+    fn senders_test() {
+        let mut layer = SessionLayer::new(Encryption::PreShared(psk()), None);
+        let data = [1u8, 2, 3];
+
+        for _ in 0..MAX_SENDERS + 10 {
+            let frame = SessionLayer::new(Encryption::PreShared(psk()), None).
+                seal(&test_addr(), AAD, &data);
+            assert!(layer.open(AAD, frame.as_slice()).is_some());
+        }
+        assert_eq!(layer.windows.len(), MAX_SENDERS);
+        assert_eq!(layer.order.len(), MAX_SENDERS);
+    }
+
+    #[test]
+    fn tampered_test() {
+        let mut layer = SessionLayer::new(Encryption::PreShared(psk()), None);
+        let data = [1u8, 2, 3];
+
+        let mut frame = layer.seal(&test_addr(), AAD, &data);
+        let last = frame.len() - 1;
+        frame[last] ^= 1;
+        assert_eq!(layer.open(AAD, frame.as_slice()), None);
+        assert_eq!(layer.open(AAD, &[SESSION, 1, 2]), None);
+        assert_eq!(layer.open(AAD, &[]), None);
+
+        // The associated data is authenticated along with the frame.
+        let frame = layer.seal(&test_addr(), AAD, &data);
+        assert_eq!(layer.open(b"heades", frame.as_slice()), None);
+    }
+}
@@ -1,86 +1,299 @@
-use rustc_serialize::{Decodable, Decoder, Encodable, Encoder};
-use std::io::net::ip::{SocketAddr, ToSocketAddr};
-use std::io::net::udp::UdpSocket;
-use std::io::IoErrorKind;
-use std::io::MemWriter;
-use std::io::{IoResult, IoError, BufReader};
-use bincode::{self, decode_from, encode, DecodingError, EncodingError};
-use bincode::DecoderReader;
-use bincode::EncoderWriter;
-use std::mem;
-use std::error::FromError;
-
-pub const MAX_PACKET_SIZE: usize = 1024;
-const SOCKET_TIMEOUT: Option<u64> = Some(1000);
-
-#[derive(RustcDecodable, RustcEncodable, Show)]
-struct Packet<B> {
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+use codec::{self, Codec, CodecError, WireFormat};
+use swarm::session::{self, SessionLayer};
+use swarm::capture::{CaptureWriter, CapturedPacket, Direction};
+
+// The minimum IPv6 MTU of 1280 bytes less the IPv6 and UDP headers, so that
+// packets are never fragmented.
+pub const MAX_PACKET_SIZE: usize = 1232;
+const SOCKET_TIMEOUT: Option<Duration> = Some(Duration::from_millis(1000));
+
+// "IRSW", identifies Iron Swarm packets.
+const MAGIC: u32 = 0x49525357;
+pub const PROTOCOL_VERSION: u8 = 6;
+
+// Type of the body of a packet, carried in the packet header.
+pub trait PacketType {
+    fn packet_type(&self) -> u8;
+}
+
+// Every packet starts with a header, which is checked before the body is
+// decrypted and decoded. Packets of a different protocol version or of another
+// swarm are rejected. The header is sent in cleartext in front of the session
+// frame of the body, which authenticates it, and has a fixed binary layout,
+// independent of the codec the body is encoded with:
+//
+// magic (4, big-endian) | version (1) | swarm id (4, big-endian) | type (1) |
+// wire format (1)
+#[derive(Clone, Eq, PartialEq, Debug)]
+struct Header {
+    magic: u32,
+    version: u8,
+    swarm_id: u32,
+    packet_type: u8,
+    format: u8
+}
+
+const HEADER_LEN: usize = 11;
+
+// A decoded packet in a form suitable for printing, see packet_to_json.
+#[derive(Serialize)]
+struct PacketDump<B> {
+    version: u8,
+    swarm_id: u32,
+    packet_type: u8,
+    format: WireFormat,
     body: B
 }
 
-impl<B> Packet<B> {
-    pub fn inner(self) -> B {
-        self.body
+fn write_u32(buf: &mut Vec<u8>, n: u32) {
+    buf.push((n >> 24) as u8);
+    buf.push((n >> 16) as u8);
+    buf.push((n >> 8) as u8);
+    buf.push(n as u8);
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    (data[0] as u32) << 24 | (data[1] as u32) << 16 |
+        (data[2] as u32) << 8 | data[3] as u32
+}
+
+impl Header {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(HEADER_LEN);
+        write_u32(&mut buf, self.magic);
+        buf.push(self.version);
+        write_u32(&mut buf, self.swarm_id);
+        buf.push(self.packet_type);
+        buf.push(self.format);
+        buf
     }
+
+    fn from_bytes(data: &[u8]) -> Option<Header> {
+        if data.len() < HEADER_LEN {
+            return None
+        }
+
+        Some(Header {
+            magic: read_u32(&data[0..4]),
+            version: data[4],
+            swarm_id: read_u32(&data[5..9]),
+            packet_type: data[9],
+            format: data[10]
+        })
+    }
+}
+
+// Decode the body of a packet in the wire format given by its header. A
+// socket accepts packets in any format, so that agents can be switched to JSON
+// for debugging without reconfiguring the rest of the swarm.
+fn decode_body<B>(header: &Header, data: &[u8]) -> SwarmResult<B>
+where B: DeserializeOwned + PacketType {
+    let format = match WireFormat::from_id(header.format) {
+        Some(format) => format,
+        None => return Err(SwarmError::InvalidPacket)
+    };
+
+    let body: B = format.decode(&data[HEADER_LEN..])?;
+    if body.packet_type() != header.packet_type {
+        return Err(SwarmError::InvalidPacket)
+    }
+    Ok(body)
+}
+
+// The cleartext of a packet as sent over the network: its header followed by
+// its decrypted body, as captured. Returns None if the body can not be
+// decrypted.
+pub fn open_packet(session: &mut SessionLayer, packet: &[u8]) -> Option<Vec<u8>> {
+    if packet.len() < HEADER_LEN {
+        return None
+    }
+
+    let (header, frame) = packet.split_at(HEADER_LEN);
+    session.open(header, frame).map(|body| {
+        let mut data = header.to_vec();
+        data.extend_from_slice(body.as_slice());
+        data
+    })
+}
+
+// Render a cleartext packet, see open_packet, as pretty-printed JSON,
+// regardless of the wire format it was sent in.
+pub fn packet_to_json<B>(data: &[u8]) -> SwarmResult<String>
+where B: Serialize + DeserializeOwned + PacketType {
+    let header = match Header::from_bytes(data) {
+        Some(header) => header,
+        None => return Err(SwarmError::IncompatiblePacket)
+    };
+    if header.magic != MAGIC || header.version != PROTOCOL_VERSION {
+        return Err(SwarmError::IncompatiblePacket)
+    }
+
+    let body: B = decode_body(&header, data)?;
+    let dump = PacketDump {
+        version: header.version,
+        swarm_id: header.swarm_id,
+        packet_type: header.packet_type,
+        format: WireFormat::from_id(header.format).unwrap(),
+        body
+    };
+    Ok(codec::to_json_pretty(&dump)?)
 }
 
 pub struct SwarmSocket {
     recv_buf: [u8; MAX_PACKET_SIZE],
-    socket: UdpSocket
+    socket: UdpSocket,
+    session: SessionLayer,
+    // Source address of the last received packet.
+    last_peer: Option<SocketAddr>,
+    // Size of the last packet received.
+    last_size: usize,
+    swarm_id: u32,
+    codec: WireFormat,
+    // Number of packets rejected because of an incompatible header.
+    rejected: u64,
+    capture: Option<CaptureWriter>,
+    // Packets sent while replaying a capture, which are not actually sent.
+    replay_log: Option<Vec<CapturedPacket>>
 }
 
 pub type SwarmResult<R> = Result<R, SwarmError>;
 
-fn io_to_swarm_result<R>(res: IoResult<R>) -> SwarmResult<R> {
+fn io_to_swarm_result<R>(res: io::Result<R>) -> SwarmResult<R> {
     match res {
         Ok(r) => Ok(r),
         Err(err) => Err(SwarmError::IoError(err))
     }
 }
 
-#[derive(Show)]
+#[derive(Debug)]
 pub enum SwarmError {
-    IoError(IoError),
-}
-
-impl FromError<DecodingError> for SwarmError {
-    fn from_error(err: DecodingError) -> SwarmError {
-        match err {
-            DecodingError::IoError(err) => SwarmError::IoError(err),
-            _ => panic!("{:?}", err)
-        }
-    }
+    IoError(io::Error),
+    // A received RPC was not signed by the agent it claims to be from.
+    InvalidSignature,
+    // A received packet could not be decrypted.
+    DecryptionFailed,
+    // A received packet could not be decoded.
+    InvalidPacket,
+    // A received packet belongs to another swarm or protocol version.
+    IncompatiblePacket,
+    // A packet to be sent could not be encoded.
+    EncodingFailed,
+    // A packet to be sent exceeds MAX_PACKET_SIZE.
+    PacketTooLarge,
 }
 
-impl FromError<EncodingError> for SwarmError {
-    fn from_error(err: EncodingError) -> SwarmError {
+impl From<CodecError> for SwarmError {
+    fn from(err: CodecError) -> SwarmError {
         match err {
-            EncodingError::IoError(err) => SwarmError::IoError(err),
-            _ => panic!("{:?}", err)
+            CodecError::Encoding(_) => SwarmError::EncodingFailed,
+            CodecError::Decoding(_) => SwarmError::InvalidPacket
         }
     }
 }
 
-impl FromError<IoError> for SwarmError {
-    fn from_error(err: IoError) -> SwarmError {
+impl From<io::Error> for SwarmError {
+    fn from(err: io::Error) -> SwarmError {
         SwarmError::IoError(err)
     }
 }
 
 impl SwarmSocket {
-    pub fn new<A: ToSocketAddr>(address: A) -> SwarmSocket {
-        let mut socket = match UdpSocket::bind(address) {
+    pub fn new<A: ToSocketAddrs>(address: A) -> SwarmSocket {
+        let socket = match UdpSocket::bind(address) {
             Ok(s) => s,
             Err(e) => panic!("Could not bind socket: {}", e)
         };
         SwarmSocket {
             recv_buf: [0u8; MAX_PACKET_SIZE],
-            socket: socket,
+            socket,
+            session: SessionLayer::disabled(),
+            last_peer: None,
+            last_size: 0,
+            swarm_id: 0,
+            codec: WireFormat::Bincode,
+            rejected: 0,
+            capture: None,
+            replay_log: None
         }
     }
 
+    pub fn set_session_layer(&mut self, session: SessionLayer) {
+        self.session = session;
+    }
+
+    pub fn session_layer(&mut self) -> &mut SessionLayer {
+        &mut self.session
+    }
+
+    pub fn last_peer(&self) -> Option<SocketAddr> {
+        self.last_peer
+    }
+
+    pub fn last_packet_size(&self) -> usize {
+        self.last_size
+    }
+
+    pub fn set_swarm_id(&mut self, swarm_id: u32) {
+        self.swarm_id = swarm_id;
+    }
+
+    // Encoding of the packet bodies sent by this socket. Packets are accepted
+    // in any format.
+    pub fn set_wire_format(&mut self, format: WireFormat) {
+        self.codec = format;
+    }
+
+    pub fn rejected_packets(&self) -> u64 {
+        self.rejected
+    }
+
+    // Record every packet sent and received to a capture, or stop recording.
+    pub fn set_capture(&mut self, capture: Option<CaptureWriter>) {
+        self.capture = capture;
+    }
+
+    // Capturing is best effort, a failing capture file does not stop the
+    // agent from working.
+    fn record(&mut self, direction: Direction, peer: SocketAddr, data: &[u8]) {
+        if let Some(ref mut capture) = self.capture {
+            let packet = CapturedPacket::new(direction, peer, data.to_vec());
+            let _ = capture.write(&packet);
+        }
+    }
+
+    // While replaying, packets are logged instead of sent.
+    pub fn start_replay(&mut self) {
+        self.replay_log = Some(Vec::new());
+    }
+
+    // Stop replaying and return the packets that would have been sent.
+    pub fn stop_replay(&mut self) -> Vec<CapturedPacket> {
+        self.replay_log.take().unwrap_or_default()
+    }
+
+    fn header(&self, packet_type: u8) -> Header {
+        Header {
+            magic: MAGIC,
+            version: PROTOCOL_VERSION,
+            swarm_id: self.swarm_id,
+            packet_type,
+            format: self.codec.id()
+        }
+    }
+
+    fn is_compatible(&self, header: &Header) -> bool {
+        header.magic == MAGIC &&
+            header.version == PROTOCOL_VERSION &&
+            header.swarm_id == self.swarm_id
+    }
+
     pub fn socket_name(&mut self) -> SocketAddr {
-        match self.socket.socket_name() {
+        match self.socket.local_addr() {
             Ok(a) => a,
             Err(e) => panic!("Could not get socket name: {}", e)
         }
@@ -89,66 +302,119 @@ impl SwarmSocket {
 
 // Implement receiving of packets through the UDP socket.
 impl SwarmSocket {
-    pub fn recv_msg<'a, B>(&mut self) -> SwarmResult<B>
-    where B: Decodable {
-        self.socket.set_timeout(SOCKET_TIMEOUT);
+    pub fn recv_msg<B>(&mut self) -> SwarmResult<B>
+    where B: DeserializeOwned + PacketType {
+        self.socket.set_read_timeout(SOCKET_TIMEOUT)?;
         match self.socket.recv_from(&mut self.recv_buf) {
-            Ok((amt, _)) => {
-                //XXX: transmuting the buffer in order to get appropriate lifetime.
-                let transmuted_buf = unsafe {
-                    mem::transmute(self.recv_buf.slice_to(amt))
+            Ok((amt, src)) => {
+                self.last_peer = Some(src);
+                let compatible = match Header::from_bytes(&self.recv_buf[..amt]) {
+                    Some(ref header) => self.is_compatible(header),
+                    None => false
+                };
+                if !compatible {
+                    self.rejected += 1;
+                    return Err(SwarmError::IncompatiblePacket)
+                }
+
+                let data = match open_packet(&mut self.session, &self.recv_buf[..amt]) {
+                    Some(data) => data,
+                    None => return Err(SwarmError::DecryptionFailed)
                 };
-                let limit = bincode::SizeLimit::UpperBound(MAX_PACKET_SIZE as u64);
-                let pkt: Packet<B> = try!(decode_from(
-                        &mut BufReader::new(transmuted_buf),
-                        limit));
-                Ok(pkt.inner())
+
+                self.record(Direction::Received, src, data.as_slice());
+                let res = self.decode_msg(src, data.as_slice());
+                self.last_size = amt;
+                res
             }
             Err(e) => Err(SwarmError::IoError(e))
         }
     }
+
+    // Decode a cleartext packet received from src, e.g. one read from a
+    // capture.
+    pub fn decode_msg<B>(&mut self, src: SocketAddr, data: &[u8]) -> SwarmResult<B>
+    where B: DeserializeOwned + PacketType {
+        self.last_peer = Some(src);
+        self.last_size = data.len();
+        let header = match Header::from_bytes(data) {
+            Some(ref header) if self.is_compatible(header) => header.clone(),
+            _ => {
+                self.rejected += 1;
+                return Err(SwarmError::IncompatiblePacket)
+            }
+        };
+
+        decode_body(&header, data)
+    }
 }
 
 // Implement sending of IronSwarmRPC through the UDP socket.
 impl SwarmSocket {
-    pub fn send_packet<B:Encodable, A: ToSocketAddr>(&mut self, body: B, dest: A) -> SwarmResult<()> {
-        let packet = Packet { body: body };
-        let limit = bincode::SizeLimit::UpperBound(MAX_PACKET_SIZE as u64);
-        let encoded = try!(encode(&packet, limit));
-        if encoded.len() > MAX_PACKET_SIZE {
-            Err(SwarmError::IoError(IoError {
-                kind: IoErrorKind::OtherIoError,
-                desc: "Packet exceed maximum size",
-                detail: Some(format!("Maximum size is {} bytes", MAX_PACKET_SIZE)),
-            }))
+    // The size of the packet body would be sent in, however it is sealed.
+    pub fn packet_size<B: Serialize>(&self, body: &B) -> SwarmResult<usize> {
+        Ok(HEADER_LEN + self.codec.encode(body)?.len() + session::MAX_OVERHEAD)
+    }
+
+    pub fn send_packet<B, A>(&mut self, body: B, dest: A) -> SwarmResult<()>
+    where B: Serialize + PacketType, A: ToSocketAddrs {
+        let header = self.header(body.packet_type()).to_bytes();
+        let mut encoded = header.clone();
+        encoded.extend_from_slice(self.codec.encode(&body)?.as_slice());
+        let dest = match dest.to_socket_addrs()?.next() {
+            Some(dest) => dest,
+            None => return Err(SwarmError::IoError(
+                io::Error::new(io::ErrorKind::InvalidInput, "no address to send to")))
+        };
+        if let Some(ref mut log) = self.replay_log {
+            log.push(CapturedPacket::new(Direction::Sent, dest, encoded));
+            return Ok(())
+        }
+
+        let mut sealed = header;
+        sealed.extend(self.session.seal(&dest, sealed.as_slice(), &encoded[HEADER_LEN..]));
+        if sealed.len() > MAX_PACKET_SIZE {
+            Err(SwarmError::PacketTooLarge)
         } else {
-            self.socket.set_timeout(SOCKET_TIMEOUT);
-            io_to_swarm_result(self.socket.send_to(encoded.as_slice(), dest))
+            self.record(Direction::Sent, dest, encoded.as_slice());
+            self.socket.set_write_timeout(SOCKET_TIMEOUT)?;
+            io_to_swarm_result(self.socket.send_to(sealed.as_slice(), dest).map(|_| ()))
         }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use std::io::net::ip::{SocketAddr, Ipv4Addr};
-    use std::io::net::udp::UdpSocket;
-    use std::io::test::next_test_port;
+    use std::net::SocketAddr;
+    use std::net::UdpSocket;
     use std::vec::Vec;
-    use super::{SwarmSocket, SwarmResult};
+    use super::{SwarmSocket, SwarmResult, SwarmError, PacketType, packet_to_json, open_packet};
+    use super::MAX_PACKET_SIZE;
+    use std::iter::repeat_n;
+    use swarm::session::{SessionLayer, Encryption};
+    use swarm::capture::{CaptureWriter, Direction, read_capture};
+    use codec::WireFormat;
+    use tempfile::TempDir;
 
     fn local_socket() -> SocketAddr {
-        SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: next_test_port() }
+        SocketAddr::from(([127, 0, 0, 1], 0))
     }
 
     fn construct_swarm_socket_with_local_socket() -> SwarmSocket {
         SwarmSocket::new(local_socket())
     }
 
-    #[derive(Show, RustcDecodable, RustcEncodable)]
+    #[derive(Debug, Serialize, Deserialize)]
     struct Test<T> {
         body: T
     }
 
+    impl<T> PacketType for Test<T> {
+        fn packet_type(&self) -> u8 {
+            1
+        }
+    }
+
     #[test]
     fn socket_test() {
         let mut from_socket = construct_swarm_socket_with_local_socket();
@@ -161,4 +427,259 @@ mod test {
         let res: SwarmResult<Test<u8>> = to_socket.recv_msg();
         assert!(res.is_ok());
     }
+
+    #[test]
+    fn encrypted_socket_test() {
+        let mut from_socket = construct_swarm_socket_with_local_socket();
+        let mut to_socket = construct_swarm_socket_with_local_socket();
+        let mut plain_socket = construct_swarm_socket_with_local_socket();
+        let psk = b"swarm key".to_vec();
+        from_socket.set_session_layer(
+            SessionLayer::new(Encryption::PreShared(psk.clone()), None));
+        to_socket.set_session_layer(
+            SessionLayer::new(Encryption::PreShared(psk), None));
+
+        let socket_addr = to_socket.socket_name();
+        let res = from_socket.send_packet(Test { body: 27u8 }, socket_addr);
+        assert!(res.is_ok());
+        let res: SwarmResult<Test<u8>> = to_socket.recv_msg();
+        assert_eq!(res.ok().unwrap().body, 27u8);
+
+        let socket_addr = plain_socket.socket_name();
+        let res = from_socket.send_packet(Test { body: 27u8 }, socket_addr);
+        assert!(res.is_ok());
+        let res: SwarmResult<Test<u8>> = plain_socket.recv_msg();
+        match res {
+            Err(SwarmError::DecryptionFailed) => {}
+            _ => panic!("expected decryption to fail")
+        }
+    }
+
+    #[test]
+    fn invalid_packet_test() {
+        let mut from_socket = construct_swarm_socket_with_local_socket();
+        let mut to_socket = construct_swarm_socket_with_local_socket();
+        let from_addr = from_socket.socket_name();
+        let socket_addr = to_socket.socket_name();
+
+        let res = from_socket.send_packet(Test { body: 27u8 }, socket_addr);
+        assert!(res.is_ok());
+        let res: SwarmResult<Test<bool>> = to_socket.recv_msg();
+        match res {
+            Err(SwarmError::InvalidPacket) => {}
+            _ => panic!("expected an invalid packet")
+        }
+        assert_eq!(to_socket.last_peer(), Some(from_addr));
+    }
+
+    #[test]
+    fn packet_too_large_test() {
+        let mut from_socket = construct_swarm_socket_with_local_socket();
+        let socket_addr = from_socket.socket_name();
+        let body = Test { body: repeat_n(0u8, MAX_PACKET_SIZE).collect::<Vec<u8>>() };
+
+        match from_socket.send_packet(body, socket_addr) {
+            Err(SwarmError::PacketTooLarge) => {}
+            _ => panic!("expected the packet to be too large")
+        }
+    }
+
+    #[test]
+    fn swarm_id_test() {
+        let mut from_socket = construct_swarm_socket_with_local_socket();
+        let mut to_socket = construct_swarm_socket_with_local_socket();
+        from_socket.set_swarm_id(1);
+        to_socket.set_swarm_id(2);
+        let socket_addr = to_socket.socket_name();
+
+        let res = from_socket.send_packet(Test { body: 27u8 }, socket_addr);
+        assert!(res.is_ok());
+        let res: SwarmResult<Test<u8>> = to_socket.recv_msg();
+        match res {
+            Err(SwarmError::IncompatiblePacket) => {}
+            _ => panic!("expected an incompatible packet")
+        }
+        assert_eq!(to_socket.rejected_packets(), 1);
+
+        to_socket.set_swarm_id(1);
+        let res = from_socket.send_packet(Test { body: 27u8 }, socket_addr);
+        assert!(res.is_ok());
+        let res: SwarmResult<Test<u8>> = to_socket.recv_msg();
+        assert!(res.is_ok());
+        assert_eq!(to_socket.rejected_packets(), 1);
+    }
+
+    #[test]
+    fn encrypted_header_test() {
+        let mut from_socket = construct_swarm_socket_with_local_socket();
+        let mut to_socket = construct_swarm_socket_with_local_socket();
+        let raw_socket = UdpSocket::bind(local_socket()).ok().unwrap();
+        from_socket.set_session_layer(
+            SessionLayer::new(Encryption::PreShared(b"swarm key".to_vec()), None));
+        to_socket.set_session_layer(
+            SessionLayer::new(Encryption::PreShared(b"other key".to_vec()), None));
+        from_socket.set_swarm_id(1);
+        to_socket.set_swarm_id(2);
+
+        // Encrypted packets of another swarm are told apart by their header.
+        let socket_addr = to_socket.socket_name();
+        assert!(from_socket.send_packet(Test { body: 27u8 }, socket_addr).is_ok());
+        let res: SwarmResult<Test<u8>> = to_socket.recv_msg();
+        match res {
+            Err(SwarmError::IncompatiblePacket) => {}
+            _ => panic!("expected an incompatible packet")
+        }
+        assert_eq!(to_socket.rejected_packets(), 1);
+
+        // The header is authenticated along with the body.
+        let raw_addr = raw_socket.local_addr().ok().unwrap();
+        assert!(from_socket.send_packet(Test { body: 27u8 }, raw_addr).is_ok());
+        let mut buf = [0u8; 128];
+        let (amt, _) = raw_socket.recv_from(&mut buf).ok().unwrap();
+        let mut packet = buf[..amt].to_vec();
+        packet[9] ^= 1;
+        let mut psk = SessionLayer::new(Encryption::PreShared(b"swarm key".to_vec()), None);
+        assert!(open_packet(&mut psk, packet.as_slice()).is_none());
+        assert!(open_packet(&mut psk, &buf[..amt]).is_some());
+    }
+
+    #[test]
+    fn garbage_packet_test() {
+        let from_socket = UdpSocket::bind(local_socket()).ok().unwrap();
+        let mut to_socket = construct_swarm_socket_with_local_socket();
+        let socket_addr = to_socket.socket_name();
+
+        // A cleartext frame that is not an Iron Swarm packet.
+        let garbage = [0u8, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+        assert!(from_socket.send_to(&garbage, socket_addr).is_ok());
+        let res: SwarmResult<Test<u8>> = to_socket.recv_msg();
+        match res {
+            Err(SwarmError::IncompatiblePacket) => {}
+            _ => panic!("expected an incompatible packet")
+        }
+
+        // Too short to contain a header.
+        assert!(from_socket.send_to(&[0u8, 1], socket_addr).is_ok());
+        let res: SwarmResult<Test<u8>> = to_socket.recv_msg();
+        assert!(res.is_err());
+        assert_eq!(to_socket.rejected_packets(), 2);
+    }
+
+    #[test]
+    fn wire_format_test() {
+        let formats = [WireFormat::Bincode, WireFormat::Json, WireFormat::MessagePack];
+        for &format in formats.iter() {
+            let mut from_socket = construct_swarm_socket_with_local_socket();
+            let mut to_socket = construct_swarm_socket_with_local_socket();
+            from_socket.set_wire_format(format);
+            to_socket.set_wire_format(format);
+            let socket_addr = to_socket.socket_name();
+
+            let res = from_socket.send_packet(Test { body: 27u8 }, socket_addr);
+            assert!(res.is_ok());
+            let res: SwarmResult<Test<u8>> = to_socket.recv_msg();
+            assert_eq!(res.ok().unwrap().body, 27u8);
+        }
+
+        // Packets are accepted in any format.
+        let mut from_socket = construct_swarm_socket_with_local_socket();
+        let mut to_socket = construct_swarm_socket_with_local_socket();
+        from_socket.set_wire_format(WireFormat::Json);
+        let socket_addr = to_socket.socket_name();
+
+        let res = from_socket.send_packet(Test { body: 27u8 }, socket_addr);
+        assert!(res.is_ok());
+        let res: SwarmResult<Test<u8>> = to_socket.recv_msg();
+        assert_eq!(res.ok().unwrap().body, 27u8);
+    }
+
+    #[test]
+    fn json_wire_test() {
+        let mut from_socket = construct_swarm_socket_with_local_socket();
+        let to_socket = UdpSocket::bind(local_socket()).ok().unwrap();
+        from_socket.set_wire_format(WireFormat::Json);
+        let socket_addr = to_socket.local_addr().ok().unwrap();
+
+        let res = from_socket.send_packet(Test { body: 27u8 }, socket_addr);
+        assert!(res.is_ok());
+        let mut buf = [0u8; 64];
+        let (amt, _) = to_socket.recv_from(&mut buf).ok().unwrap();
+
+        // Header and session frame byte, followed by plain JSON.
+        let json = &buf[super::HEADER_LEN + 1..amt];
+        assert_eq!(json, b"{\"body\":27}");
+    }
+
+    #[test]
+    fn packet_to_json_test() {
+        let mut from_socket = construct_swarm_socket_with_local_socket();
+        let to_socket = UdpSocket::bind(local_socket()).ok().unwrap();
+        from_socket.set_swarm_id(7);
+        let socket_addr = to_socket.local_addr().ok().unwrap();
+
+        let res = from_socket.send_packet(Test { body: 27u8 }, socket_addr);
+        assert!(res.is_ok());
+        let mut buf = [0u8; 64];
+        let (amt, _) = to_socket.recv_from(&mut buf).ok().unwrap();
+
+        let data = open_packet(&mut SessionLayer::disabled(), &buf[..amt]).unwrap();
+        let json = packet_to_json::<Test<u8>>(data.as_slice()).ok().unwrap();
+        assert!(json.contains("\"swarm_id\": 7"));
+        assert!(json.contains("\"format\": \"Bincode\""));
+        assert!(json.contains("\"body\": 27"));
+
+        match packet_to_json::<Test<u8>>(&[1u8, 2, 3]) {
+            Err(SwarmError::IncompatiblePacket) => {}
+            _ => panic!("expected an incompatible packet")
+        }
+    }
+
+    #[test]
+    fn capture_test() {
+        let dir = TempDir::new().ok().unwrap();
+        let path = dir.path().join("swarm.cap");
+        let mut from_socket = construct_swarm_socket_with_local_socket();
+        let mut to_socket = construct_swarm_socket_with_local_socket();
+        let from_addr = from_socket.socket_name();
+        let to_addr = to_socket.socket_name();
+        to_socket.set_capture(Some(CaptureWriter::create(&path).ok().unwrap()));
+
+        assert!(from_socket.send_packet(Test { body: 27u8 }, to_addr).is_ok());
+        let res: SwarmResult<Test<u8>> = to_socket.recv_msg();
+        assert!(res.is_ok());
+        assert!(to_socket.send_packet(Test { body: 28u8 }, from_addr).is_ok());
+        to_socket.set_capture(None);
+
+        let packets = read_capture(&path).ok().unwrap();
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].direction(), Direction::Received);
+        assert_eq!(packets[0].peer(), from_addr);
+        assert_eq!(packets[1].direction(), Direction::Sent);
+        assert_eq!(packets[1].peer(), from_addr);
+
+        // A captured packet decodes like the packet received from the socket.
+        let res: SwarmResult<Test<u8>> =
+            to_socket.decode_msg(packets[0].peer(), packets[0].data());
+        assert_eq!(res.ok().unwrap().body, 27u8);
+    }
+
+    #[test]
+    fn replay_log_test() {
+        let mut from_socket = construct_swarm_socket_with_local_socket();
+        let mut to_socket = construct_swarm_socket_with_local_socket();
+        let to_addr = to_socket.socket_name();
+
+        from_socket.start_replay();
+        assert!(from_socket.send_packet(Test { body: 27u8 }, to_addr).is_ok());
+        let log = from_socket.stop_replay();
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].peer(), to_addr);
+
+        // Nothing was sent.
+        let res: SwarmResult<Test<u8>> = to_socket.recv_msg();
+        match res {
+            Err(SwarmError::IoError(_)) => {}
+            _ => panic!("expected a timeout")
+        }
+    }
 }
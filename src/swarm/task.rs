@@ -0,0 +1,424 @@
+// Task Allocation
+//
+// Tasks are handed out with the contract net protocol, carried as Task events
+// in signed SwarmMsgs:
+//
+// 1. The announcing agent routes an Announce with INFO towards the location
+//    of the task. The agent closest to it BROADCASTs the announcement on to
+//    its neighbors, and agents within the radius of the task pass it on in
+//    turn, up to a limited number of hops.
+// 2. Every agent within the radius of the task with spare capacity BROADCASTs
+//    a Bid straight back to the announcer. A bid is a cost, lower is better,
+//    which grows with the distance to the task and the tasks already taken.
+// 3. Once bidding has gone on long enough, the announcer Awards the task to
+//    the lowest bid, telling every bidder who won.
+// 4. The winner carries out the task and Reports its completion or failure
+//    back to the announcer.
+//
+// Because the messages are signed, an Award is only accepted from the agent
+// that announced the task, and a Report only from the agent it was awarded
+// to. A TaskBoard keeps the state of both sides of the protocol for an agent.
+// It remembers a bounded number of the announcements it saw and of the tasks
+// it bid on, forgetting the oldest first.
+use agent::SwarmAgent;
+use std::collections::{HashMap, HashSet, VecDeque};
+use rand::{thread_rng, Rng};
+use std::vec::Vec;
+use Location;
+
+pub type TaskId = u64;
+
+// Announcements remembered, so that each is bid on and passed on once.
+const MAX_SEEN: usize = 1024;
+// Tasks bid on and waiting for an award.
+const MAX_BIDS: usize = 64;
+// Bids kept for a task announced by this agent, the lowest first.
+const MAX_BIDDERS: usize = 64;
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Task<Loc> {
+    id: TaskId,
+    location: Loc,
+    // Only agents within radius of the location bid.
+    radius: usize,
+    announcer: SwarmAgent<Loc>,
+    // Application defined description of the task.
+    description: Vec<u8>
+}
+
+impl<Loc> Task<Loc> {
+    pub fn new(location: Loc, radius: usize, announcer: SwarmAgent<Loc>,
+               description: Vec<u8>) -> Task<Loc> {
+        Task {
+            id: thread_rng().gen(),
+            location,
+            radius,
+            announcer,
+            description
+        }
+    }
+
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    pub fn location(&self) -> &Loc {
+        &self.location
+    }
+
+    pub fn radius(&self) -> usize {
+        self.radius
+    }
+
+    pub fn announcer(&self) -> &SwarmAgent<Loc> {
+        &self.announcer
+    }
+
+    pub fn description(&self) -> &[u8] {
+        self.description.as_slice()
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub enum TaskOutcome {
+    Completed,
+    Failed(String)
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub enum TaskMsg<Loc> {
+    Announce(Task<Loc>),
+    // The task and the cost of the bid; the bidder is the sender.
+    Bid(TaskId, u64),
+    // The task and the agent it is awarded to.
+    Award(TaskId, SwarmAgent<Loc>),
+    Report(TaskId, TaskOutcome)
+}
+
+// State of a task announced by this agent.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum TaskStatus<Loc> {
+    // Bids received so far.
+    Bidding(usize),
+    Awarded(SwarmAgent<Loc>),
+    Done(SwarmAgent<Loc>, TaskOutcome)
+}
+
+struct Announced<Loc> {
+    // Bids by cost, along with the bidding agents.
+    bids: Vec<(u64, SwarmAgent<Loc>)>,
+    status: TaskStatus<Loc>
+}
+
+pub struct TaskBoard<Loc> {
+    // Tasks this agent takes on at the same time.
+    capacity: usize,
+    announced: HashMap<TaskId, Announced<Loc>>,
+    // Tasks this agent bid on, by id, and their ids, oldest first.
+    bid_on: HashMap<TaskId, Task<Loc>>,
+    bid_order: VecDeque<TaskId>,
+    // Announcements seen, so that each is bid on and passed on once, and
+    // their ids, oldest first.
+    seen: HashSet<TaskId>,
+    seen_order: VecDeque<TaskId>,
+    // Tasks awarded to this agent and not yet reported on.
+    assigned: Vec<Task<Loc>>,
+    // Tasks awarded since take_assignments was last called.
+    new_assignments: Vec<Task<Loc>>
+}
+
+impl<Loc: Location + Clone> TaskBoard<Loc> {
+    pub fn new(capacity: usize) -> TaskBoard<Loc> {
+        TaskBoard {
+            capacity,
+            announced: HashMap::new(),
+            bid_on: HashMap::new(),
+            bid_order: VecDeque::new(),
+            seen: HashSet::new(),
+            seen_order: VecDeque::new(),
+            assigned: Vec::new(),
+            new_assignments: Vec::new()
+        }
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+    }
+
+    pub fn announce(&mut self, task: &Task<Loc>) {
+        self.first_seen(task);
+        self.announced.insert(task.id, Announced {
+            bids: Vec::new(),
+            status: TaskStatus::Bidding(0)
+        });
+    }
+
+    // Whether an announcement is new to this agent.
+    pub fn first_seen(&mut self, task: &Task<Loc>) -> bool {
+        if !self.seen.insert(task.id) {
+            return false
+        }
+        self.seen_order.push_back(task.id);
+        if self.seen_order.len() > MAX_SEEN {
+            if let Some(oldest) = self.seen_order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    // The bid of local for the task, if it bids at all.
+    pub fn bid(&mut self, local: &SwarmAgent<Loc>, task: &Task<Loc>) -> Option<u64> {
+        let distance = local.location().distance(&task.location);
+        if task.announcer.id() == local.id() ||
+            distance > task.radius ||
+            self.assigned.len() >= self.capacity ||
+            self.bid_on.contains_key(&task.id) {
+            return None
+        }
+
+        self.bid_on.insert(task.id, task.clone());
+        self.bid_order.push_back(task.id);
+        if self.bid_order.len() > MAX_BIDS {
+            if let Some(oldest) = self.bid_order.pop_front() {
+                self.bid_on.remove(&oldest);
+            }
+        }
+        Some((distance as u64).saturating_add(1).
+             saturating_mul(self.assigned.len() as u64 + 1))
+    }
+
+    // Record a bid for a task announced by this agent. Once MAX_BIDDERS have
+    // bid, a new bid takes the place of the highest, if it is lower.
+    pub fn receive_bid(&mut self, task_id: TaskId, bidder: SwarmAgent<Loc>, cost: u64) -> bool {
+        match self.announced.get_mut(&task_id) {
+            Some(announced) => {
+                match announced.status {
+                    TaskStatus::Bidding(_) => {}
+                    _ => return false
+                }
+                if announced.bids.iter().any(|(_, b)| b.id() == bidder.id()) {
+                    return false
+                }
+                if announced.bids.len() >= MAX_BIDDERS {
+                    let highest = announced.bids.iter().enumerate().
+                        max_by_key(|&(_, &(cost, ref agn))| (cost, agn.id().clone())).
+                        map(|(i, &(cost, _))| (i, cost));
+                    match highest {
+                        Some((i, highest)) if cost < highest => { announced.bids.swap_remove(i); }
+                        _ => return false
+                    }
+                }
+                announced.bids.push((cost, bidder));
+                announced.status = TaskStatus::Bidding(announced.bids.len());
+                true
+            }
+            None => false
+        }
+    }
+
+    // Close bidding on a task announced by this agent, returning the lowest
+    // bidder. Ties go to the lower id. Without any bids, bidding stays open.
+    pub fn award(&mut self, task_id: TaskId) -> Option<SwarmAgent<Loc>> {
+        let announced = self.announced.get_mut(&task_id)?;
+        match announced.status {
+            TaskStatus::Bidding(_) => {}
+            _ => return None
+        }
+
+        let winner = match announced.bids.iter().
+            min_by_key(|&&(cost, ref agn)| (cost, agn.id().clone())) {
+            Some((_, agn)) => agn.clone(),
+            None => return None
+        };
+        announced.status = TaskStatus::Awarded(winner.clone());
+        Some(winner)
+    }
+
+    // Agents that bid on a task announced by this agent, which are told who
+    // it was awarded to.
+    pub fn bidders(&self, task_id: TaskId) -> Vec<SwarmAgent<Loc>> {
+        match self.announced.get(&task_id) {
+            Some(announced) => announced.bids.iter().map(|(_, agn)| agn.clone()).collect(),
+            None => Vec::new()
+        }
+    }
+
+    // Accept an Award sent by from. Only awards from the announcer of a task
+    // this agent bid on, naming this agent, are accepted.
+    pub fn receive_award(&mut self, local: &SwarmAgent<Loc>, from: &SwarmAgent<Loc>,
+                         task_id: TaskId, winner: &SwarmAgent<Loc>) -> bool {
+        let from_announcer = match self.bid_on.get(&task_id) {
+            Some(task) => task.announcer.id() == from.id(),
+            None => false
+        };
+        if !from_announcer {
+            return false
+        }
+
+        let task = self.bid_on.remove(&task_id).unwrap();
+        self.bid_order.retain(|&id| id != task_id);
+        if winner.id() != local.id() {
+            return false
+        }
+        self.assigned.push(task.clone());
+        self.new_assignments.push(task);
+        true
+    }
+
+    pub fn take_assignments(&mut self) -> Vec<Task<Loc>> {
+        let mut assignments = Vec::new();
+        ::std::mem::swap(&mut assignments, &mut self.new_assignments);
+        assignments
+    }
+
+    // Stop working on an assigned task, returning it so that the outcome can
+    // be reported to its announcer.
+    pub fn finish(&mut self, task_id: TaskId) -> Option<Task<Loc>> {
+        match self.assigned.iter().position(|t| t.id == task_id) {
+            Some(i) => Some(self.assigned.remove(i)),
+            None => None
+        }
+    }
+
+    // Accept a Report sent by from, the agent the task was awarded to.
+    pub fn receive_report(&mut self, from: &SwarmAgent<Loc>, task_id: TaskId,
+                          outcome: TaskOutcome) -> bool {
+        match self.announced.get_mut(&task_id) {
+            Some(announced) => {
+                let winner = match announced.status {
+                    TaskStatus::Awarded(ref winner) if winner.id() == from.id() => winner.clone(),
+                    _ => return false
+                };
+                announced.status = TaskStatus::Done(winner, outcome);
+                true
+            }
+            None => false
+        }
+    }
+
+    pub fn status(&self, task_id: TaskId) -> Option<&TaskStatus<Loc>> {
+        self.announced.get(&task_id).map(|a| &a.status)
+    }
+
+    pub fn assigned(&self) -> &[Task<Loc>] {
+        self.assigned.as_slice()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+    use agent::SwarmAgent;
+    use super::{Task, TaskBoard, TaskStatus, TaskOutcome, MAX_SEEN, MAX_BIDS, MAX_BIDDERS};
+
+    fn agent(loc: isize) -> SwarmAgent<isize> {
+        SwarmAgent::new(loc, SocketAddr::from(([127, 0, 0, 1], 7000)))
+    }
+
+    #[test]
+    fn bid_test() {
+        let announcer = agent(0);
+        let task = Task::new(10, 5, announcer.clone(), b"survey".to_vec());
+        let mut board = TaskBoard::new(1);
+
+        assert_eq!(board.bid(&agent(12), &task), Some(3));
+        // Each task is bid on once.
+        assert_eq!(board.bid(&agent(12), &task), None);
+
+        let mut far = TaskBoard::new(1);
+        assert_eq!(far.bid(&agent(20), &task), None);
+        let mut busy = TaskBoard::new(0);
+        assert_eq!(busy.bid(&agent(10), &task), None);
+        let mut own = TaskBoard::new(1);
+        assert_eq!(own.bid(&announcer, &task), None);
+
+        // Costs saturate rather than overflow.
+        let task = Task::new(0, usize::MAX, announcer.clone(), Vec::new());
+        let mut loaded = TaskBoard::new(2);
+        loaded.assigned.push(task.clone());
+        assert_eq!(loaded.bid(&agent(isize::MAX), &task), Some(u64::MAX));
+    }
+
+    #[test]
+    fn max_bidders_test() {
+        let task = Task::new(10, 5, agent(0), Vec::new());
+        let mut board = TaskBoard::new(1);
+        board.announce(&task);
+        for _ in 0..MAX_BIDDERS {
+            assert!(board.receive_bid(task.id(), agent(10), 10));
+        }
+
+        // Once full, only lower bids are taken, in place of a highest one.
+        assert!(!board.receive_bid(task.id(), agent(10), 10));
+        let lowest = agent(10);
+        assert!(board.receive_bid(task.id(), lowest.clone(), 5));
+        assert_eq!(*board.status(task.id()).unwrap(), TaskStatus::Bidding(MAX_BIDDERS));
+        assert_eq!(board.award(task.id()).unwrap().id(), lowest.id());
+    }
+
+    #[test]
+    fn bounded_test() {
+        let announcer = agent(0);
+        let mut board = TaskBoard::new(1);
+        let tasks: Vec<Task<isize>> = (0..MAX_SEEN + 1).
+            map(|_| Task::new(10, 5, announcer.clone(), Vec::new())).
+            collect();
+
+        for task in tasks.iter() {
+            assert!(board.first_seen(task));
+            board.bid(&agent(10), task);
+        }
+        assert_eq!(board.seen.len(), MAX_SEEN);
+        assert_eq!(board.bid_on.len(), MAX_BIDS);
+
+        // The oldest are forgotten first.
+        assert!(board.first_seen(&tasks[0]));
+        assert!(!board.first_seen(&tasks[MAX_SEEN]));
+        assert!(!board.bid_on.contains_key(&tasks[0].id()));
+        assert!(board.bid_on.contains_key(&tasks[MAX_SEEN].id()));
+    }
+
+    #[test]
+    fn contract_test() {
+        let announcer = agent(0);
+        let near = agent(9);
+        let nearer = agent(10);
+        let task = Task::new(10, 5, announcer.clone(), Vec::new());
+
+        let mut board = TaskBoard::new(1);
+        board.announce(&task);
+        assert!(!board.first_seen(&task));
+        assert_eq!(board.award(task.id()), None);
+
+        let mut near_board = TaskBoard::new(1);
+        let mut nearer_board = TaskBoard::new(1);
+        let near_bid = near_board.bid(&near, &task).unwrap();
+        let nearer_bid = nearer_board.bid(&nearer, &task).unwrap();
+        assert!(board.receive_bid(task.id(), near.clone(), near_bid));
+        assert!(board.receive_bid(task.id(), nearer.clone(), nearer_bid));
+        assert!(!board.receive_bid(task.id(), nearer.clone(), nearer_bid));
+        assert_eq!(*board.status(task.id()).unwrap(), TaskStatus::Bidding(2));
+
+        let winner = board.award(task.id()).unwrap();
+        assert_eq!(winner.id(), nearer.id());
+        assert_eq!(board.bidders(task.id()).len(), 2);
+        assert_eq!(board.award(task.id()), None);
+
+        // Awards are only taken from the announcer.
+        assert!(!near_board.receive_award(&near, &near, task.id(), &winner));
+        assert!(!near_board.receive_award(&near, &announcer, task.id(), &winner));
+        assert!(nearer_board.receive_award(&nearer, &announcer, task.id(), &winner));
+        assert_eq!(nearer_board.take_assignments().len(), 1);
+        assert!(nearer_board.take_assignments().is_empty());
+
+        // Reports are only taken from the winner.
+        assert!(!board.receive_report(&near, task.id(), TaskOutcome::Completed));
+        let finished = nearer_board.finish(task.id()).unwrap();
+        assert_eq!(finished.id(), task.id());
+        assert!(nearer_board.assigned().is_empty());
+        assert!(board.receive_report(&nearer, task.id(), TaskOutcome::Completed));
+        assert_eq!(*board.status(task.id()).unwrap(),
+                   TaskStatus::Done(nearer.clone(), TaskOutcome::Completed));
+    }
+}
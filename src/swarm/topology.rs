@@ -0,0 +1,179 @@
+// Topology Export
+//
+// A Topology is a snapshot of the overlay network: every agent known, and an
+// edge from each agent to each of its neighbors. It is built from the agents
+// of a simulation, or from neighbor tables collected some other way, and
+// exported as Graphviz DOT or GraphML with the location of each agent as the
+// position of its node, to see how well the neighbor graph follows spatial
+// locality.
+//
+// Edges are directed, as an agent may keep another as a neighbor without
+// being its neighbor in turn. Nodes and edges are written in a fixed order, so
+// the export of the same topology is always the same.
+use agent::SwarmAgent;
+use swarm::network::SwarmNetwork;
+use std::collections::{BTreeMap, BTreeSet};
+use {Coordinates, Location};
+
+pub struct Topology<Loc> {
+    // Agents by the hex encoding of their id.
+    nodes: BTreeMap<String, SwarmAgent<Loc>>,
+    edges: BTreeSet<(String, String)>
+}
+
+impl<Loc: Clone> Default for Topology<Loc> {
+    fn default() -> Topology<Loc> {
+        Topology::new()
+    }
+}
+
+impl<Loc: Clone> Topology<Loc> {
+    pub fn new() -> Topology<Loc> {
+        Topology {
+            nodes: BTreeMap::new(),
+            edges: BTreeSet::new()
+        }
+    }
+
+    pub fn from_networks<P: Clone>(networks: &[&SwarmNetwork<Loc, P>]) -> Topology<Loc>
+    where Loc: Location {
+        let mut topology = Topology::new();
+        for network in networks.iter() {
+            topology.add_agent(network.local_agent(), network.neighbors());
+        }
+        topology
+    }
+
+    // Add an agent along with its neighbors. Neighbors that are not added
+    // themselves still show up as nodes, at the location they were last
+    // reported at.
+    pub fn add_agent(&mut self, agent: &SwarmAgent<Loc>, neighbors: &[SwarmAgent<Loc>]) {
+        let id = agent.id().to_hex();
+        self.nodes.insert(id.clone(), agent.clone());
+
+        for neighbor in neighbors.iter() {
+            let neighbor_id = neighbor.id().to_hex();
+            if !self.nodes.contains_key(&neighbor_id) {
+                self.nodes.insert(neighbor_id.clone(), neighbor.clone());
+            }
+            if neighbor_id != id {
+                self.edges.insert((id.clone(), neighbor_id));
+            }
+        }
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.edges.len()
+    }
+}
+
+impl<Loc: Coordinates> Topology<Loc> {
+    // Nodes are pinned to their coordinates, which neato and fdp honour.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph swarm {\n");
+        for (id, agent) in self.nodes.iter() {
+            let (x, y) = agent.location().coordinates();
+            out.push_str(format!("    \"{}\" [label=\"{}\", pos=\"{},{}!\"];\n",
+                                 id, agent.address().socket_addr(), x, y).as_str());
+        }
+        for (from, to) in self.edges.iter() {
+            out.push_str(format!("    \"{}\" -> \"{}\";\n", from, to).as_str());
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    pub fn to_graphml(&self) -> String {
+        let mut out = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n  \
+             <key id=\"address\" for=\"node\" attr.name=\"address\" attr.type=\"string\"/>\n  \
+             <key id=\"x\" for=\"node\" attr.name=\"x\" attr.type=\"double\"/>\n  \
+             <key id=\"y\" for=\"node\" attr.name=\"y\" attr.type=\"double\"/>\n  \
+             <graph id=\"swarm\" edgedefault=\"directed\">\n");
+        for (id, agent) in self.nodes.iter() {
+            let (x, y) = agent.location().coordinates();
+            out.push_str(format!("    <node id=\"{}\">\n", id).as_str());
+            out.push_str(format!("      <data key=\"address\">{}</data>\n",
+                                 agent.address().socket_addr()).as_str());
+            out.push_str(format!("      <data key=\"x\">{}</data>\n", x).as_str());
+            out.push_str(format!("      <data key=\"y\">{}</data>\n", y).as_str());
+            out.push_str("    </node>\n");
+        }
+        for (from, to) in self.edges.iter() {
+            out.push_str(format!("    <edge source=\"{}\" target=\"{}\"/>\n",
+                                 from, to).as_str());
+        }
+        out.push_str("  </graph>\n</graphml>\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+    use agent::SwarmAgent;
+    use Coordinates;
+    use super::Topology;
+
+    #[derive(Clone, Debug)]
+    struct Point(f64, f64);
+
+    impl Coordinates for Point {
+        fn coordinates(&self) -> (f64, f64) {
+            let Point(x, y) = *self;
+            (x, y)
+        }
+    }
+
+    fn agent(x: f64, y: f64, port: u16) -> SwarmAgent<Point> {
+        SwarmAgent::new(Point(x, y), SocketAddr::from(([127, 0, 0, 1], port)))
+    }
+
+    fn topology() -> (Topology<Point>, SwarmAgent<Point>, SwarmAgent<Point>) {
+        let a = agent(0.5, 2.0, 7000);
+        let b = agent(3.5, 1.0, 7001);
+        let c = agent(4.0, 4.0, 7002);
+
+        let mut topology = Topology::new();
+        topology.add_agent(&a, &[b.clone(), c.clone()]);
+        topology.add_agent(&b, ::std::slice::from_ref(&a));
+        (topology, a, b)
+    }
+
+    #[test]
+    fn add_agent_test() {
+        let (topology, _, _) = topology();
+        assert_eq!(topology.node_count(), 3);
+        assert_eq!(topology.edge_count(), 3);
+    }
+
+    #[test]
+    fn dot_test() {
+        let (topology, a, b) = topology();
+        let dot = topology.to_dot();
+
+        assert!(dot.starts_with("digraph swarm {"));
+        assert!(dot.contains("pos=\"0.5,2!\""));
+        assert!(dot.contains("label=\"127.0.0.1:7000\""));
+        let edge = format!("\"{}\" -> \"{}\";", a.id().to_hex(), b.id().to_hex());
+        assert!(dot.contains(edge.as_str()));
+    }
+
+    #[test]
+    fn graphml_test() {
+        let (topology, a, b) = topology();
+        let graphml = topology.to_graphml();
+
+        assert_eq!(graphml.split("<node ").count(), 4);
+        assert_eq!(graphml.split("<edge ").count(), 4);
+        assert!(graphml.contains("<data key=\"x\">3.5</data>"));
+        let edge = format!("<edge source=\"{}\" target=\"{}\"/>",
+                           b.id().to_hex(), a.id().to_hex());
+        assert!(graphml.contains(edge.as_str()));
+    }
+}
@@ -0,0 +1,172 @@
+// Tracing
+//
+// A SwarmNetwork reports what it does with the RPCs it handles to a Tracer:
+// which RPC arrived from where, whether it was accepted, and which neighbor it
+// was routed to along with the distances the decision was based on.
+//
+// Every RPC carries a trace id. An agent forwarding an RPC, or answering one,
+// keeps its trace id, so the events reported by all agents along the path of
+// an INFO share the same id and the path can be pieced together afterwards,
+// e.g. with a TraceLog shared between the agents of a simulation. The trace id
+// is not covered by the signature of the RPC; a forged trace id only confuses
+// the trace, not the swarm.
+use agent::SwarmAddr;
+use byteid::ByteId;
+use codec::{Codec, Json};
+use std::io::Write;
+use rand::{thread_rng, Rng};
+use std::sync::{Arc, Mutex};
+use std::vec::Vec;
+
+pub type TraceId = u64;
+
+pub fn new_trace_id() -> TraceId {
+    thread_rng().gen()
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize)]
+pub enum TraceDecision {
+    // The RPC was received and its signature checked out.
+    Received,
+    // The RPC was dropped, for the given reason.
+    Rejected(String),
+    // Forwarded to the neighbor closest to the target location, which is
+    // closer to it than this agent.
+    Forwarded {
+        next_hop: SwarmAddr,
+        self_distance: usize,
+        next_hop_distance: usize
+    },
+    // No neighbor is closer to the target location than this agent.
+    Delivered {
+        self_distance: usize,
+        closest_neighbor_distance: Option<usize>
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize)]
+pub struct TraceEvent {
+    pub trace_id: TraceId,
+    // Id of the agent reporting the event.
+    pub agent: ByteId,
+    pub rpc: &'static str,
+    // Address the RPC was received from, if any.
+    pub source: Option<SwarmAddr>,
+    pub decision: TraceDecision
+}
+
+pub trait Tracer {
+    fn trace(&mut self, _event: &TraceEvent) {}
+}
+
+// The default tracer, which drops every event.
+pub struct NoTrace;
+
+impl Tracer for NoTrace {}
+
+// Writes each event as a line of JSON.
+pub struct JsonLines<W> {
+    writer: W
+}
+
+impl<W: Write> JsonLines<W> {
+    pub fn new(writer: W) -> JsonLines<W> {
+        JsonLines { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Tracer for JsonLines<W> {
+    fn trace(&mut self, event: &TraceEvent) {
+        // Tracing must never disturb the agent, so write errors are ignored.
+        if let Ok(line) = Json.encode(event) {
+            let _ = self.writer.write_all(line.as_slice());
+            let _ = self.writer.write_all(b"\n");
+        }
+    }
+}
+
+// Keeps the events in memory. Clones share the same log, so one TraceLog can
+// collect the events of several agents.
+#[derive(Clone)]
+pub struct TraceLog {
+    events: Arc<Mutex<Vec<TraceEvent>>>
+}
+
+impl Default for TraceLog {
+    fn default() -> TraceLog {
+        TraceLog::new()
+    }
+}
+
+impl TraceLog {
+    pub fn new() -> TraceLog {
+        TraceLog { events: Arc::new(Mutex::new(Vec::new())) }
+    }
+
+    pub fn events(&self) -> Vec<TraceEvent> {
+        self.events.lock().unwrap().clone()
+    }
+
+    // The events of a single trace, in the order they were reported.
+    pub fn path(&self, trace_id: TraceId) -> Vec<TraceEvent> {
+        self.events.lock().unwrap().iter().
+            filter(|e| e.trace_id == trace_id).
+            cloned().
+            collect()
+    }
+}
+
+impl Tracer for TraceLog {
+    fn trace(&mut self, event: &TraceEvent) {
+        self.events.lock().unwrap().push(event.clone());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use byteid::ByteId;
+    use super::{Tracer, TraceEvent, TraceDecision, TraceLog, JsonLines};
+
+    fn event(trace_id: u64) -> TraceEvent {
+        TraceEvent {
+            trace_id,
+            agent: ByteId::random_id(),
+            rpc: "INFO",
+            source: None,
+            decision: TraceDecision::Delivered {
+                self_distance: 1,
+                closest_neighbor_distance: Some(3)
+            }
+        }
+    }
+
+    #[test]
+    fn trace_log_test() {
+        let log = TraceLog::new();
+        let mut tracer = log.clone();
+        tracer.trace(&event(1));
+        tracer.trace(&event(2));
+        tracer.trace(&event(1));
+
+        assert_eq!(log.events().len(), 3);
+        assert_eq!(log.path(1).len(), 2);
+        assert_eq!(log.path(3).len(), 0);
+    }
+
+    #[test]
+    fn json_lines_test() {
+        let mut tracer = JsonLines::new(Vec::new());
+        tracer.trace(&event(7));
+        tracer.trace(&event(8));
+
+        let text = String::from_utf8(tracer.into_inner()).ok().unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("\"trace_id\":7"));
+        assert!(lines[1].contains("\"closest_neighbor_distance\":3"));
+    }
+}